@@ -0,0 +1,432 @@
+use proc_macro2::{Span, TokenStream};
+use quote::{format_ident, quote};
+use syn::{
+    parse_quote, spanned::Spanned, Attribute, Data, DeriveInput, Error, Field, Fields, Generics,
+    Ident, Lifetime, LifetimeDef, Lit, Meta, NestedMeta, Result, Variant,
+};
+
+// Options set with the `#[lua(...)]` attribute.
+#[derive(Default)]
+struct Attrs {
+    rename: Option<String>,
+    default: bool,
+    skip: bool,
+}
+
+fn parse_attrs(attrs: &[Attribute]) -> Result<Attrs> {
+    let mut result = Attrs::default();
+    for attr in attrs.iter().filter(|attr| attr.path.is_ident("lua")) {
+        let list = match attr.parse_meta()? {
+            Meta::List(list) => list,
+            meta => return Err(Error::new(meta.span(), "expected `#[lua(...)]`")),
+        };
+        for nested in list.nested {
+            match nested {
+                NestedMeta::Meta(Meta::NameValue(nv)) if nv.path.is_ident("rename") => {
+                    match nv.lit {
+                        Lit::Str(s) => result.rename = Some(s.value()),
+                        lit => return Err(Error::new(lit.span(), "expected string literal")),
+                    }
+                }
+                NestedMeta::Meta(Meta::Path(path)) if path.is_ident("default") => {
+                    result.default = true
+                }
+                NestedMeta::Meta(Meta::Path(path)) if path.is_ident("skip") => result.skip = true,
+                nested => {
+                    return Err(Error::new(
+                        nested.span(),
+                        "unknown attribute, expected `rename = \"...\"`, `default` or `skip`",
+                    ))
+                }
+            }
+        }
+    }
+    Ok(result)
+}
+
+// A struct or variant field with parsed attributes.
+struct FieldInfo<'a> {
+    ident: Option<&'a Ident>,
+    key: String,
+    attrs: Attrs,
+}
+
+fn fields_info(fields: &Fields) -> Result<Vec<FieldInfo<'_>>> {
+    fields
+        .iter()
+        .map(|field: &Field| {
+            let attrs = parse_attrs(&field.attrs)?;
+            let key = match (&attrs.rename, &field.ident) {
+                (Some(rename), _) => rename.clone(),
+                (None, Some(ident)) => ident.to_string(),
+                (None, None) => String::new(),
+            };
+            Ok(FieldInfo {
+                ident: field.ident.as_ref(),
+                key,
+                attrs,
+            })
+        })
+        .collect()
+}
+
+// Whether the fields are a single unnamed field converted transparently (newtype).
+// Only `skip` is supported on such a field, as it has no key and is always present.
+fn is_newtype(fields: &Fields) -> Result<bool> {
+    match fields {
+        Fields::Unnamed(unnamed) if unnamed.unnamed.len() == 1 => {
+            let field = &unnamed.unnamed[0];
+            let attrs = parse_attrs(&field.attrs)?;
+            if attrs.skip {
+                return Ok(false);
+            }
+            if attrs.rename.is_some() || attrs.default {
+                let attr = field.attrs.iter().find(|attr| attr.path.is_ident("lua"));
+                return Err(Error::new_spanned(
+                    attr,
+                    "only `skip` is supported on the field of a newtype",
+                ));
+            }
+            Ok(true)
+        }
+        _ => Ok(false),
+    }
+}
+
+fn variant_name(variant: &Variant) -> Result<String> {
+    let attrs = parse_attrs(&variant.attrs)?;
+    if attrs.default || attrs.skip {
+        return Err(Error::new(
+            variant.span(),
+            "only `rename` is supported on enum variants",
+        ));
+    }
+    Ok(attrs.rename.unwrap_or_else(|| variant.ident.to_string()))
+}
+
+// No `#[lua(...)]` options are supported on the struct or enum itself.
+fn check_container_attrs(attrs: &[Attribute]) -> Result<()> {
+    match attrs.iter().find(|attr| attr.path.is_ident("lua")) {
+        Some(attr) => Err(Error::new_spanned(
+            attr,
+            "`#[lua(...)]` is only supported on fields and enum variants",
+        )),
+        None => Ok(()),
+    }
+}
+
+// Adds the `'lua` lifetime (unless already declared) and the trait bound for type parameters.
+fn lua_generics(generics: &Generics, bound: TokenStream) -> (Generics, Lifetime) {
+    let lifetime = Lifetime::new("'lua", Span::call_site());
+    let mut generics = generics.clone();
+    if generics.lifetimes().all(|def| def.lifetime != lifetime) {
+        generics
+            .params
+            .insert(0, LifetimeDef::new(lifetime.clone()).into());
+    }
+    let type_params: Vec<_> = generics.type_params().map(|p| p.ident.clone()).collect();
+    let where_clause = generics.make_where_clause();
+    for ident in type_params {
+        where_clause
+            .predicates
+            .push(parse_quote!(#ident: #bound<#lifetime>));
+    }
+    (generics, lifetime)
+}
+
+pub fn derive_to_lua(input: DeriveInput) -> Result<TokenStream> {
+    check_container_attrs(&input.attrs)?;
+
+    let name = &input.ident;
+    let (generics, lifetime) = lua_generics(&input.generics, quote!(mlua::ToLua));
+    let (impl_generics, _, where_clause) = generics.split_for_impl();
+    let (_, ty_generics, _) = input.generics.split_for_impl();
+
+    let body = match &input.data {
+        Data::Struct(data) if is_newtype(&data.fields)? => quote! {
+            mlua::ToLua::to_lua(self.0, lua)
+        },
+        Data::Struct(data) => {
+            let infos = fields_info(&data.fields)?;
+            let bindings = field_bindings(&infos);
+            let set_fields = to_lua_fields(&infos, &bindings);
+            let pattern = fields_pattern(quote!(#name), &data.fields, &bindings);
+            quote! {
+                let #pattern = self;
+                let table = lua.create_table()?;
+                #set_fields
+                Ok(mlua::Value::Table(table))
+            }
+        }
+        Data::Enum(data) => {
+            let arms = data
+                .variants
+                .iter()
+                .map(|variant| {
+                    let ident = &variant.ident;
+                    let key = variant_name(variant)?;
+                    let infos = fields_info(&variant.fields)?;
+                    let bindings = field_bindings(&infos);
+                    let pattern = fields_pattern(quote!(#name::#ident), &variant.fields, &bindings);
+                    let newtype = is_newtype(&variant.fields)?;
+                    Ok(match &variant.fields {
+                        Fields::Unit => quote! {
+                            #pattern => mlua::ToLua::to_lua(#key, lua),
+                        },
+                        Fields::Unnamed(_) if newtype => {
+                            let binding = &bindings[0];
+                            quote! {
+                                #pattern => {
+                                    let variant = lua.create_table()?;
+                                    variant.raw_set(#key, #binding)?;
+                                    Ok(mlua::Value::Table(variant))
+                                }
+                            }
+                        }
+                        _ => {
+                            let set_fields = to_lua_fields(&infos, &bindings);
+                            quote! {
+                                #pattern => {
+                                    let table = lua.create_table()?;
+                                    #set_fields
+                                    let variant = lua.create_table()?;
+                                    variant.raw_set(#key, table)?;
+                                    Ok(mlua::Value::Table(variant))
+                                }
+                            }
+                        }
+                    })
+                })
+                .collect::<Result<Vec<_>>>()?;
+            quote! {
+                match self {
+                    #(#arms)*
+                }
+            }
+        }
+        Data::Union(_) => {
+            return Err(Error::new(
+                input.span(),
+                "`ToLua` cannot be derived for unions",
+            ))
+        }
+    };
+
+    Ok(quote! {
+        impl #impl_generics mlua::ToLua<#lifetime> for #name #ty_generics #where_clause {
+            fn to_lua(self, lua: &#lifetime mlua::Lua) -> mlua::Result<mlua::Value<#lifetime>> {
+                #body
+            }
+        }
+    })
+}
+
+pub fn derive_from_lua(input: DeriveInput) -> Result<TokenStream> {
+    check_container_attrs(&input.attrs)?;
+
+    let name = &input.ident;
+    let type_name = name.to_string();
+    let (generics, lifetime) = lua_generics(&input.generics, quote!(mlua::FromLua));
+    let (impl_generics, _, where_clause) = generics.split_for_impl();
+    let (_, ty_generics, _) = input.generics.split_for_impl();
+
+    let body = match &input.data {
+        Data::Struct(data) if is_newtype(&data.fields)? => quote! {
+            Ok(#name(mlua::FromLua::from_lua(value, lua)?))
+        },
+        Data::Struct(data) => {
+            let construct = from_lua_fields(quote!(#name), &data.fields, &type_name)?;
+            quote! {
+                let table = match value {
+                    mlua::Value::Table(table) => table,
+                    value => return Err(mlua::Error::FromLuaConversionError {
+                        from: value.type_name(),
+                        to: #type_name,
+                        message: Some("expected table".to_string()),
+                    }),
+                };
+                Ok(#construct)
+            }
+        }
+        Data::Enum(data) => {
+            let mut unit_arms = Vec::new();
+            let mut data_arms = Vec::new();
+            for variant in &data.variants {
+                let ident = &variant.ident;
+                let key = variant_name(variant)?;
+                let newtype = is_newtype(&variant.fields)?;
+                match &variant.fields {
+                    Fields::Unit => unit_arms.push(quote! {
+                        #key => Ok(#name::#ident),
+                    }),
+                    Fields::Unnamed(_) if newtype => {
+                        let convert = convert_field(quote!(value), &key, &type_name);
+                        data_arms.push(quote! {
+                            #key => Ok(#name::#ident(#convert)),
+                        });
+                    }
+                    fields => {
+                        let construct = from_lua_fields(quote!(#name::#ident), fields, &type_name)?;
+                        data_arms.push(quote! {
+                            #key => {
+                                let table = match value {
+                                    mlua::Value::Table(table) => table,
+                                    value => return Err(mlua::Error::FromLuaConversionError {
+                                        from: value.type_name(),
+                                        to: #type_name,
+                                        message: Some(format!("variant `{}`: expected table", #key)),
+                                    }),
+                                };
+                                Ok(#construct)
+                            }
+                        });
+                    }
+                }
+            }
+
+            quote! {
+                let unknown_variant = |variant: &str| mlua::Error::FromLuaConversionError {
+                    from: "string",
+                    to: #type_name,
+                    message: Some(format!("unknown variant `{}`", variant)),
+                };
+                match value {
+                    mlua::Value::String(variant) => match variant.to_str()? {
+                        #(#unit_arms)*
+                        variant => Err(unknown_variant(variant)),
+                    },
+                    mlua::Value::Table(table) => {
+                        let mut pairs = table.pairs::<mlua::String, mlua::Value>();
+                        let (variant, value) = match (pairs.next(), pairs.next()) {
+                            (Some(pair), None) => pair?,
+                            _ => return Err(mlua::Error::FromLuaConversionError {
+                                from: "table",
+                                to: #type_name,
+                                message: Some("expected table with a single key".to_string()),
+                            }),
+                        };
+                        match variant.to_str()? {
+                            #(#data_arms)*
+                            variant => Err(unknown_variant(variant)),
+                        }
+                    }
+                    value => Err(mlua::Error::FromLuaConversionError {
+                        from: value.type_name(),
+                        to: #type_name,
+                        message: Some("expected string or table".to_string()),
+                    }),
+                }
+            }
+        }
+        Data::Union(_) => {
+            return Err(Error::new(
+                input.span(),
+                "`FromLua` cannot be derived for unions",
+            ))
+        }
+    };
+
+    Ok(quote! {
+        impl #impl_generics mlua::FromLua<#lifetime> for #name #ty_generics #where_clause {
+            #[allow(unused_variables)]
+            fn from_lua(value: mlua::Value<#lifetime>, lua: &#lifetime mlua::Lua) -> mlua::Result<Self> {
+                #body
+            }
+        }
+    })
+}
+
+fn field_bindings(infos: &[FieldInfo]) -> Vec<Ident> {
+    (0..infos.len())
+        .map(|i| format_ident!("__field{}", i))
+        .collect()
+}
+
+// Pattern destructuring `self` (or an enum variant) into the field bindings.
+fn fields_pattern(path: TokenStream, fields: &Fields, bindings: &[Ident]) -> TokenStream {
+    match fields {
+        Fields::Named(fields) => {
+            let idents = fields.named.iter().map(|f| &f.ident);
+            quote!(#path { #(#idents: #bindings),* })
+        }
+        Fields::Unnamed(_) => quote!(#path(#(#bindings),*)),
+        Fields::Unit => quote!(#path),
+    }
+}
+
+// Stores the fields in `table`, by name for named fields and by position otherwise.
+fn to_lua_fields(infos: &[FieldInfo], bindings: &[Ident]) -> TokenStream {
+    let mut index = 0usize;
+    let sets = infos.iter().zip(bindings).filter_map(|(info, binding)| {
+        if info.attrs.skip {
+            return None;
+        }
+        index += 1;
+        Some(if info.ident.is_some() || info.attrs.rename.is_some() {
+            let key = &info.key;
+            quote!(table.raw_set(#key, #binding)?;)
+        } else {
+            quote!(table.raw_set(#index, #binding)?;)
+        })
+    });
+    quote!(#(#sets)*)
+}
+
+// Converts `value` taken from a field, adding the field name to the error message.
+fn convert_field(value: TokenStream, key: &str, type_name: &str) -> TokenStream {
+    quote! {{
+        let value = #value;
+        let from = value.type_name();
+        mlua::FromLua::from_lua(value, lua).map_err(|err| mlua::Error::FromLuaConversionError {
+            from,
+            to: #type_name,
+            message: Some(format!("field `{}`: {}", #key, err)),
+        })?
+    }}
+}
+
+// Builds the struct (or enum variant) from the fields stored in `table`.
+fn from_lua_fields(path: TokenStream, fields: &Fields, type_name: &str) -> Result<TokenStream> {
+    let infos = fields_info(fields)?;
+    let mut index = 0usize;
+    let values = infos
+        .iter()
+        .map(|info| {
+            if info.attrs.skip {
+                return quote!(::std::default::Default::default());
+            }
+            index += 1;
+            let key = if info.ident.is_some() || info.attrs.rename.is_some() {
+                let key = &info.key;
+                quote!(#key)
+            } else {
+                quote!(#index)
+            };
+            let key_name = match info.ident {
+                Some(_) => info.key.clone(),
+                None => index.to_string(),
+            };
+            let convert = convert_field(quote!(value), &key_name, type_name);
+            if info.attrs.default {
+                quote! {
+                    match table.raw_get::<_, mlua::Value>(#key)? {
+                        mlua::Value::Nil => ::std::default::Default::default(),
+                        value => #convert,
+                    }
+                }
+            } else {
+                let value = quote!(table.raw_get::<_, mlua::Value>(#key)?);
+                convert_field(value, &key_name, type_name)
+            }
+        })
+        .collect::<Vec<_>>();
+
+    Ok(match fields {
+        Fields::Named(fields) => {
+            let idents = fields.named.iter().map(|f| &f.ident);
+            quote!(#path { #(#idents: #values),* })
+        }
+        Fields::Unnamed(_) => quote!(#path(#(#values),*)),
+        Fields::Unit => quote!(#path),
+    })
+}
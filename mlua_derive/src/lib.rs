@@ -3,7 +3,12 @@ extern crate proc_macro;
 use proc_macro::TokenStream;
 use proc_macro2::{Ident, Span};
 use quote::quote_spanned;
-use syn::{parse_macro_input, spanned::Spanned, AttributeArgs, Error, ItemFn};
+use syn::{
+    parse_macro_input, spanned::Spanned, AttributeArgs, DeriveInput, Error, ItemFn, ItemImpl,
+};
+
+mod conversion;
+mod userdata;
 
 #[proc_macro_attribute]
 pub fn lua_module(attr: TokenStream, item: TokenStream) -> TokenStream {
@@ -35,3 +40,148 @@ pub fn lua_module(attr: TokenStream, item: TokenStream) -> TokenStream {
 
     wrapped.into()
 }
+
+/// Implements `mlua::UserData` for a type using annotated methods of an inherent `impl` block.
+///
+/// Methods are registered in `UserData::add_methods` according to their attribute and receiver:
+///
+/// - `#[method]` on `&self` uses `add_method`, on `&mut self` uses `add_method_mut`,
+///   and on `async fn` uses `add_async_method`. Async methods work on a clone of the userdata,
+///   so they cannot take `&mut self`.
+/// - `#[function]` on an associated function (without `self`) uses `add_function`,
+///   or `add_async_function` for `async fn`.
+/// - `#[meta(Name)]` uses `add_meta_method`, `add_meta_method_mut` or `add_meta_function`
+///   with the `MetaMethod::Name` variant.
+///
+/// The Lua name defaults to the Rust name and can be changed with `#[method(name = "...")]`
+/// or `#[function(name = "...")]`.
+///
+/// The first argument after the receiver may be `&Lua` to get access to the Lua state.
+/// Other arguments must implement `FromLua` and are collected from the Lua call.
+/// The return value is passed to Lua with `ToLuaMulti`, so a returned `Result` becomes `nil`
+/// followed by the error value in case of error. With the `result` flag, for example
+/// `#[method(result)]`, `#[function(name = "...", result)]` or `#[meta(Name, result)]`, the
+/// method must return a `Result` whose error converts into `mlua::Error`, and the error is raised
+/// in Lua instead.
+///
+/// Methods without an attribute are left untouched and are not visible from Lua.
+///
+/// The attribute generates the whole `UserData` implementation, so it can be used on a single
+/// `impl` block per type, and the type cannot register fields or additional methods manually.
+///
+/// # Example
+///
+/// ```ignore
+/// #[derive(Clone)]
+/// struct Vec2(f64, f64);
+///
+/// #[mlua::userdata_impl]
+/// impl Vec2 {
+///     #[function]
+///     fn new(x: f64, y: f64) -> Self {
+///         Vec2(x, y)
+///     }
+///
+///     #[method]
+///     fn magnitude(&self) -> f64 {
+///         (self.0 * self.0 + self.1 * self.1).sqrt()
+///     }
+///
+///     #[method(name = "scale")]
+///     fn scale_by(&mut self, factor: f64) {
+///         self.0 *= factor;
+///         self.1 *= factor;
+///     }
+///
+///     #[meta(Add)]
+///     fn add(&self, other: Vec2) -> Self {
+///         Vec2(self.0 + other.0, self.1 + other.1)
+///     }
+/// }
+/// ```
+#[proc_macro_attribute]
+pub fn userdata_impl(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let args = parse_macro_input!(attr as AttributeArgs);
+    let item = parse_macro_input!(item as ItemImpl);
+
+    if !args.is_empty() {
+        let err = Error::new(Span::call_site(), "the number of arguments must be zero")
+            .to_compile_error();
+        return err.into();
+    }
+
+    match userdata::userdata_impl(item) {
+        Ok(expanded) => expanded.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+/// Derives `mlua::ToLua` for a struct or enum.
+///
+/// Structs with named fields are converted to a table keyed by field name, tuple structs to a
+/// sequence table, single-field tuple structs to the value of the field and unit structs to an
+/// empty table. Fields are stored and read with raw access, metamethods are not invoked.
+///
+/// Unit enum variants are converted to a string with the variant name. Other variants are
+/// converted to a table with a single key, the variant name, set to the variant data
+/// (a table of fields or the value itself for single-field variants).
+///
+/// # Attributes
+///
+/// - `#[lua(rename = "name")]` on a field or variant uses `name` as the Lua key or variant name.
+/// - `#[lua(skip)]` on a field excludes it from conversion. A single-field tuple struct or variant
+///   with a skipped field is converted as if it had no fields.
+///
+/// Only `skip` is accepted on the field of a single-field tuple struct or variant.
+///
+/// # Example
+///
+/// ```ignore
+/// #[derive(mlua::ToLua, mlua::FromLua)]
+/// struct Server {
+///     host: String,
+///     #[lua(default)]
+///     port: u16,
+///     #[lua(rename = "tls")]
+///     use_tls: bool,
+///     #[lua(skip)]
+///     connections: usize,
+/// }
+///
+/// #[derive(mlua::ToLua, mlua::FromLua)]
+/// enum Shape {
+///     Empty,
+///     Circle(f64),
+///     Rect { w: f64, h: f64 },
+/// }
+/// ```
+#[proc_macro_derive(ToLua, attributes(lua))]
+pub fn to_lua(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match conversion::derive_to_lua(input) {
+        Ok(expanded) => expanded.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+/// Derives `mlua::FromLua` for a struct or enum.
+///
+/// Uses the same representation as [`ToLua`](derive.ToLua.html). Conversion errors are reported as
+/// `Error::FromLuaConversionError` and include the name of the field that failed.
+///
+/// # Attributes
+///
+/// - `#[lua(rename = "name")]` on a field or variant uses `name` as the Lua key or variant name.
+/// - `#[lua(default)]` on a field uses `Default::default()` when the value is `nil`.
+/// - `#[lua(skip)]` on a field excludes it from conversion, the field is set to
+///   `Default::default()`.
+///
+/// Only `skip` is accepted on the field of a single-field tuple struct or variant.
+#[proc_macro_derive(FromLua, attributes(lua))]
+pub fn from_lua(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match conversion::derive_from_lua(input) {
+        Ok(expanded) => expanded.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
@@ -0,0 +1,288 @@
+use proc_macro2::{Ident, TokenStream};
+use quote::{format_ident, quote, quote_spanned};
+use syn::{
+    spanned::Spanned, Attribute, Error, FnArg, ImplItem, ImplItemMethod, ItemImpl, Lit, Meta,
+    NestedMeta, Result, Type,
+};
+
+// How an annotated method is registered in `UserDataMethods`.
+enum Registration {
+    Method(String),
+    Function(String),
+    Meta(Ident),
+}
+
+// Registration of a method with its options.
+struct Attrs {
+    registration: Registration,
+    // The method returns a `Result` whose error is raised in Lua (`result` flag)
+    raises: bool,
+}
+
+pub fn userdata_impl(mut item: ItemImpl) -> Result<TokenStream> {
+    if let Some((_, path, _)) = &item.trait_ {
+        return Err(Error::new(
+            path.span(),
+            "`userdata_impl` must be used on an inherent impl block",
+        ));
+    }
+
+    let mut registrations = Vec::new();
+    for impl_item in &mut item.items {
+        if let ImplItem::Method(method) = impl_item {
+            if let Some(attrs) = take_registration(&mut method.attrs)? {
+                // Registration must follow the conditional compilation of the method
+                let cfg_attrs = method.attrs.iter().filter(|attr| attr.path.is_ident("cfg"));
+                let register = register_method(method, attrs)?;
+                registrations.push(quote!(#(#cfg_attrs)* #register));
+            }
+        }
+    }
+
+    let self_ty = &item.self_ty;
+    let (impl_generics, _, where_clause) = item.generics.split_for_impl();
+
+    Ok(quote! {
+        #item
+
+        impl #impl_generics mlua::UserData for #self_ty #where_clause {
+            fn add_methods<'lua, M: mlua::UserDataMethods<'lua, Self>>(methods: &mut M) {
+                #(#registrations)*
+            }
+        }
+    })
+}
+
+// Finds and removes the `#[method]`, `#[function]` or `#[meta]` attribute from the method.
+fn take_registration(attrs: &mut Vec<Attribute>) -> Result<Option<Attrs>> {
+    let mut registration = None;
+    let mut error = None;
+
+    attrs.retain(|attr| {
+        let kind = match attr.path.get_ident() {
+            Some(ident) if ident == "method" || ident == "function" || ident == "meta" => {
+                ident.to_string()
+            }
+            _ => return true,
+        };
+        if registration.is_some() {
+            error = Some(Error::new(
+                attr.span(),
+                "only one of `method`, `function` or `meta` attributes is allowed",
+            ));
+            return false;
+        }
+        match parse_registration(&kind, attr) {
+            Ok(r) => registration = Some(r),
+            Err(err) => error = Some(err),
+        }
+        false
+    });
+
+    match error {
+        Some(err) => Err(err),
+        None => Ok(registration),
+    }
+}
+
+fn parse_registration(kind: &str, attr: &Attribute) -> Result<Attrs> {
+    let meta = attr.parse_meta()?;
+
+    let nested = match &meta {
+        Meta::Path(_) => Vec::new(),
+        Meta::List(list) => list.nested.iter().collect(),
+        Meta::NameValue(nv) => {
+            return Err(Error::new(nv.span(), "expected arguments in parentheses"));
+        }
+    };
+
+    let mut name = String::new();
+    let mut meta_name = None;
+    let mut raises = false;
+    for (i, nested) in nested.into_iter().enumerate() {
+        match nested {
+            NestedMeta::Meta(Meta::Path(path)) if path.is_ident("result") => raises = true,
+            NestedMeta::Meta(Meta::Path(path)) if kind == "meta" && i == 0 => {
+                match path.get_ident() {
+                    Some(ident) => meta_name = Some(ident.clone()),
+                    None => return Err(Error::new(path.span(), "expected a `MetaMethod` variant")),
+                }
+            }
+            NestedMeta::Meta(Meta::NameValue(nv)) if kind != "meta" && nv.path.is_ident("name") => {
+                match &nv.lit {
+                    Lit::Str(s) => name = s.value(),
+                    lit => return Err(Error::new(lit.span(), "expected string literal")),
+                }
+            }
+            nested if kind == "meta" => {
+                return Err(Error::new(
+                    nested.span(),
+                    "expected `#[meta(Name)]` or `#[meta(Name, result)]`",
+                ))
+            }
+            nested => {
+                return Err(Error::new(
+                    nested.span(),
+                    "expected `name = \"...\"` or `result`",
+                ))
+            }
+        }
+    }
+
+    let registration = match kind {
+        "method" => Registration::Method(name),
+        "function" => Registration::Function(name),
+        _ => match meta_name {
+            Some(ident) => Registration::Meta(ident),
+            None => {
+                return Err(Error::new(
+                    meta.span(),
+                    "expected `#[meta(Name)]` with a `MetaMethod` variant",
+                ))
+            }
+        },
+    };
+
+    Ok(Attrs {
+        registration,
+        raises,
+    })
+}
+
+fn register_method(method: &ImplItemMethod, attrs: Attrs) -> Result<TokenStream> {
+    let Attrs {
+        registration,
+        raises,
+    } = attrs;
+    let sig = &method.sig;
+    let span = method.span();
+    let fn_name = &sig.ident;
+    let is_async = sig.asyncness.is_some();
+
+    let mut inputs = sig.inputs.iter().peekable();
+
+    // Receiver: `None` for associated functions, `Some(true)` for `&mut self`
+    let receiver = match inputs.peek() {
+        Some(FnArg::Receiver(receiver)) => {
+            if receiver.reference.is_none() && !is_async {
+                return Err(Error::new(
+                    receiver.span(),
+                    "only `&self` and `&mut self` receivers are supported for non-async methods",
+                ));
+            }
+            let is_mut = receiver.reference.is_some() && receiver.mutability.is_some();
+            inputs.next();
+            Some(is_mut)
+        }
+        _ => None,
+    };
+
+    // An optional leading `&Lua` argument receives the Lua state
+    let pass_lua = match inputs.peek() {
+        Some(FnArg::Typed(arg)) if is_lua_ref(&arg.ty) => {
+            inputs.next();
+            true
+        }
+        _ => false,
+    };
+
+    let mut arg_names = Vec::new();
+    let mut arg_types = Vec::new();
+    for (i, input) in inputs.enumerate() {
+        if let FnArg::Typed(arg) = input {
+            arg_names.push(format_ident!("__arg{}", i));
+            arg_types.push(&arg.ty);
+        }
+    }
+
+    let lua_arg = if pass_lua { quote!(__lua,) } else { quote!() };
+    let call = match receiver {
+        Some(_) => quote_spanned!(span=> __this.#fn_name(#lua_arg #(#arg_names),*)),
+        None => quote_spanned!(span=> Self::#fn_name(#lua_arg #(#arg_names),*)),
+    };
+    let call = if is_async { quote!(#call.await) } else { call };
+    let body = if raises {
+        quote!(Ok(#call?))
+    } else {
+        quote!(Ok(#call))
+    };
+    let args = quote!((#(#arg_names,)*): (#(#arg_types,)*));
+
+    let name = match &registration {
+        Registration::Method(name) | Registration::Function(name) if name.is_empty() => {
+            fn_name.to_string()
+        }
+        Registration::Method(name) | Registration::Function(name) => name.clone(),
+        Registration::Meta(_) => String::new(),
+    };
+
+    Ok(match (registration, receiver, is_async) {
+        (Registration::Meta(_), _, true) => {
+            return Err(Error::new(
+                sig.asyncness.span(),
+                "async metamethods are not supported",
+            ))
+        }
+        (Registration::Function(_), Some(_), _) => {
+            return Err(Error::new(
+                span,
+                "`#[function]` must be used on an associated function without `self`",
+            ))
+        }
+        (Registration::Method(_), None, _) => {
+            return Err(Error::new(
+                span,
+                "`#[method]` requires a `self` receiver, use `#[function]` instead",
+            ))
+        }
+        (Registration::Method(_), Some(false), false) => quote_spanned! {span=>
+            methods.add_method(#name, |__lua, __this, #args| #body);
+        },
+        (Registration::Method(_), Some(true), false) => quote_spanned! {span=>
+            methods.add_method_mut(#name, |__lua, __this, #args| #body);
+        },
+        (Registration::Method(_), Some(true), true) => {
+            // Async methods receive a clone of the userdata, mutations would be silently lost
+            return Err(Error::new(
+                sig.asyncness.span(),
+                "async methods cannot take `&mut self`",
+            ));
+        }
+        (Registration::Method(_), Some(false), true) => quote_spanned! {span=>
+            methods.add_async_method(#name, |__lua, __this, #args| async move { #body });
+        },
+        (Registration::Function(_), None, false) => quote_spanned! {span=>
+            methods.add_function(#name, |__lua, #args| #body);
+        },
+        (Registration::Function(_), None, true) => quote_spanned! {span=>
+            methods.add_async_function(#name, |__lua, #args| async move { #body });
+        },
+        (Registration::Meta(meta), Some(false), false) => quote_spanned! {span=>
+            methods.add_meta_method(mlua::MetaMethod::#meta, |__lua, __this, #args| #body);
+        },
+        (Registration::Meta(meta), Some(true), false) => quote_spanned! {span=>
+            methods.add_meta_method_mut(mlua::MetaMethod::#meta, |__lua, __this, #args| #body);
+        },
+        (Registration::Meta(meta), None, false) => quote_spanned! {span=>
+            methods.add_meta_function(mlua::MetaMethod::#meta, |__lua, #args| #body);
+        },
+    })
+}
+
+fn is_lua_ref(ty: &Type) -> bool {
+    match ty {
+        Type::Reference(r) => is_path_to(&r.elem, "Lua"),
+        _ => false,
+    }
+}
+
+// Checks that the type is a path ending with `name`, ignoring generic arguments.
+fn is_path_to(ty: &Type, name: &str) -> bool {
+    match ty {
+        Type::Path(p) => match p.path.segments.last() {
+            Some(segment) => segment.ident == name,
+            None => false,
+        },
+        _ => false,
+    }
+}
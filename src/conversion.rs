@@ -16,6 +16,9 @@ use crate::types::{LightUserData, MaybeSend, Number};
 use crate::userdata::{AnyUserData, UserData};
 use crate::value::{FromLua, Nil, ToLua, Value};
 
+#[cfg(not(feature = "send"))]
+use crate::{function::OwnedFunction, table::OwnedTable, userdata::OwnedAnyUserData};
+
 impl<'lua> ToLua<'lua> for Value<'lua> {
     fn to_lua(self, _: &'lua Lua) -> Result<Value<'lua>> {
         Ok(self)
@@ -65,6 +68,27 @@ impl<'lua> FromLua<'lua> for Table<'lua> {
     }
 }
 
+#[cfg(not(feature = "send"))]
+impl<'lua> ToLua<'lua> for OwnedTable {
+    fn to_lua(self, lua: &'lua Lua) -> Result<Value<'lua>> {
+        Ok(Value::Table(Table(self.0.to_lua_ref(lua))))
+    }
+}
+
+#[cfg(not(feature = "send"))]
+impl<'lua> ToLua<'lua> for &OwnedTable {
+    fn to_lua(self, lua: &'lua Lua) -> Result<Value<'lua>> {
+        Ok(Value::Table(Table(self.0.to_lua_ref(lua))))
+    }
+}
+
+#[cfg(not(feature = "send"))]
+impl<'lua> FromLua<'lua> for OwnedTable {
+    fn from_lua(value: Value<'lua>, lua: &'lua Lua) -> Result<OwnedTable> {
+        Table::from_lua(value, lua).and_then(Table::into_owned)
+    }
+}
+
 impl<'lua> ToLua<'lua> for Function<'lua> {
     fn to_lua(self, _: &'lua Lua) -> Result<Value<'lua>> {
         Ok(Value::Function(self))
@@ -84,6 +108,27 @@ impl<'lua> FromLua<'lua> for Function<'lua> {
     }
 }
 
+#[cfg(not(feature = "send"))]
+impl<'lua> ToLua<'lua> for OwnedFunction {
+    fn to_lua(self, lua: &'lua Lua) -> Result<Value<'lua>> {
+        Ok(Value::Function(Function(self.0.to_lua_ref(lua))))
+    }
+}
+
+#[cfg(not(feature = "send"))]
+impl<'lua> ToLua<'lua> for &OwnedFunction {
+    fn to_lua(self, lua: &'lua Lua) -> Result<Value<'lua>> {
+        Ok(Value::Function(Function(self.0.to_lua_ref(lua))))
+    }
+}
+
+#[cfg(not(feature = "send"))]
+impl<'lua> FromLua<'lua> for OwnedFunction {
+    fn from_lua(value: Value<'lua>, lua: &'lua Lua) -> Result<OwnedFunction> {
+        Function::from_lua(value, lua).and_then(Function::into_owned)
+    }
+}
+
 impl<'lua> ToLua<'lua> for Thread<'lua> {
     fn to_lua(self, _: &'lua Lua) -> Result<Value<'lua>> {
         Ok(Value::Thread(self))
@@ -122,6 +167,27 @@ impl<'lua> FromLua<'lua> for AnyUserData<'lua> {
     }
 }
 
+#[cfg(not(feature = "send"))]
+impl<'lua> ToLua<'lua> for OwnedAnyUserData {
+    fn to_lua(self, lua: &'lua Lua) -> Result<Value<'lua>> {
+        Ok(Value::UserData(AnyUserData(self.0.to_lua_ref(lua))))
+    }
+}
+
+#[cfg(not(feature = "send"))]
+impl<'lua> ToLua<'lua> for &OwnedAnyUserData {
+    fn to_lua(self, lua: &'lua Lua) -> Result<Value<'lua>> {
+        Ok(Value::UserData(AnyUserData(self.0.to_lua_ref(lua))))
+    }
+}
+
+#[cfg(not(feature = "send"))]
+impl<'lua> FromLua<'lua> for OwnedAnyUserData {
+    fn from_lua(value: Value<'lua>, lua: &'lua Lua) -> Result<OwnedAnyUserData> {
+        AnyUserData::from_lua(value, lua).and_then(AnyUserData::into_owned)
+    }
+}
+
 impl<'lua, T: 'static + MaybeSend + UserData> ToLua<'lua> for T {
     fn to_lua(self, lua: &'lua Lua) -> Result<Value<'lua>> {
         Ok(Value::UserData(lua.create_userdata(self)?))
@@ -7,6 +7,9 @@ use std::str::Utf8Error;
 use std::string::String as StdString;
 use std::sync::Arc;
 
+use crate::hook::Traceback;
+use crate::value::Value;
+
 /// Error type returned by `mlua` methods.
 #[derive(Debug, Clone)]
 pub enum Error {
@@ -43,11 +46,20 @@ pub enum Error {
     /// This error can only happen when Lua state was not created by us and does not have the
     /// custom allocator attached.
     MemoryLimitNotAvailable,
+    /// Collecting memory statistics is not available.
+    ///
+    /// This error happens when Lua state was not created by us and does not have the custom
+    /// allocator attached, or in LuaJIT which uses its own allocator.
+    MemoryStatsNotAvailable,
     /// Main thread is not available.
     ///
     /// This error can only happen in Lua5.1/LuaJIT module mode, when module loaded within a coroutine.
     /// These Lua versions does not have `LUA_RIDX_MAINTHREAD` registry key.
     MainThreadNotAvailable,
+    /// The execution deadline set with [`Lua::set_execution_deadline`] has been exceeded.
+    ///
+    /// [`Lua::set_execution_deadline`]: struct.Lua.html#method.set_execution_deadline
+    DeadlineExceeded,
     /// A mutable callback has triggered Lua code that has called the same mutable callback again.
     ///
     /// This is an error because a mutable callback can only be borrowed mutably once.
@@ -85,6 +97,28 @@ pub enum Error {
         /// A string containing more detailed error information.
         message: Option<StdString>,
     },
+    /// A Lua value could not be copied to another Lua state by [`Lua::copy_value`].
+    ///
+    /// [`Lua::copy_value`]: struct.Lua.html#method.copy_value
+    CopyError {
+        /// Name of the Lua type that could not be copied.
+        from: &'static str,
+        /// A message indicating why the value could not be copied.
+        message: Option<StdString>,
+    },
+    /// An argument passed to a Rust callback could not be converted to the expected type.
+    ///
+    /// This is the equivalent of the "bad argument" errors raised by the Lua standard library.
+    BadArgument {
+        /// Position of the argument, starting from 1.
+        ///
+        /// As in Lua, the receiver of a method call is not counted.
+        pos: usize,
+        /// Name of the called function, if it can be determined.
+        name: Option<StdString>,
+        /// Underlying conversion error.
+        cause: Arc<Error>,
+    },
     /// [`Thread::resume`] was called on an inactive coroutine.
     ///
     /// A coroutine is inactive if its main function has returned or if an error has occured inside
@@ -132,6 +166,17 @@ pub enum Error {
         /// Original error returned by the Rust code.
         cause: Arc<Error>,
     },
+    /// An error with additional context, added with [`ErrorContext::context`] or
+    /// [`ErrorContext::with_context`].
+    ///
+    /// [`ErrorContext::context`]: trait.ErrorContext.html#tymethod.context
+    /// [`ErrorContext::with_context`]: trait.ErrorContext.html#tymethod.with_context
+    WithContext {
+        /// A message describing the context in which the error happened.
+        context: StdString,
+        /// Underlying error.
+        cause: Arc<Error>,
+    },
     /// A custom error.
     ///
     /// This can be used for returning user-defined errors from callbacks.
@@ -145,6 +190,24 @@ pub enum Error {
     ExternalError(Arc<dyn StdError>),
 }
 
+/// Details of a runtime error raised by Lua code, returned by [`Function::try_call`].
+///
+/// [`Function::try_call`]: struct.Function.html#method.try_call
+#[derive(Debug, Clone)]
+pub struct ScriptError<'lua> {
+    /// The error message, or the result of `tostring` for non-string error values.
+    pub message: StdString,
+    /// "Printable" name of the chunk where the error was raised, for example
+    /// `[string "chunk"]`, or `None` if the error was not raised in Lua code.
+    pub chunk: Option<StdString>,
+    /// The line where the error was raised, if known.
+    pub line: Option<i32>,
+    /// The Lua call stack at the moment the error was raised.
+    pub traceback: Traceback,
+    /// The original error value, for example a table passed to `error`.
+    pub value: Value<'lua>,
+}
+
 /// A specialized `Result` type used by `mlua`'s API.
 pub type Result<T> = StdResult<T, Error>;
 
@@ -166,9 +229,13 @@ impl fmt::Display for Error {
             Error::MemoryLimitNotAvailable => {
                 write!(fmt, "setting memory limit is not available")
             }
+            Error::MemoryStatsNotAvailable => {
+                write!(fmt, "collecting memory statistics is not available")
+            }
             Error::MainThreadNotAvailable => {
                 write!(fmt, "main thread is not available in Lua 5.1")
             }
+            Error::DeadlineExceeded => write!(fmt, "execution deadline exceeded"),
             Error::RecursiveMutCallback => write!(fmt, "mutable callback called recursively"),
             Error::CallbackDestructed => write!(
                 fmt,
@@ -204,6 +271,21 @@ impl fmt::Display for Error {
                     Some(ref message) => write!(fmt, " ({})", message),
                 }
             }
+            Error::CopyError { from, ref message } => {
+                write!(fmt, "error copying Lua {}", from)?;
+                match *message {
+                    None => Ok(()),
+                    Some(ref message) => write!(fmt, " ({})", message),
+                }
+            }
+            Error::BadArgument {
+                pos,
+                ref name,
+                ref cause,
+            } => match *name {
+                Some(ref name) => write!(fmt, "bad argument #{} to '{}' ({})", pos, name, cause),
+                None => write!(fmt, "bad argument #{} ({})", pos, cause),
+            },
             Error::CoroutineInactive => write!(fmt, "cannot resume inactive coroutine"),
             Error::UserDataTypeMismatch => write!(fmt, "userdata is not expected type"),
             Error::UserDataBorrowError => write!(fmt, "userdata already mutably borrowed"),
@@ -214,15 +296,28 @@ impl fmt::Display for Error {
             Error::CallbackError { ref traceback, .. } => {
                 write!(fmt, "callback error: {}", traceback)
             }
+            Error::WithContext {
+                ref context,
+                ref cause,
+            } => write!(fmt, "{}: {}", context, cause),
             Error::ExternalError(ref err) => write!(fmt, "{}", err),
         }
     }
 }
 
+impl<'lua> fmt::Display for ScriptError<'lua> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(fmt, "runtime error: {}", self.message)?;
+        write!(fmt, "{}", self.traceback)
+    }
+}
+
 impl StdError for Error {
     fn source(&self) -> Option<&(dyn StdError + 'static)> {
         match *self {
             Error::CallbackError { ref cause, .. } => Some(cause.as_ref()),
+            Error::WithContext { ref cause, .. } => Some(cause.as_ref()),
+            Error::BadArgument { ref cause, .. } => Some(cause.as_ref()),
             Error::ExternalError(ref err) => err.source(),
             _ => None,
         }
@@ -239,6 +334,14 @@ impl Error {
     pub fn external<T: Into<Box<dyn StdError>>>(err: T) -> Error {
         Error::ExternalError(err.into().into())
     }
+
+    pub(crate) fn bad_argument(pos: usize, cause: Error) -> Error {
+        Error::BadArgument {
+            pos,
+            name: None,
+            cause: Arc::new(cause),
+        }
+    }
 }
 
 pub trait ExternalError {
@@ -272,6 +375,63 @@ where
     }
 }
 
+/// Adds context to errors.
+///
+/// The context is prepended to the error message, and the original error is available via
+/// [`std::error::Error::source`]. Errors with context returned from Rust callbacks keep the context
+/// when propagated through Lua code.
+///
+/// # Examples
+///
+/// ```
+/// # use mlua::{ErrorContext, Lua, Result};
+/// # fn main() -> Result<()> {
+/// let lua = Lua::new();
+/// let err = lua
+///     .load("error('boom')")
+///     .exec()
+///     .context("failed to run the script")
+///     .unwrap_err();
+/// assert!(err.to_string().starts_with("failed to run the script: "));
+/// # Ok(())
+/// # }
+/// ```
+///
+/// [`std::error::Error::source`]: https://doc.rust-lang.org/std/error/trait.Error.html#method.source
+pub trait ErrorContext: Sized {
+    /// Wraps the error with the given context message.
+    fn context<C: fmt::Display>(self, context: C) -> Self;
+
+    /// Wraps the error with a context message produced by the given function.
+    ///
+    /// The function is called only if there is an error.
+    fn with_context<C: fmt::Display, F: FnOnce(&Error) -> C>(self, f: F) -> Self;
+}
+
+impl ErrorContext for Error {
+    fn context<C: fmt::Display>(self, context: C) -> Self {
+        Error::WithContext {
+            context: context.to_string(),
+            cause: Arc::new(self),
+        }
+    }
+
+    fn with_context<C: fmt::Display, F: FnOnce(&Error) -> C>(self, f: F) -> Self {
+        let context = f(&self).to_string();
+        self.context(context)
+    }
+}
+
+impl<T> ErrorContext for Result<T> {
+    fn context<C: fmt::Display>(self, context: C) -> Self {
+        self.map_err(|err| err.context(context))
+    }
+
+    fn with_context<C: fmt::Display, F: FnOnce(&Error) -> C>(self, f: F) -> Self {
+        self.map_err(|err| err.with_context(f))
+    }
+}
+
 impl std::convert::From<AddrParseError> for Error {
     fn from(err: AddrParseError) -> Self {
         Error::external(err)
@@ -289,3 +449,25 @@ impl std::convert::From<Utf8Error> for Error {
         Error::external(err)
     }
 }
+
+#[cfg(feature = "serialize")]
+impl serde::ser::Error for Error {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        Error::ToLuaConversionError {
+            from: "Serialize",
+            to: "value",
+            message: Some(msg.to_string()),
+        }
+    }
+}
+
+#[cfg(feature = "serialize")]
+impl serde::de::Error for Error {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        Error::FromLuaConversionError {
+            from: "value",
+            to: "Deserialize",
+            message: Some(msg.to_string()),
+        }
+    }
+}
@@ -1,14 +1,20 @@
 use std::os::raw::{c_int, c_void};
+use std::result::Result as StdResult;
 use std::{ptr, slice};
 
-use crate::error::{Error, Result};
+use crate::error::{Error, Result, ScriptError};
 use crate::ffi;
+use crate::lua::relax_memory_limit;
 use crate::types::LuaRef;
 use crate::util::{
-    assert_stack, check_stack, error_traceback, pop_error, protect_lua_closure, StackGuard,
+    assert_stack, check_stack, error_traceback, pop_error, protect_lua_closure,
+    script_error_traceback, take_script_error, StackGuard,
 };
 use crate::value::{FromLuaMulti, MultiValue, ToLuaMulti};
 
+#[cfg(not(feature = "send"))]
+use crate::types::OwnedRef;
+
 #[cfg(feature = "async")]
 use {futures_core::future::LocalBoxFuture, futures_util::future};
 
@@ -67,13 +73,16 @@ impl<'lua> Function<'lua> {
             let _sg = StackGuard::new(lua.state);
             check_stack(lua.state, nargs + 3)?;
 
-            ffi::lua_pushcfunction(lua.state, error_traceback);
+            relax_memory_limit(lua.state, || {
+                ffi::lua_pushcfunction(lua.state, error_traceback)
+            });
             let stack_start = ffi::lua_gettop(lua.state);
             lua.push_ref(&self.0);
             for arg in args {
                 lua.push_value(arg)?;
             }
             let ret = ffi::lua_pcall(lua.state, nargs, ffi::LUA_MULTRET, stack_start);
+            lua.resume_warn_panic();
             if ret != ffi::LUA_OK {
                 return Err(pop_error(lua.state, ret));
             }
@@ -89,6 +98,89 @@ impl<'lua> Function<'lua> {
         R::from_lua_multi(results, lua)
     }
 
+    /// Calls the function like [`call`], but returns the details of errors raised by Lua code.
+    ///
+    /// A runtime error raised by Lua code (either by the `error` function or by the Lua VM) is
+    /// returned as `Ok(Err(script_error))` instead of [`Error::RuntimeError`], with the chunk and
+    /// the line where it was raised, the stack frames and the original error value. Other errors,
+    /// such as errors returned by Rust callbacks or conversion errors, are returned as `Err`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mlua::{Function, Lua, Result, Value};
+    /// # fn main() -> Result<()> {
+    /// # let lua = Lua::new();
+    /// let f: Function = lua.load(
+    ///     r#"
+    ///         function()
+    ///             error({ code = 42 })
+    ///         end
+    /// "#).eval()?;
+    ///
+    /// let err = f.try_call::<_, ()>(())?.unwrap_err();
+    /// assert_eq!(err.line, Some(3));
+    /// match err.value {
+    ///     Value::Table(t) => assert_eq!(t.get::<_, i64>("code")?, 42),
+    ///     _ => unreachable!(),
+    /// }
+    ///
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`call`]: #method.call
+    /// [`Error::RuntimeError`]: enum.Error.html#variant.RuntimeError
+    pub fn try_call<A, R>(&self, args: A) -> Result<StdResult<R, ScriptError<'lua>>>
+    where
+        A: ToLuaMulti<'lua>,
+        R: FromLuaMulti<'lua>,
+    {
+        let lua = self.0.lua;
+
+        let args = args.to_lua_multi(lua)?;
+        let nargs = args.len() as c_int;
+
+        let results = unsafe {
+            let _sg = StackGuard::new(lua.state);
+            check_stack(lua.state, nargs + 3)?;
+
+            relax_memory_limit(lua.state, || {
+                ffi::lua_pushcfunction(lua.state, script_error_traceback)
+            });
+            let stack_start = ffi::lua_gettop(lua.state);
+            lua.push_ref(&self.0);
+            for arg in args {
+                lua.push_value(arg)?;
+            }
+            let ret = ffi::lua_pcall(lua.state, nargs, ffi::LUA_MULTRET, stack_start);
+            lua.resume_warn_panic();
+            if ret == ffi::LUA_ERRRUN {
+                if let Some(details) = take_script_error(lua.state) {
+                    return Ok(Err(ScriptError {
+                        message: details.message,
+                        chunk: details.chunk,
+                        line: details.line,
+                        traceback: details.traceback,
+                        value: lua.pop_value(),
+                    }));
+                }
+            }
+            if ret != ffi::LUA_OK {
+                return Err(pop_error(lua.state, ret));
+            }
+            let nresults = ffi::lua_gettop(lua.state) - stack_start;
+            let mut results = MultiValue::new();
+            assert_stack(lua.state, 2);
+            for _ in 0..nresults {
+                results.push_front(lua.pop_value());
+            }
+            ffi::lua_pop(lua.state, 1);
+            results
+        };
+        R::from_lua_multi(results, lua).map(Ok)
+    }
+
     /// Returns a Feature that, when polled, calls `self`, passing `args` as function arguments,
     /// and drives the execution.
     ///
@@ -241,6 +333,19 @@ impl<'lua> Function<'lua> {
 
         Ok(data)
     }
+
+    /// Converts this function into an owned handle, which is not bound to the lifetime of `Lua`.
+    ///
+    /// See [`OwnedFunction`] for details.
+    ///
+    /// Not available with `feature = "send"`.
+    ///
+    /// [`OwnedFunction`]: struct.OwnedFunction.html
+    #[cfg(not(feature = "send"))]
+    #[cfg_attr(docsrs, doc(cfg(not(feature = "send"))))]
+    pub fn into_owned(self) -> Result<OwnedFunction> {
+        Ok(OwnedFunction(self.0.lua.make_owned_ref(&self.0)?))
+    }
 }
 
 impl<'lua> PartialEq for Function<'lua> {
@@ -248,3 +353,71 @@ impl<'lua> PartialEq for Function<'lua> {
         self.0 == other.0
     }
 }
+
+/// Owned handle to an internal Lua function.
+///
+/// Unlike [`Function`], it does not borrow the [`Lua`] instance and keeps the Lua state alive while
+/// it exists, even after the `Lua` instance is dropped. The function is stored in the Lua registry.
+/// Use [`to_ref`] to get a borrowed [`Function`] back.
+///
+/// Owned handles cannot be sent to other threads. Owned handles captured by Rust callbacks or
+/// userdata stored in the same Lua state keep the state alive forever, as this is a reference
+/// cycle.
+///
+/// Not available with `feature = "send"`, as an owned handle keeps its own [`Lua`] handle to the
+/// state, the same way as a [`Lua`] clone does.
+///
+/// # Examples
+///
+/// ```
+/// # use mlua::{Lua, OwnedFunction, Result};
+/// # fn main() -> Result<()> {
+/// struct EventHandlers {
+///     handlers: Vec<OwnedFunction>,
+/// }
+///
+/// impl EventHandlers {
+///     fn emit(&self, event: &str) -> Result<()> {
+///         for handler in &self.handlers {
+///             handler.to_ref().call::<_, ()>(event)?;
+///         }
+///         Ok(())
+///     }
+/// }
+///
+/// let lua = Lua::new();
+/// let handler = lua
+///     .load("function(event) last_event = event end")
+///     .eval::<mlua::Function>()?
+///     .into_owned()?;
+/// let events = EventHandlers { handlers: vec![handler] };
+///
+/// events.emit("started")?;
+/// assert_eq!(lua.globals().get::<_, String>("last_event")?, "started");
+/// # Ok(())
+/// # }
+/// ```
+///
+/// [`Function`]: struct.Function.html
+/// [`Lua`]: struct.Lua.html
+/// [`to_ref`]: #method.to_ref
+#[cfg(not(feature = "send"))]
+#[cfg_attr(docsrs, doc(cfg(not(feature = "send"))))]
+#[derive(Debug)]
+pub struct OwnedFunction(pub(crate) OwnedRef);
+
+#[cfg(not(feature = "send"))]
+impl OwnedFunction {
+    /// Returns a borrowed handle to the function.
+    pub fn to_ref(&self) -> Function<'_> {
+        Function(self.0.to_ref())
+    }
+
+    /// Creates a new owned handle to the same function.
+    ///
+    /// Unlike cloning a borrowed handle, this stores the function in a new registry slot, which can
+    /// fail with a memory error.
+    pub fn try_clone(&self) -> Result<Self> {
+        Ok(OwnedFunction(self.0.try_clone()?))
+    }
+}
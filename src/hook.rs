@@ -1,28 +1,64 @@
 use std::ffi::CStr;
-use std::marker::PhantomData;
+use std::fmt;
+use std::mem;
 use std::os::raw::{c_char, c_int};
+use std::sync::atomic::{AtomicUsize, Ordering};
 
+use crate::error::{Error, Result};
 use crate::ffi::{self, lua_Debug, lua_State};
 use crate::lua::Lua;
-use crate::util::callback_error;
+use crate::types::HookCallback;
+use crate::util::{assert_stack, hook_callback_error, StackGuard};
+use crate::value::{ToLua, Value};
+
+// Number of VM instructions between calls of the interrupt callback.
+pub(crate) const INTERRUPT_INSTRUCTIONS: u32 = 1000;
 
 /// Contains information about currently executing Lua code.
 ///
-/// The `Debug` structure is provided as a parameter to the hook function set with
-/// [`Lua::set_hook`].  You may call the methods on this structure to retrieve information about the
-/// Lua code executing at the time that the hook function was called.  Further information can be
-/// found in the [Lua 5.3 documentaton][lua_doc].
+/// The `Debug` structure is provided as a parameter to the hook functions set with
+/// [`Lua::set_hook`] or [`Lua::add_hook`], and to the closure passed to [`Lua::inspect_stack`].
+/// You may call the methods on this structure to retrieve information about the Lua code executing
+/// at the time that the hook function was called, or about the function running at the given stack
+/// level.
+/// Further information can be found in the [Lua 5.3 documentaton][lua_doc].
 ///
 /// [lua_doc]: https://www.lua.org/manual/5.3/manual.html#lua_Debug
 /// [`Lua::set_hook`]: struct.Lua.html#method.set_hook
+/// [`Lua::add_hook`]: struct.Lua.html#method.add_hook
+/// [`Lua::inspect_stack`]: struct.Lua.html#method.inspect_stack
 #[derive(Clone)]
 pub struct Debug<'a> {
+    lua: &'a Lua,
     ar: *mut lua_Debug,
     state: *mut lua_State,
-    _phantom: PhantomData<&'a ()>,
 }
 
 impl<'a> Debug<'a> {
+    pub(crate) fn new(lua: &'a Lua, ar: *mut lua_Debug) -> Self {
+        Debug {
+            lua,
+            ar,
+            state: lua.state,
+        }
+    }
+
+    /// Returns the kind of event that caused the hook function to be called.
+    ///
+    /// The result is meaningful only in hook functions.
+    pub fn event(&self) -> DebugEvent {
+        unsafe {
+            match (*self.ar).event {
+                ffi::LUA_HOOKCALL => DebugEvent::Call,
+                ffi::LUA_HOOKRET => DebugEvent::Ret,
+                ffi::LUA_HOOKTAILCALL => DebugEvent::TailCall,
+                ffi::LUA_HOOKLINE => DebugEvent::Line,
+                ffi::LUA_HOOKCOUNT => DebugEvent::Count,
+                event => DebugEvent::Unknown(event),
+            }
+        }
+    }
+
     /// Corresponds to the `n` what mask.
     pub fn names(&self) -> DebugNames<'a> {
         unsafe {
@@ -37,7 +73,7 @@ impl<'a> Debug<'a> {
         }
     }
 
-    /// Corresponds to the `n` what mask.
+    /// Corresponds to the `S` what mask.
     pub fn source(&self) -> DebugSource<'a> {
         unsafe {
             mlua_assert!(
@@ -93,6 +129,101 @@ impl<'a> Debug<'a> {
             }
         }
     }
+
+    /// Returns the name and the value of the `n`-th local variable of the running function.
+    ///
+    /// Local variables are numbered from 1 in the order they are declared, and only variables that
+    /// are active at the current line are available. Temporary values on the function stack follow
+    /// the named variables and have names starting with `(`. Since Lua 5.2, negative indices refer
+    /// to the vararg arguments of the function. Returns `None` if there is no local variable with
+    /// the given index.
+    ///
+    /// Corresponds to `lua_getlocal`.
+    pub fn local(&self, n: i32) -> Option<(String, Value<'a>)> {
+        unsafe {
+            let _sg = StackGuard::new(self.state);
+            assert_stack(self.state, 1);
+
+            let name = ptr_to_str(ffi::lua_getlocal(self.state, self.ar, n as c_int))?;
+            Some((bytes_to_string(name), self.lua.pop_value()))
+        }
+    }
+
+    /// Returns the names and the values of all active local variables of the running function.
+    pub fn locals(&self) -> Vec<(String, Value<'a>)> {
+        (1..)
+            .map(|n| self.local(n))
+            .take_while(Option::is_some)
+            .flatten()
+            .collect()
+    }
+
+    /// Sets the value of the `n`-th local variable of the running function.
+    ///
+    /// Returns `false` if there is no local variable with the given index. See [`local`] for the
+    /// numbering of local variables.
+    ///
+    /// Corresponds to `lua_setlocal`.
+    ///
+    /// [`local`]: #method.local
+    pub fn set_local<V: ToLua<'a>>(&self, n: i32, value: V) -> Result<bool> {
+        let value = value.to_lua(self.lua)?;
+        unsafe {
+            let _sg = StackGuard::new(self.state);
+            assert_stack(self.state, 1);
+
+            self.lua.push_value(value)?;
+            Ok(!ffi::lua_setlocal(self.state, self.ar, n as c_int).is_null())
+        }
+    }
+
+    /// Returns the name and the value of the `n`-th upvalue of the running function.
+    ///
+    /// Upvalues are numbered from 1. The names of upvalues of Rust and C functions are empty.
+    /// Returns `None` if there is no upvalue with the given index.
+    ///
+    /// Corresponds to `lua_getupvalue`.
+    pub fn upvalue(&self, n: i32) -> Option<(String, Value<'a>)> {
+        unsafe {
+            let _sg = StackGuard::new(self.state);
+            assert_stack(self.state, 2);
+
+            mlua_assert!(
+                ffi::lua_getinfo(self.state, cstr!("f"), self.ar) != 0,
+                "lua_getinfo failed with `f`"
+            );
+            let name = ptr_to_str(ffi::lua_getupvalue(self.state, -1, n as c_int))?;
+            Some((bytes_to_string(name), self.lua.pop_value()))
+        }
+    }
+
+    /// Returns the names and the values of all upvalues of the running function.
+    pub fn upvalues(&self) -> Vec<(String, Value<'a>)> {
+        (1..)
+            .map(|n| self.upvalue(n))
+            .take_while(Option::is_some)
+            .flatten()
+            .collect()
+    }
+}
+
+/// Kind of event that caused a hook function to be called, returned by [`Debug::event`].
+///
+/// [`Debug::event`]: struct.Debug.html#method.event
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DebugEvent {
+    /// A function is about to be called.
+    Call,
+    /// A function is about to return.
+    Ret,
+    /// A tail call in Lua 5.2+, or a return from a tail called function in Lua 5.1 and LuaJIT.
+    TailCall,
+    /// A new line of code is about to be executed.
+    Line,
+    /// A number of VM instructions have been executed.
+    Count,
+    /// An event not known to mlua.
+    Unknown(c_int),
 }
 
 #[derive(Clone, Debug)]
@@ -121,6 +252,166 @@ pub struct DebugStack {
     pub is_vararg: bool,
 }
 
+/// A snapshot of the call stack of a Lua thread.
+///
+/// Returned by [`Lua::traceback`] and [`Thread::traceback`]. The frames can be inspected
+/// individually, and the `Display` implementation formats the traceback the same way as the
+/// standard `debug.traceback` function.
+///
+/// [`Lua::traceback`]: struct.Lua.html#method.traceback
+/// [`Thread::traceback`]: struct.Thread.html#method.traceback
+#[derive(Clone, Debug, PartialEq)]
+pub struct Traceback {
+    /// Message to display before the traceback.
+    pub message: Option<String>,
+    /// Stack frames, starting from the innermost function.
+    ///
+    /// For very deep stacks only the innermost and the outermost frames are kept, the same way
+    /// as `debug.traceback` does.
+    pub frames: Vec<TracebackFrame>,
+    /// Number of frames omitted from the middle of a very deep stack, or 0 if all frames are kept.
+    pub skipped: usize,
+    /// Position in `frames` where the frames were omitted.
+    pub skipped_at: usize,
+}
+
+/// A single frame of a [`Traceback`].
+///
+/// [`Traceback`]: struct.Traceback.html
+#[derive(Clone, Debug, PartialEq)]
+pub struct TracebackFrame {
+    /// A "printable" version of the source of the function, for example `[string "chunk"]`.
+    pub source: String,
+    /// The line being executed, or `None` if not available (for example, in C or Rust functions).
+    pub line: Option<i32>,
+    /// The line where the function definition starts, or `None` if not available.
+    pub line_defined: Option<i32>,
+    /// A reasonable name of the function, or `None` if not known.
+    pub name: Option<String>,
+    /// Explains the `name` field: "global", "local", "method", "field", "upvalue" or empty.
+    pub name_what: String,
+    /// Kind of the function: "Lua", "C", "main" or "tail".
+    pub what: String,
+}
+
+impl Traceback {
+    // Collects the frames of the given thread starting from `level`. As with `debug.traceback`,
+    // only the first and the last few frames of very deep stacks are kept.
+    pub(crate) unsafe fn new(state: *mut lua_State, message: Option<String>, level: c_int) -> Self {
+        let skip = skipped_levels(level, last_level(state));
+        let mut frames = Vec::new();
+        let (mut skipped, mut skipped_at) = (0, 0);
+        let mut ar: lua_Debug = mem::zeroed();
+        let mut level = level;
+        while ffi::lua_getstack(state, level, &mut ar) != 0 {
+            if let Some((first, next)) = skip {
+                if level == first {
+                    skipped = (next - first) as usize;
+                    skipped_at = frames.len();
+                    level = next;
+                    continue;
+                }
+            }
+            mlua_assert!(
+                ffi::lua_getinfo(state, cstr!("Sln"), &mut ar) != 0,
+                "lua_getinfo failed with `Sln`"
+            );
+            let str_or_empty = |s| ptr_to_str(s).map(bytes_to_string).unwrap_or_default();
+            frames.push(TracebackFrame {
+                source: str_or_empty(ar.short_src.as_ptr()),
+                line: Some(ar.currentline).filter(|&l| l > 0),
+                line_defined: Some(ar.linedefined).filter(|&l| l > 0),
+                name: ptr_to_str(ar.name).map(bytes_to_string),
+                name_what: str_or_empty(ar.namewhat),
+                what: str_or_empty(ar.what),
+            });
+            level += 1;
+        }
+        Traceback {
+            message,
+            frames,
+            skipped,
+            skipped_at,
+        }
+    }
+}
+
+// Returns the first level omitted by `debug.traceback` when the traceback starts at `level` and
+// `last` is the deepest level, and the level to continue from, or `None` if no levels are omitted.
+// Each Lua version has its own limits and a slightly different way to apply them.
+fn skipped_levels(level: c_int, last: c_int) -> Option<(c_int, c_int)> {
+    #[cfg(any(feature = "lua54", feature = "lua53"))]
+    let (first, next, skip) = (level + 10, last - 10, last - level > 21);
+    #[cfg(feature = "lua52")]
+    let (first, next, skip) = (11, last - 10, last > 22 && level <= 11);
+    #[cfg(any(feature = "lua51", feature = "luajit"))]
+    let (first, next, skip) = (level.max(12), last - 9, level.max(12) + 11 <= last);
+    if skip {
+        Some((first, next))
+    } else {
+        None
+    }
+}
+
+// Finds the deepest valid stack level using binary search, as `lua_getstack` is linear in the
+// level.
+unsafe fn last_level(state: *mut lua_State) -> c_int {
+    let mut ar: lua_Debug = mem::zeroed();
+    let (mut li, mut le) = (1, 1);
+    while ffi::lua_getstack(state, le, &mut ar) != 0 {
+        li = le;
+        le *= 2;
+    }
+    while li < le {
+        let m = (li + le) / 2;
+        if ffi::lua_getstack(state, m, &mut ar) != 0 {
+            li = m + 1;
+        } else {
+            le = m;
+        }
+    }
+    le - 1
+}
+
+impl fmt::Display for Traceback {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(ref message) = self.message {
+            writeln!(f, "{}", message)?;
+        }
+        format_frames(f, self)
+    }
+}
+
+// Formats stack frames of the traceback the same way as `debug.traceback`, ignoring the message.
+fn format_frames(f: &mut fmt::Formatter, traceback: &Traceback) -> fmt::Result {
+    write!(f, "stack traceback:")?;
+    for (i, frame) in traceback.frames.iter().enumerate() {
+        if traceback.skipped > 0 && i == traceback.skipped_at {
+            // Lua 5.4 reports one level less than it actually skips
+            #[cfg(feature = "lua54")]
+            write!(f, "\n\t...\t(skipping {} levels)", traceback.skipped - 1)?;
+            #[cfg(not(feature = "lua54"))]
+            write!(f, "\n\t...")?;
+        }
+        write!(f, "\n\t{}:", frame.source)?;
+        if let Some(line) = frame.line {
+            write!(f, "{}:", line)?;
+        }
+        match (&frame.name, frame.what.as_str()) {
+            (Some(name), _) if !frame.name_what.is_empty() => write!(f, " in function '{}'", name)?,
+            (_, "main") => write!(f, " in main chunk")?,
+            (_, "C") | (_, "tail") => write!(f, " in ?")?,
+            _ => write!(
+                f,
+                " in function <{}:{}>",
+                frame.source,
+                frame.line_defined.unwrap_or(0)
+            )?,
+        }
+    }
+    Ok(())
+}
+
 /// Determines when a hook function will be called by Lua.
 #[derive(Clone, Copy, Debug, Default)]
 pub struct HookTriggers {
@@ -158,35 +449,126 @@ impl HookTriggers {
         mask
     }
 
-    // Returns the `count` parameter to pass to `lua_sethook`, if applicable. Otherwise, zero is
-    // returned.
-    pub(crate) fn count(&self) -> c_int {
-        self.every_nth_instruction.unwrap_or(0) as c_int
+    // Returns `true` if the hook should be called for the given event, except `LUA_HOOKCOUNT`.
+    pub(crate) fn triggered_by(&self, event: c_int) -> bool {
+        match event {
+            ffi::LUA_HOOKCALL => self.on_calls,
+            ffi::LUA_HOOKRET => self.on_returns,
+            // Tail call event in Lua 5.2+ and tail return event in Lua 5.1/LuaJIT
+            #[cfg(any(feature = "lua54", feature = "lua53", feature = "lua52"))]
+            ffi::LUA_HOOKTAILCALL => self.on_calls,
+            #[cfg(any(feature = "lua51", feature = "luajit"))]
+            ffi::LUA_HOOKTAILCALL => self.on_returns,
+            ffi::LUA_HOOKLINE => self.every_line,
+            _ => false,
+        }
     }
 }
 
-pub(crate) unsafe extern "C" fn hook_proc(state: *mut lua_State, ar: *mut lua_Debug) {
-    callback_error(state, |_| {
-        let debug = Debug {
-            ar,
-            state,
-            _phantom: PhantomData,
-        };
+/// A handle to a hook function registered with [`Lua::add_hook`].
+///
+/// The handle can be passed to [`Lua::remove_hook_handle`] to remove only this hook, leaving the
+/// other registered hooks in place.
+///
+/// [`Lua::add_hook`]: struct.Lua.html#method.add_hook
+/// [`Lua::remove_hook_handle`]: struct.Lua.html#method.remove_hook_handle
+#[derive(Debug, PartialEq, Eq, Hash)]
+pub struct HookHandle(pub(crate) usize);
+
+impl HookHandle {
+    // Handles are unique across all Lua states, so removing a hook using a handle from
+    // another state has no effect.
+    pub(crate) fn new() -> HookHandle {
+        static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+        HookHandle(NEXT_ID.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+// Hook state of a single thread, stored in the registry with the thread as a weak key.
+pub(crate) struct ThreadHook {
+    // Hook function set with `Thread::set_hook`
+    pub(crate) hook: Option<(HookTriggers, HookCallback)>,
+    // Number of VM instructions executed by the thread, used to dispatch count events
+    pub(crate) instructions: u64,
+}
 
+/// Action to take after calling the interrupt callback set with [`Lua::set_interrupt`].
+///
+/// [`Lua::set_interrupt`]: struct.Lua.html#method.set_interrupt
+#[derive(Debug)]
+pub enum InterruptAction {
+    /// Continue execution.
+    Continue,
+    /// Yield the running coroutine, as if it called `coroutine.yield()` without arguments.
+    ///
+    /// Has no effect if the running thread cannot yield (for example, the main thread or a
+    /// coroutine running inside a C function or a metamethod).
+    ///
+    /// Requires `feature = "lua54/lua53"`
+    #[cfg(any(feature = "lua54", feature = "lua53", doc))]
+    Yield,
+    /// Stop execution by raising the provided error.
+    Error(Error),
+}
+
+pub(crate) unsafe extern "C" fn hook_proc(state: *mut lua_State, ar: *mut lua_Debug) {
+    let yield_thread = hook_callback_error(state, || {
         let lua = Lua::make_from_ptr(state);
-        let hook_cb = mlua_expect!(lua.hook_callback(), "no hook callback set in hook_proc");
+        let targets = lua.hook_targets((*ar).event)?;
 
-        #[allow(clippy::match_wild_err_arm)]
-        match hook_cb.try_borrow_mut() {
-            Ok(mut b) => (&mut *b)(&lua, debug),
-            Err(_) => mlua_panic!("Lua should not allow hooks to be called within another hook"),
-        }?;
+        for hook_cb in &targets.callbacks {
+            let debug = Debug::new(&lua, ar);
 
-        Ok(())
+            #[allow(clippy::match_wild_err_arm)]
+            match hook_cb.try_borrow_mut() {
+                Ok(mut b) => (&mut *b)(&lua, debug),
+                Err(_) => {
+                    mlua_panic!("Lua should not allow hooks to be called within another hook")
+                }
+            }?;
+        }
+
+        if targets.interrupt {
+            return lua.interrupt();
+        }
+        Ok(false)
     });
+
+    // Hooks can yield only on count and line events, which is the case for the interrupt
+    #[cfg(any(feature = "lua54", feature = "lua53"))]
+    {
+        if yield_thread && ffi::lua_isyieldable(state) != 0 {
+            ffi::lua_yield(state, 0);
+        }
+    }
+    #[cfg(any(feature = "lua52", feature = "lua51", feature = "luajit"))]
+    let _ = yield_thread;
+}
+
+// Returns the number of instructions left until a hook called every `n` instructions must be
+// called, given the number of instructions executed so far, or zero if the hook is not called.
+pub(crate) fn count_distance(n: Option<u32>, executed: u64) -> u32 {
+    match n {
+        Some(n) if n > 0 => n - (executed % n as u64) as u32,
+        _ => 0,
+    }
+}
+
+// Returns `true` if a hook called every `n` instructions must be called after the instruction
+// counter has advanced from `prev` to `next`.
+// Threads can have different instruction counts, so the counter can skip the exact multiples of `n`.
+pub(crate) fn count_triggered(n: Option<u32>, prev: u64, next: u64) -> bool {
+    match n {
+        Some(n) if n > 0 => prev / n as u64 != next / n as u64,
+        _ => false,
+    }
+}
+
+pub(crate) fn bytes_to_string(bytes: &[u8]) -> String {
+    String::from_utf8_lossy(bytes).into_owned()
 }
 
-unsafe fn ptr_to_str<'a>(input: *const c_char) -> Option<&'a [u8]> {
+pub(crate) unsafe fn ptr_to_str<'a>(input: *const c_char) -> Option<&'a [u8]> {
     if input.is_null() {
         None
     } else {
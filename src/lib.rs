@@ -16,6 +16,8 @@
 //! For more general conversions, the [`ToLuaMulti`] and [`FromLuaMulti`] traits allow converting
 //! between Rust types and *any number* of Lua values.
 //!
+//! With `feature = "macros"`, both traits can be derived for structs and enums.
+//!
 //! Most code in `mlua` is generic over implementors of those traits, so in most places the normal
 //! Rust data structures are accepted without having to write any boilerplate.
 //!
@@ -23,6 +25,10 @@
 //!
 //! The [`UserData`] trait can be implemented by user-defined types to make them available to Lua.
 //! Methods and operators to be used from Lua can be added using the [`UserDataMethods`] API.
+//! Fields are supported using the [`UserDataFields`] API.
+//!
+//! With `feature = "macros"`, the [`userdata_impl`] attribute generates the [`UserData`]
+//! implementation from annotated methods of an `impl` block.
 //!
 //! # Async/await support
 //!
@@ -32,6 +38,15 @@
 //!
 //! Requires `feature = "async"`.
 //!
+//! # Serde support
+//!
+//! With the `serialize` feature, any type implementing [`serde::Serialize`] can be converted
+//! into a Lua [`Value`] and back into any [`serde::Deserialize`] type using the [`LuaSerdeExt`]
+//! trait. Options such as attaching an array metatable to sequences or choosing enum
+//! representation can be set with [`SerializeOptions`] and [`DeserializeOptions`].
+//!
+//! Requires `feature = "serialize"`.
+//!
 //! # `Send` requirement
 //! By default `mlua` is `!Send`. This can be changed by enabling `feature = "send"` that adds `Send` requirement
 //! to `Function`s and [`UserData`].
@@ -47,14 +62,23 @@
 //! [`FromLuaMulti`]: trait.FromLuaMulti.html
 //! [`UserData`]: trait.UserData.html
 //! [`UserDataMethods`]: trait.UserDataMethods.html
+//! [`UserDataFields`]: trait.UserDataFields.html
+//! [`userdata_impl`]: attr.userdata_impl.html
 //! [`create_async_function`]: struct.Lua.html#method.create_async_function
 //! [`call_async`]: struct.Function.html#method.call_async
 //! [`AsyncThread`]: struct.AsyncThread.html
 //! [`Future`]: ../futures_core/future/trait.Future.html
+//! [`serde::Serialize`]: https://docs.serde.rs/serde/ser/trait.Serialize.html
+//! [`Value`]: enum.Value.html
+//! [`LuaSerdeExt`]: serde/trait.LuaSerdeExt.html
+//! [`serde::Deserialize`]: https://docs.serde.rs/serde/de/trait.Deserialize.html
+//! [`SerializeOptions`]: serde/ser/struct.Options.html
+//! [`DeserializeOptions`]: serde/de/struct.Options.html
 
 // Deny warnings inside doc tests / examples. When this isn't present, rustdoc doesn't show *any*
 // warnings at all.
 #![doc(test(attr(deny(warnings))))]
+#![cfg_attr(docsrs, feature(doc_cfg))]
 
 #[macro_use]
 mod macros;
@@ -65,6 +89,7 @@ mod ffi;
 mod function;
 mod hook;
 mod lua;
+mod memory;
 mod multi;
 mod scope;
 mod stdlib;
@@ -78,21 +103,41 @@ mod value;
 
 pub use crate::ffi::lua_State;
 
-pub use crate::error::{Error, ExternalError, ExternalResult, Result};
+pub use crate::error::{Error, ErrorContext, ExternalError, ExternalResult, Result, ScriptError};
 pub use crate::function::Function;
-pub use crate::hook::{Debug, DebugNames, DebugSource, DebugStack, HookTriggers};
-pub use crate::lua::{Chunk, ChunkMode, GCMode, Lua};
+pub use crate::hook::{
+    Debug, DebugEvent, DebugNames, DebugSource, DebugStack, HookHandle, HookTriggers,
+    InterruptAction, Traceback, TracebackFrame,
+};
+pub use crate::lua::{Allocator, Chunk, ChunkMode, GCMode, Lua};
+pub use crate::memory::{FunctionMemoryStats, MemoryStats};
 pub use crate::multi::Variadic;
 pub use crate::scope::Scope;
 pub use crate::stdlib::StdLib;
 pub use crate::string::String;
-pub use crate::table::{Table, TableExt, TablePairs, TableSequence};
+pub use crate::table::{Table, TableExt, TablePairs, TablePairsRef, TableSequence};
 pub use crate::thread::{Thread, ThreadStatus};
-pub use crate::types::{Integer, LightUserData, Number, RegistryKey};
-pub use crate::userdata::{AnyUserData, MetaMethod, UserData, UserDataMethods};
+pub use crate::types::{AppDataRef, AppDataRefMut, Integer, LightUserData, Number, RegistryKey};
+pub use crate::userdata::{AnyUserData, MetaMethod, UserData, UserDataFields, UserDataMethods};
 pub use crate::value::{FromLua, FromLuaMulti, MultiValue, Nil, ToLua, ToLuaMulti, Value};
 
+#[cfg(not(feature = "send"))]
+pub use crate::{
+    function::OwnedFunction, lua::WeakLua, table::OwnedTable, userdata::OwnedAnyUserData,
+};
+
 #[cfg(feature = "async")]
 pub use crate::thread::AsyncThread;
 
+#[cfg(feature = "serialize")]
+#[doc(inline)]
+pub use crate::serde::{
+    de::Options as DeserializeOptions, ser::Options as SerializeOptions, LuaSerdeExt,
+};
+
+#[cfg(feature = "macros")]
+pub use mlua_derive::{userdata_impl, FromLua, ToLua};
+
 pub mod prelude;
+#[cfg(feature = "serialize")]
+pub mod serde;
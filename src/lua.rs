@@ -1,25 +1,33 @@
+use std::alloc::{self, Layout};
 use std::any::TypeId;
 use std::cell::{RefCell, UnsafeCell};
 use std::collections::HashMap;
-use std::ffi::CString;
+use std::ffi::{CStr, CString};
 use std::marker::PhantomData;
 use std::os::raw::{c_char, c_int, c_void};
 use std::sync::{Arc, Mutex, Weak};
-use std::{mem, ptr, str};
+use std::time::Instant;
+use std::{cmp, mem, ptr, str};
 
 use crate::error::{Error, Result};
 use crate::ffi;
 use crate::function::Function;
-use crate::hook::{hook_proc, Debug, HookTriggers};
+use crate::hook::{
+    count_distance, count_triggered, hook_proc, Debug, HookHandle, HookTriggers, InterruptAction,
+    ThreadHook, Traceback, INTERRUPT_INSTRUCTIONS,
+};
+use crate::memory::{AllocStats, MemoryStats};
+use crate::multi::Variadic;
 use crate::scope::Scope;
 use crate::stdlib::StdLib;
 use crate::string::String;
 use crate::table::Table;
 use crate::thread::Thread;
 use crate::types::{
-    Callback, HookCallback, Integer, LightUserData, LuaRef, MaybeSend, Number, RegistryKey,
+    AppData, AppDataRef, AppDataRefMut, Callback, HookCallback, Integer, InterruptCallback,
+    LightUserData, LuaRef, MaybeSend, Number, RegistryKey,
 };
-use crate::userdata::{AnyUserData, MetaMethod, UserData, UserDataMethods};
+use crate::userdata::{AnyUserData, MetaMethod, UserData, UserDataFields, UserDataMethods};
 use crate::util::{
     assert_stack, callback_error, check_stack, get_gc_userdata, get_main_state,
     get_meta_gc_userdata, get_wrapped_error, init_error_registry, init_gc_metatable_for,
@@ -28,6 +36,16 @@ use crate::util::{
 };
 use crate::value::{FromLua, FromLuaMulti, MultiValue, Nil, ToLua, ToLuaMulti, Value};
 
+#[cfg(not(feature = "send"))]
+use {crate::types::OwnedRef, std::fmt};
+
+#[cfg(feature = "lua54")]
+use {
+    crate::types::WarnCallback,
+    std::any::Any,
+    std::panic::{catch_unwind, resume_unwind, AssertUnwindSafe},
+};
+
 #[cfg(feature = "async")]
 use {
     crate::types::AsyncCallback,
@@ -44,7 +62,8 @@ pub struct Lua {
     pub(crate) state: *mut ffi::lua_State,
     main_state: Option<*mut ffi::lua_State>,
     extra: Arc<Mutex<ExtraData>>,
-    ephemeral: bool,
+    // Keeps the main state alive, `None` if this instance does not own the state
+    owner: Option<Arc<LuaOwner>>,
     safe: bool,
     // Lua has lots of interior mutability, should not be RefUnwindSafe
     _no_ref_unwind_safe: PhantomData<UnsafeCell<()>>,
@@ -53,22 +72,156 @@ pub struct Lua {
 // Data associated with the lua_State.
 struct ExtraData {
     registered_userdata: HashMap<TypeId, c_int>,
+    // Functions cloning userdata types allowed to be copied to other states
+    userdata_cloners: HashMap<TypeId, UserDataCloner>,
     registry_unref_list: Arc<Mutex<Option<Vec<c_int>>>>,
 
     mem_info: *mut MemoryInfo,
+    // Owner of the main state, if it was created by us
+    owner: Weak<LuaOwner>,
 
     ref_thread: *mut ffi::lua_State,
     ref_stack_size: c_int,
     ref_stack_max: c_int,
     ref_free: Vec<c_int>,
 
-    hook_callback: Option<HookCallback>,
+    // Registered hooks in the order of registration, and the handle of the hook set by `set_hook`.
+    // Hook functions get a snapshot of the list, so it's copied on write.
+    hooks: Arc<Vec<(usize, HookTriggers, HookCallback)>>,
+    default_hook: Option<HookHandle>,
+    interrupt_callback: Option<InterruptCallback>,
+    #[cfg(feature = "lua54")]
+    warn_callback: Option<WarnCallback>,
+    // Panic of the warning function, resumed once Lua returns control to Rust
+    #[cfg(feature = "lua54")]
+    warn_panic: Option<Box<dyn Any + Send>>,
+    execution_deadline: Option<Instant>,
+
+    app_data: Box<AppData>,
+
+    // Registry reference to the metatable of sandboxed chunk environments
+    sandbox_env_mt: Option<c_int>,
+    // Registry reference to the `__metatable` field of the string metatable before the sandbox
+    // mode was enabled
+    sandbox_string_mt_field: Option<c_int>,
+}
+
+// Hooks and the interrupt callback shared by all threads.
+// A copy is kept in the registry and updated whenever they change, so that `hook_proc` can read
+// it without locking `ExtraData`.
+struct SharedHooks {
+    hooks: Arc<Vec<(usize, HookTriggers, HookCallback)>>,
+    interrupt_callback: Option<InterruptCallback>,
+    execution_deadline: Option<Instant>,
+    mem_info: *mut MemoryInfo,
+}
+
+impl SharedHooks {
+    // Returns `true` if the interrupt callback must be called every `INTERRUPT_INSTRUCTIONS`.
+    fn has_interrupt(&self) -> bool {
+        self.interrupt_callback.is_some() || self.execution_deadline.is_some()
+    }
+
+    // Returns `true` if the running Lua code must be stopped as the soft memory limit has been
+    // exceeded (LuaJIT only).
+    fn memory_limit_exceeded(&self) -> bool {
+        #[cfg(feature = "luajit")]
+        unsafe {
+            if !self.mem_info.is_null() {
+                return (*self.mem_info).limit_exceeded;
+            }
+        }
+        false
+    }
+
+    // Returns the number of VM instructions between samples of the running function made for
+    // memory statistics.
+    fn memory_sample_interval(&self) -> Option<u32> {
+        if self.mem_info.is_null() {
+            return None;
+        }
+        unsafe { (*self.mem_info).stats.as_ref()?.sample_interval }
+    }
+}
+
+// Hooks to call for an event, returned by `Lua::hook_targets`.
+pub(crate) struct HookTargets {
+    pub(crate) callbacks: Vec<HookCallback>,
+    pub(crate) interrupt: bool,
+}
+
+type UserDataCloner = for<'a, 'lua> fn(&AnyUserData<'a>, &'lua Lua) -> Result<AnyUserData<'lua>>;
+
+/// A custom memory allocator for a Lua state.
+///
+/// All the memory of a Lua state created by [`Lua::new_with_allocator`] is obtained from the
+/// allocator. The memory usage accounting and [`set_memory_limit`] keep working on top of it:
+/// requests exceeding the limit are rejected before reaching the allocator.
+///
+/// # Safety
+/// Implementations must behave like [`GlobalAlloc`]: returned blocks must be valid for the given
+/// layout (size and alignment) and stay valid until freed, and a null pointer must be returned
+/// on failure.
+///
+/// [`Lua::new_with_allocator`]: struct.Lua.html#method.new_with_allocator
+/// [`set_memory_limit`]: struct.Lua.html#method.set_memory_limit
+/// [`GlobalAlloc`]: https://doc.rust-lang.org/std/alloc/trait.GlobalAlloc.html
+pub unsafe trait Allocator: MaybeSend + 'static {
+    /// Allocates a new block of memory described by `layout`.
+    ///
+    /// # Safety
+    /// `layout` has a non-zero size.
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8;
+
+    /// Shrinks or grows a block of memory to `new_size` bytes, preserving its contents.
+    ///
+    /// On failure the old block must remain untouched.
+    ///
+    /// # Safety
+    /// `ptr` was returned by this allocator for `layout`, and `new_size` is non-zero.
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8;
+
+    /// Frees a block of memory.
+    ///
+    /// # Safety
+    /// `ptr` was returned by this allocator for `layout`.
+    unsafe fn free(&self, ptr: *mut u8, layout: Layout);
+}
+
+// The default allocator, backed by the Rust global allocator
+struct SystemAllocator;
+
+unsafe impl Allocator for SystemAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        alloc::alloc(layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        alloc::realloc(ptr, layout, new_size)
+    }
+
+    unsafe fn free(&self, ptr: *mut u8, layout: Layout) {
+        alloc::dealloc(ptr, layout)
+    }
 }
 
-#[cfg_attr(any(feature = "lua51", feature = "luajit"), allow(dead_code))]
+#[cfg_attr(feature = "luajit", allow(dead_code))]
 struct MemoryInfo {
+    allocator: Box<dyn Allocator>,
     used_memory: isize,
     memory_limit: isize,
+    // Allocation statistics, collected after `Lua::enable_memory_stats`
+    stats: Option<Box<AllocStats>>,
+    // LuaJIT tracks the memory limit after each GC cycle instead of in the allocator.
+    // Set when the limit is exceeded, until the running code is stopped by the hook.
+    #[cfg(feature = "luajit")]
+    limit_exceeded: bool,
+    // Whether the GC sentinel checking the memory limit is alive
+    #[cfg(feature = "luajit")]
+    gc_check_armed: bool,
+    // Allow allocations above the limit (see `relax_memory_limit`)
+    #[cfg(feature = "lua51")]
+    ignore_limit: bool,
 }
 
 /// Mode of the Lua garbage collector (GC).
@@ -91,29 +244,116 @@ pub(crate) struct AsyncPollPending;
 #[cfg(feature = "async")]
 pub(crate) static WAKER_REGISTRY_KEY: u8 = 0;
 pub(crate) static EXTRA_REGISTRY_KEY: u8 = 0;
+static HOOKS_REGISTRY_KEY: u8 = 0;
+static THREAD_HOOKS_REGISTRY_KEY: u8 = 0;
+#[cfg(feature = "lua54")]
+static WARN_THREAD_REGISTRY_KEY: u8 = 0;
 
 /// Requires `feature = "send"`
 #[cfg(feature = "send")]
 unsafe impl Send for Lua {}
 
-impl Drop for Lua {
+/// Creates a new handle to the same Lua state.
+///
+/// Cloning is cheap: all clones share the underlying state, which is closed when the last clone
+/// (or owned handle, such as [`OwnedTable`]) is dropped. The new handle always refers to the main
+/// thread of the state.
+///
+/// A clone captured by a Rust callback or stored in userdata keeps the state alive forever, as
+/// this is a reference cycle. Use [`WeakLua`] in such cases.
+///
+/// Not available with `feature = "send"`. A `Lua` handle is `Send` but not `Sync`, and the state
+/// is not synchronized, so clones moved to different threads would access it concurrently.
+/// Use [`Lua::into_static`] to share the state in this case.
+///
+/// [`OwnedTable`]: struct.OwnedTable.html
+/// [`WeakLua`]: struct.WeakLua.html
+/// [`Lua::into_static`]: struct.Lua.html#method.into_static
+#[cfg(not(feature = "send"))]
+#[cfg_attr(docsrs, doc(cfg(not(feature = "send"))))]
+impl Clone for Lua {
+    fn clone(&self) -> Self {
+        self.to_owner()
+    }
+}
+
+/// A weak reference to a Lua state, created with [`Lua::downgrade`].
+///
+/// Unlike a [`Lua`] clone, it does not keep the state alive, so it can be stored in userdata or
+/// captured by Rust callbacks without creating a reference cycle.
+///
+/// Not available with `feature = "send"` for the same reason as cloning [`Lua`]: a handle
+/// upgraded on another thread would access the state concurrently.
+///
+/// [`Lua::downgrade`]: struct.Lua.html#method.downgrade
+/// [`Lua`]: struct.Lua.html
+#[cfg(not(feature = "send"))]
+#[cfg_attr(docsrs, doc(cfg(not(feature = "send"))))]
+#[derive(Clone)]
+pub struct WeakLua {
+    owner: Weak<LuaOwner>,
+    safe: bool,
+}
+
+#[cfg(not(feature = "send"))]
+impl WeakLua {
+    /// Returns a new [`Lua`] handle to the state, or `None` if the state has been closed.
+    ///
+    /// [`Lua`]: struct.Lua.html
+    pub fn upgrade(&self) -> Option<Lua> {
+        let owner = self.owner.upgrade()?;
+        Some(Lua {
+            state: owner.main_state,
+            main_state: Some(owner.main_state),
+            extra: owner.extra.clone(),
+            owner: Some(owner),
+            safe: self.safe,
+            _no_ref_unwind_safe: PhantomData,
+        })
+    }
+}
+
+#[cfg(not(feature = "send"))]
+impl fmt::Debug for WeakLua {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "WeakLua({:p})", self.owner.as_ptr())
+    }
+}
+
+// Owns the main Lua state and closes it when the last owner is dropped.
+pub(crate) struct LuaOwner {
+    main_state: *mut ffi::lua_State,
+    extra: Arc<Mutex<ExtraData>>,
+}
+
+impl Drop for LuaOwner {
     fn drop(&mut self) {
         unsafe {
-            if !self.ephemeral {
+            let mem_info = {
                 let extra = mlua_expect!(self.extra.lock(), "extra is poisoned");
                 mlua_debug_assert!(
                     ffi::lua_gettop(extra.ref_thread) == extra.ref_stack_max
                         && extra.ref_stack_max as usize == extra.ref_free.len(),
                     "reference leak detected"
                 );
-                let mut unref_list =
-                    mlua_expect!(extra.registry_unref_list.lock(), "unref list poisoned");
-                *unref_list = None;
-                ffi::lua_close(self.main_state.expect("main_state is null"));
-                if !extra.mem_info.is_null() {
-                    Box::from_raw(extra.mem_info);
+                // The lock must be released before closing the state, as userdata being collected
+                // may hold registry keys
+                *mlua_expect!(extra.registry_unref_list.lock(), "unref list poisoned") = None;
+                extra.mem_info
+            };
+            // Do not rearm the GC sentinel while closing the state
+            #[cfg(feature = "luajit")]
+            {
+                if !mem_info.is_null() {
+                    (*mem_info).memory_limit = 0;
                 }
             }
+            // `extra` must not be locked while closing the state, as `__gc` metamethods can call
+            // Rust callbacks or the warning function
+            ffi::lua_close(self.main_state);
+            if !mem_info.is_null() {
+                Box::from_raw(mem_info);
+            }
         }
     }
 }
@@ -172,6 +412,78 @@ impl Lua {
         Ok(lua)
     }
 
+    /// Creates a new Lua state with a custom memory allocator and loads the specified safe subset
+    /// of the standard libraries.
+    ///
+    /// All the memory used by the state is requested from the [`Allocator`]. Memory accounting
+    /// ([`used_memory`]) and limits ([`set_memory_limit`]) work the same way as for states using
+    /// the default allocator.
+    ///
+    /// Requires `feature = "lua54/lua53/lua52/lua51"`
+    ///
+    /// # Safety
+    /// The created Lua state would have _some_ safety guarantees and would not allow to load unsafe
+    /// standard libraries or C modules.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mlua::{Allocator, Lua, Result, StdLib};
+    /// # use std::alloc::{self, Layout};
+    /// # use std::sync::atomic::{AtomicUsize, Ordering};
+    /// # use std::sync::Arc;
+    /// # fn main() -> Result<()> {
+    /// struct CountingAllocator(Arc<AtomicUsize>);
+    ///
+    /// unsafe impl Allocator for CountingAllocator {
+    ///     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+    ///         self.0.fetch_add(1, Ordering::Relaxed);
+    ///         alloc::alloc(layout)
+    ///     }
+    ///
+    ///     unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+    ///         alloc::realloc(ptr, layout, new_size)
+    ///     }
+    ///
+    ///     unsafe fn free(&self, ptr: *mut u8, layout: Layout) {
+    ///         alloc::dealloc(ptr, layout)
+    ///     }
+    /// }
+    ///
+    /// let allocations = Arc::new(AtomicUsize::new(0));
+    /// let lua = Lua::new_with_allocator(StdLib::ALL_SAFE, CountingAllocator(allocations.clone()))?;
+    /// lua.load("local t = {}").exec()?;
+    /// assert!(allocations.load(Ordering::Relaxed) > 0);
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`Allocator`]: trait.Allocator.html
+    /// [`used_memory`]: #method.used_memory
+    /// [`set_memory_limit`]: #method.set_memory_limit
+    #[cfg(any(
+        feature = "lua54",
+        feature = "lua53",
+        feature = "lua52",
+        feature = "lua51",
+        doc
+    ))]
+    pub fn new_with_allocator<A: Allocator>(libs: StdLib, allocator: A) -> Result<Lua> {
+        if libs.contains(StdLib::DEBUG) {
+            return Err(Error::SafetyError(
+                "the unsafe `debug` module can't be loaded using safe `new_with_allocator`"
+                    .to_string(),
+            ));
+        }
+
+        let mut lua = unsafe { Self::inner_new(libs, Box::new(allocator)) };
+
+        mlua_expect!(lua.disable_c_modules(), "Error during disabling C modules");
+        lua.safe = true;
+
+        Ok(lua)
+    }
+
     /// Creates a new Lua state and loads the specified subset of the standard libraries.
     ///
     /// Use the [`StdLib`] flags to specifiy the libraries you want to load.
@@ -181,83 +493,72 @@ impl Lua {
     ///
     /// [`StdLib`]: struct.StdLib.html
     pub unsafe fn unsafe_new_with(libs: StdLib) -> Lua {
-        #[cfg_attr(any(feature = "lua51", feature = "luajit"), allow(dead_code))]
-        unsafe extern "C" fn allocator(
-            extra_data: *mut c_void,
-            ptr: *mut c_void,
-            osize: usize,
-            nsize: usize,
-        ) -> *mut c_void {
-            use std::alloc;
-
-            let mem_info = &mut *(extra_data as *mut MemoryInfo);
-
-            if nsize == 0 {
-                // Free memory
-                if !ptr.is_null() {
-                    let layout =
-                        alloc::Layout::from_size_align_unchecked(osize, ffi::SYS_MIN_ALIGN);
-                    alloc::dealloc(ptr as *mut u8, layout);
-                    mem_info.used_memory -= osize as isize;
-                }
-                return ptr::null_mut();
-            }
-
-            // Are we fit to the memory limits?
-            let mut mem_diff = nsize as isize;
-            if !ptr.is_null() {
-                mem_diff -= osize as isize;
-            }
-            let new_used_memory = mem_info.used_memory + mem_diff;
-            if mem_info.memory_limit > 0 && new_used_memory > mem_info.memory_limit {
-                return ptr::null_mut();
-            }
-
-            let new_layout = alloc::Layout::from_size_align_unchecked(nsize, ffi::SYS_MIN_ALIGN);
-
-            if ptr.is_null() {
-                // Allocate new memory
-                let new_ptr = alloc::alloc(new_layout) as *mut c_void;
-                if !new_ptr.is_null() {
-                    mem_info.used_memory += mem_diff;
-                }
-                return new_ptr;
-            }
-
-            // Reallocate memory
-            let old_layout = alloc::Layout::from_size_align_unchecked(osize, ffi::SYS_MIN_ALIGN);
-            let new_ptr = alloc::realloc(ptr as *mut u8, old_layout, nsize) as *mut c_void;
-
-            if !new_ptr.is_null() {
-                mem_info.used_memory += mem_diff;
-            } else if !ptr.is_null() && nsize < osize {
-                // Should not happend
-                alloc::handle_alloc_error(new_layout);
-            }
+        Self::inner_new(libs, Box::new(SystemAllocator))
+    }
 
-            new_ptr
-        }
+    /// Creates a new Lua state with a custom memory allocator and loads the specified subset of
+    /// the standard libraries.
+    ///
+    /// See [`new_with_allocator`] for details.
+    ///
+    /// Requires `feature = "lua54/lua53/lua52/lua51"`
+    ///
+    /// # Safety
+    /// The created Lua state would not have safety guarantees and would allow to load C modules.
+    ///
+    /// [`new_with_allocator`]: #method.new_with_allocator
+    #[cfg(any(
+        feature = "lua54",
+        feature = "lua53",
+        feature = "lua52",
+        feature = "lua51",
+        doc
+    ))]
+    pub unsafe fn unsafe_new_with_allocator<A: Allocator>(libs: StdLib, allocator: A) -> Lua {
+        Self::inner_new(libs, Box::new(allocator))
+    }
 
-        #[cfg(any(feature = "lua54", feature = "lua53", feature = "lua52"))]
+    unsafe fn inner_new(libs: StdLib, allocator: Box<dyn Allocator>) -> Lua {
         let mem_info = Box::into_raw(Box::new(MemoryInfo {
+            allocator,
+            stats: None,
             used_memory: 0,
             memory_limit: 0,
+            #[cfg(feature = "luajit")]
+            limit_exceeded: false,
+            #[cfg(feature = "luajit")]
+            gc_check_armed: false,
+            #[cfg(feature = "lua51")]
+            ignore_limit: false,
         }));
 
-        #[cfg(any(feature = "lua54", feature = "lua53", feature = "lua52"))]
-        let state = ffi::lua_newstate(allocator, mem_info as *mut c_void);
-        #[cfg(any(feature = "lua51", feature = "luajit"))]
+        #[cfg(any(
+            feature = "lua54",
+            feature = "lua53",
+            feature = "lua52",
+            feature = "lua51"
+        ))]
+        let state = ffi::lua_newstate(allocator_proc, mem_info as *mut c_void);
+        // LuaJIT does not support custom allocators on all platforms, the memory limit is checked
+        // by the GC instead
+        #[cfg(feature = "luajit")]
         let state = ffi::luaL_newstate();
 
         ffi::luaL_requiref(state, cstr!("_G"), ffi::luaopen_base, 1);
         ffi::lua_pop(state, 1);
 
         let mut lua = Lua::init_from_ptr(state);
-        lua.ephemeral = false;
-        #[cfg(any(feature = "lua54", feature = "lua53", feature = "lua52"))]
+        #[allow(clippy::arc_with_non_send_sync)]
+        let owner = Arc::new(LuaOwner {
+            main_state: state,
+            extra: lua.extra.clone(),
+        });
         {
-            lua.extra.lock().unwrap().mem_info = mem_info;
+            let mut extra = lua.extra.lock().unwrap();
+            extra.mem_info = mem_info;
+            extra.owner = Arc::downgrade(&owner);
         }
+        lua.owner = Some(owner);
 
         mlua_expect!(
             protect_lua_closure(lua.main_state.expect("main_state is null"), 0, 0, |state| {
@@ -286,6 +587,10 @@ impl Lua {
                 init_gc_metatable_for::<Callback>(state, None);
                 init_gc_metatable_for::<Lua>(state, None);
                 init_gc_metatable_for::<Weak<Mutex<ExtraData>>>(state, None);
+                init_gc_metatable_for::<SharedHooks>(state, None);
+                init_gc_metatable_for::<ThreadHook>(state, None);
+                #[cfg(feature = "serialize")]
+                crate::serde::init_metatables(state);
                 #[cfg(feature = "async")]
                 {
                     init_gc_metatable_for::<AsyncCallback>(state, None);
@@ -294,6 +599,20 @@ impl Lua {
                     init_gc_metatable_for::<Waker>(state, None);
                 }
 
+                // Create the table of thread hook states. Threads are weak keys, so the states are
+                // destroyed together with their threads.
+
+                ffi::lua_newtable(state);
+                ffi::lua_newtable(state);
+                ffi::lua_pushstring(state, cstr!("k"));
+                ffi::lua_setfield(state, -2, cstr!("__mode"));
+                ffi::lua_setmetatable(state, -2);
+                ffi::lua_rawsetp(
+                    state,
+                    ffi::LUA_REGISTRYINDEX,
+                    &THREAD_HOOKS_REGISTRY_KEY as *const u8 as *mut c_void,
+                );
+
                 // Create ref stack thread and place it in the registry to prevent it from being garbage
                 // collected.
 
@@ -308,14 +627,26 @@ impl Lua {
 
         let extra = Arc::new(Mutex::new(ExtraData {
             registered_userdata: HashMap::new(),
+            userdata_cloners: HashMap::new(),
             registry_unref_list: Arc::new(Mutex::new(Some(Vec::new()))),
             ref_thread,
             mem_info: ptr::null_mut(),
+            owner: Weak::new(),
             // We need 1 extra stack space to move values in and out of the ref stack.
             ref_stack_size: ffi::LUA_MINSTACK - 1,
             ref_stack_max: 0,
             ref_free: Vec::new(),
-            hook_callback: None,
+            hooks: Arc::default(),
+            default_hook: None,
+            interrupt_callback: None,
+            #[cfg(feature = "lua54")]
+            warn_callback: None,
+            #[cfg(feature = "lua54")]
+            warn_panic: None,
+            execution_deadline: None,
+            app_data: Box::new(AppData::default()),
+            sandbox_env_mt: None,
+            sandbox_string_mt_field: None,
         }));
 
         mlua_expect!(
@@ -333,6 +664,27 @@ impl Lua {
             "Error while storing extra data"
         );
 
+        let hooks = SharedHooks {
+            hooks: Arc::default(),
+            interrupt_callback: None,
+            execution_deadline: None,
+            mem_info: ptr::null_mut(),
+        };
+        mlua_expect!(
+            push_gc_userdata(main_state, hooks),
+            "Error while storing hooks",
+        );
+        mlua_expect!(
+            protect_lua_closure(main_state, 1, 0, |state| {
+                ffi::lua_rawsetp(
+                    state,
+                    ffi::LUA_REGISTRYINDEX,
+                    &HOOKS_REGISTRY_KEY as *const u8 as *mut c_void,
+                );
+            }),
+            "Error while storing hooks"
+        );
+
         mlua_debug_assert!(
             ffi::lua_gettop(main_state) == main_state_top,
             "stack leak during creation"
@@ -343,7 +695,7 @@ impl Lua {
             state,
             main_state: maybe_main_state,
             extra,
-            ephemeral: true,
+            owner: None,
             safe: false,
             _no_ref_unwind_safe: PhantomData,
         }
@@ -377,6 +729,120 @@ impl Lua {
         }
     }
 
+    /// Enables or disables the sandbox mode.
+    ///
+    /// In the sandbox mode every chunk loaded without an explicit environment gets its own isolated
+    /// environment table. Global variables assigned by a chunk are stored in that table, while
+    /// reads fall back to a read-only view of the global table. All tables reachable from the
+    /// globals (including the standard library tables) are read-only in this view, and the `string`
+    /// metatable cannot be accessed from Lua code.
+    ///
+    /// Everything that gives access to the host system or to the internals of the Lua state is
+    /// hidden from sandboxed chunks: the `debug`, `ffi`, `io` and `package` libraries, the `require`,
+    /// `dofile`, `loadfile` and `module` functions, and all functions of the `os` library except
+    /// `os.clock`, `os.date`, `os.difftime` and `os.time`. Note that this is stricter than the
+    /// [`StdLib::ALL_SAFE`] subset, which only excludes the libraries that can break memory safety.
+    /// `load` (and `loadstring`) compile text chunks only and give them a new isolated environment
+    /// unless one is passed explicitly. Values added to the globals by the host are visible as is,
+    /// so it's up to the host to not expose anything unsafe.
+    ///
+    /// The global table itself (returned by [`globals`]) is not affected, so the host can still
+    /// add new values which become visible to the sandboxed chunks.
+    ///
+    /// Note that on Lua 5.1 and LuaJIT the read-only tables cannot be iterated using `pairs`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mlua::{Lua, Result};
+    /// # fn main() -> Result<()> {
+    /// let lua = Lua::new();
+    /// lua.sandbox(true)?;
+    ///
+    /// lua.load("var = 123").exec()?;
+    /// assert_eq!(lua.load("var").eval::<Option<i32>>()?, None);
+    /// assert!(lua.load("string.len = nil").exec().is_err());
+    ///
+    /// lua.sandbox(false)?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// Disabling the sandbox mode restores the `string` metatable protection that was in place
+    /// before it was enabled.
+    ///
+    /// [`StdLib::ALL_SAFE`]: struct.StdLib.html#associatedconstant.ALL_SAFE
+    /// [`globals`]: #method.globals
+    pub fn sandbox(&self, enabled: bool) -> Result<()> {
+        let sandbox_env_mt = mlua_expect!(self.extra.lock(), "extra is poisoned").sandbox_env_mt;
+        if enabled == sandbox_env_mt.is_some() {
+            return Ok(());
+        }
+
+        unsafe {
+            let _sg = StackGuard::new(self.state);
+            assert_stack(self.state, 5);
+
+            if enabled {
+                let load = self.create_function(sandbox_load)?;
+                self.push_ref(&self.globals().0);
+                self.push_ref(&load.0);
+                protect_lua(self.state, 2, sandbox_init)?;
+
+                let id = protect_lua_closure(self.state, 1, 0, |state| {
+                    ffi::luaL_ref(state, ffi::LUA_REGISTRYINDEX)
+                })?;
+                mlua_expect!(self.extra.lock(), "extra is poisoned").sandbox_env_mt = Some(id);
+            } else if let Some(id) = sandbox_env_mt {
+                ffi::luaL_unref(self.state, ffi::LUA_REGISTRYINDEX, id);
+                mlua_expect!(self.extra.lock(), "extra is poisoned").sandbox_env_mt = None;
+            }
+
+            // Protect the `string` metatable, or restore its previous `__metatable` field
+            let field = if enabled {
+                None
+            } else {
+                let mut extra = mlua_expect!(self.extra.lock(), "extra is poisoned");
+                extra.sandbox_string_mt_field.take()
+            };
+            push_string(self.state, "")?;
+            if ffi::lua_getmetatable(self.state, -1) != 0 {
+                push_string(self.state, "__metatable")?;
+                if enabled {
+                    ffi::lua_pushvalue(self.state, -1);
+                    ffi::lua_rawget(self.state, -3);
+                    let id = protect_lua_closure(self.state, 1, 0, |state| {
+                        ffi::luaL_ref(state, ffi::LUA_REGISTRYINDEX)
+                    })?;
+                    let mut extra = mlua_expect!(self.extra.lock(), "extra is poisoned");
+                    extra.sandbox_string_mt_field = Some(id);
+                    drop(extra);
+                    ffi::lua_pushboolean(self.state, 0);
+                } else {
+                    ffi::lua_rawgeti(
+                        self.state,
+                        ffi::LUA_REGISTRYINDEX,
+                        field.unwrap_or(ffi::LUA_REFNIL) as ffi::lua_Integer,
+                    );
+                }
+                protect_lua_closure(self.state, 3, 1, |state| {
+                    ffi::lua_rawset(state, -3);
+                })?;
+            }
+            if let Some(id) = field {
+                ffi::luaL_unref(self.state, ffi::LUA_REGISTRYINDEX, id);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns `true` if the sandbox mode is enabled.
+    pub fn is_sandboxed(&self) -> bool {
+        let extra = mlua_expect!(self.extra.lock(), "extra is poisoned");
+        extra.sandbox_env_mt.is_some()
+    }
+
     /// Consumes and leaks `Lua` object, returning a static reference `&'static Lua`.
     ///
     /// This function is useful when the `Lua` object is supposed to live for the remainder
@@ -388,11 +854,64 @@ impl Lua {
     /// the reference should first be wrapped with the [`Lua::from_static`] function producing a `Lua`.
     /// This `Lua` object can then be dropped which will properly release the allocated memory.
     ///
+    /// Without `feature = "send"`, cloning `Lua` is usually a better alternative, as the state is
+    /// released when the last clone is dropped.
+    ///
     /// [`Lua::from_static`]: #method.from_static
     pub fn into_static(self) -> &'static Self {
         Box::leak(Box::new(self))
     }
 
+    /// Creates a [`WeakLua`] reference to this Lua state.
+    ///
+    /// The weak reference cannot be upgraded if the state was not created by us (for example, in
+    /// module mode).
+    ///
+    /// Not available with `feature = "send"`, see [`WeakLua`] for details.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use mlua::{Lua, Result, UserData, UserDataMethods, WeakLua};
+    /// # fn main() -> Result<()> {
+    /// struct Plugin {
+    ///     lua: WeakLua,
+    /// }
+    ///
+    /// impl UserData for Plugin {
+    ///     fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+    ///         methods.add_method("version", |_, this, ()| {
+    ///             let lua = this.lua.upgrade().expect("Lua state is closed");
+    ///             let version = lua.globals().get::<_, String>("_VERSION")?;
+    ///             Ok(version)
+    ///         });
+    ///     }
+    /// }
+    ///
+    /// let lua = Lua::new();
+    /// let plugin = Plugin { lua: lua.downgrade() };
+    /// lua.globals().set("plugin", plugin)?;
+    /// assert!(lua.load("plugin:version()").eval::<String>()?.starts_with("Lua"));
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`WeakLua`]: struct.WeakLua.html
+    #[cfg(not(feature = "send"))]
+    #[cfg_attr(docsrs, doc(cfg(not(feature = "send"))))]
+    pub fn downgrade(&self) -> WeakLua {
+        let owner = match self.owner {
+            Some(ref owner) => Arc::downgrade(owner),
+            None => mlua_expect!(self.extra.lock(), "extra is poisoned")
+                .owner
+                .clone(),
+        };
+        WeakLua {
+            owner,
+            safe: self.safe,
+        }
+    }
+
     /// Constructs a `Lua` from a static reference to it.
     ///
     /// # Safety
@@ -424,6 +943,14 @@ impl Lua {
     /// limited form of execution limits by setting [`HookTriggers.every_nth_instruction`] and
     /// erroring once an instruction limit has been reached.
     ///
+    /// Calling this function again replaces the hook previously set by `set_hook`, but does not
+    /// affect hooks registered with [`add_hook`].
+    ///
+    /// The hook applies to all threads. New coroutines inherit it from the thread creating them,
+    /// and coroutines that already have a hook installed get it at their next hook event.
+    /// Coroutines with no hook get it when resumed using [`Thread::resume`], so a coroutine
+    /// created before any hook was set and resumed only from Lua code does not get it.
+    ///
     /// # Example
     ///
     /// Shows each line number of code being executed by the Lua interpreter.
@@ -449,45 +976,439 @@ impl Lua {
     ///
     /// [`HookTriggers`]: struct.HookTriggers.html
     /// [`HookTriggers.every_nth_instruction`]: struct.HookTriggers.html#field.every_nth_instruction
+    /// [`add_hook`]: #method.add_hook
+    /// [`Thread::resume`]: struct.Thread.html#method.resume
     pub fn set_hook<F>(&self, triggers: HookTriggers, callback: F) -> Result<()>
     where
         F: 'static + MaybeSend + FnMut(&Lua, Debug) -> Result<()>,
     {
-        let state = self.main_state.ok_or(Error::MainThreadNotAvailable)?;
-        unsafe {
+        self.main_state.ok_or(Error::MainThreadNotAvailable)?;
+        let handle = HookHandle::new();
+        {
             let mut extra = mlua_expect!(self.extra.lock(), "extra is poisoned");
-            extra.hook_callback = Some(Arc::new(RefCell::new(callback)));
-            ffi::lua_sethook(state, Some(hook_proc), triggers.mask(), triggers.count());
+            let old = extra.default_hook.replace(HookHandle(handle.0));
+            let hooks = Arc::make_mut(&mut extra.hooks);
+            if let Some(old) = old {
+                hooks.retain(|(h, _, _)| *h != old.0);
+            }
+            let callback: HookCallback = Arc::new(RefCell::new(callback));
+            hooks.push((handle.0, triggers, callback));
         }
+        unsafe { self.update_hooks() };
         Ok(())
     }
 
     /// Remove any hook previously set by `set_hook`. This function has no effect if a hook was not
     /// previously set.
+    ///
+    /// Hooks registered with [`add_hook`] are not removed.
+    ///
+    /// [`add_hook`]: #method.add_hook
     pub fn remove_hook(&self) {
         // If main_state is not available, then sethook wasn't called.
+        if self.main_state.is_none() {
+            return;
+        }
+        let removed = {
+            let mut extra = mlua_expect!(self.extra.lock(), "extra is poisoned");
+            match extra.default_hook.take() {
+                Some(old) => {
+                    Arc::make_mut(&mut extra.hooks).retain(|(h, _, _)| *h != old.0);
+                    true
+                }
+                None => false,
+            }
+        };
+        if removed {
+            unsafe { self.update_hooks() };
+        }
+    }
+
+    /// Registers an additional 'hook' function that will periodically be called as Lua code
+    /// executes.
+    ///
+    /// Unlike [`set_hook`], any number of hooks can be registered at the same time, each with its
+    /// own [`HookTriggers`]. Every hook is called only for the events it is interested in, in the
+    /// order of registration. If a hook returns an error, the remaining hooks are not called for
+    /// that event.
+    ///
+    /// Returns a [`HookHandle`] that can be passed to [`remove_hook_handle`] to remove this hook
+    /// without affecting the others.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use std::sync::atomic::{AtomicUsize, Ordering};
+    /// # use std::sync::Arc;
+    /// # use mlua::{Lua, HookTriggers, Result};
+    /// # fn main() -> Result<()> {
+    /// let lua = Lua::new();
+    /// let calls = Arc::new(AtomicUsize::new(0));
+    ///
+    /// let calls2 = calls.clone();
+    /// let profiler = lua.add_hook(HookTriggers {
+    ///     on_calls: true, ..Default::default()
+    /// }, move |_lua, _debug| {
+    ///     calls2.fetch_add(1, Ordering::Relaxed);
+    ///     Ok(())
+    /// })?;
+    ///
+    /// lua.load("string.len('abc')").exec()?;
+    /// lua.remove_hook_handle(profiler);
+    /// assert!(calls.load(Ordering::Relaxed) > 0);
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`set_hook`]: #method.set_hook
+    /// [`HookTriggers`]: struct.HookTriggers.html
+    /// [`HookHandle`]: struct.HookHandle.html
+    /// [`remove_hook_handle`]: #method.remove_hook_handle
+    pub fn add_hook<F>(&self, triggers: HookTriggers, callback: F) -> Result<HookHandle>
+    where
+        F: 'static + MaybeSend + FnMut(&Lua, Debug) -> Result<()>,
+    {
+        self.main_state.ok_or(Error::MainThreadNotAvailable)?;
+        let handle = HookHandle::new();
+        {
+            let mut extra = mlua_expect!(self.extra.lock(), "extra is poisoned");
+            let callback: HookCallback = Arc::new(RefCell::new(callback));
+            Arc::make_mut(&mut extra.hooks).push((handle.0, triggers, callback));
+        }
+        unsafe { self.update_hooks() };
+        Ok(handle)
+    }
+
+    /// Removes the hook registered with [`add_hook`] identified by the given handle.
+    ///
+    /// This function has no effect if the hook was already removed or the handle belongs to
+    /// another Lua state.
+    ///
+    /// [`add_hook`]: #method.add_hook
+    pub fn remove_hook_handle(&self, handle: HookHandle) {
+        if self.main_state.is_none() {
+            return;
+        }
+        let removed = {
+            let mut extra = mlua_expect!(self.extra.lock(), "extra is poisoned");
+            if extra.hooks.iter().all(|(h, _, _)| *h != handle.0) {
+                return;
+            }
+            Arc::make_mut(&mut extra.hooks).retain(|(h, _, _)| *h != handle.0);
+            true
+        };
+        if removed {
+            unsafe { self.update_hooks() };
+        }
+    }
+
+    /// Gets information about the interpreter runtime stack.
+    ///
+    /// Calls `f` with a [`Debug`] structure describing the function executing at the given stack
+    /// level and returns its result, or returns `None` if the level is greater than the stack
+    /// depth. Level `0` is the currently running
+    /// function, level `n+1` is the function that has called level `n`. Within a Rust callback
+    /// created by [`create_function`], level `0` is the callback itself and level `1` is the
+    /// function that called it.
+    ///
+    /// Corresponds to `lua_getstack`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use mlua::{Lua, Result};
+    /// # fn main() -> Result<()> {
+    /// let lua = Lua::new();
+    /// let log = lua.create_function(|lua, msg: String| {
+    ///     let line = lua.inspect_stack(1, |caller| caller.curr_line()).expect("no caller");
+    ///     println!("line {}: {}", line, msg);
+    ///     Ok(())
+    /// })?;
+    /// lua.globals().set("log", log)?;
+    ///
+    /// lua.load(r#"log("hello")"#).exec()
+    /// # }
+    /// ```
+    ///
+    /// [`Debug`]: struct.Debug.html
+    /// [`create_function`]: #method.create_function
+    pub fn inspect_stack<R, F>(&self, level: usize, f: F) -> Option<R>
+    where
+        F: FnOnce(Debug) -> R,
+    {
+        unsafe {
+            let mut ar: ffi::lua_Debug = mem::zeroed();
+            if ffi::lua_getstack(self.state, level as c_int, &mut ar) == 0 {
+                return None;
+            }
+            Some(f(Debug::new(self, &mut ar)))
+        }
+    }
+
+    /// Captures a traceback of the call stack of the running thread.
+    ///
+    /// Frames are collected starting from the given stack `level` (see [`inspect_stack`]), and the
+    /// optional `msg` is prepended to the formatted traceback. Unlike tracebacks attached to
+    /// errors, the returned [`Traceback`] also provides structured access to each frame.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use mlua::{Lua, Result};
+    /// # fn main() -> Result<()> {
+    /// let lua = Lua::new();
+    /// let snapshot = lua.create_function(|lua, ()| {
+    ///     let traceback = lua.traceback(Some("snapshot"), 1);
+    ///     assert_eq!(traceback.frames[0].line, Some(3));
+    ///     Ok(traceback.to_string())
+    /// })?;
+    /// lua.globals().set("snapshot", snapshot)?;
+    ///
+    /// let traceback: String = lua.load(r#"
+    ///     local function f()
+    ///         return (snapshot())
+    ///     end
+    ///     return f()
+    /// "#).eval()?;
+    /// println!("{}", traceback);
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`inspect_stack`]: #method.inspect_stack
+    /// [`Traceback`]: struct.Traceback.html
+    pub fn traceback(&self, msg: Option<&str>, level: usize) -> Traceback {
+        unsafe { Traceback::new(self.state, msg.map(|s| s.to_owned()), level as c_int) }
+    }
+
+    /// Sets an 'interrupt' function that will be periodically called as Lua code executes.
+    ///
+    /// The interrupt function is called every few hundreds of VM instructions and returns an
+    /// [`InterruptAction`] telling Lua whether to continue execution, yield the running coroutine
+    /// (Lua 5.3 and 5.4 only), or stop with an error. Unlike [`set_hook`], the interrupt function does
+    /// not replace the hook and both can be used at the same time.
+    ///
+    /// The interrupt function can be used for cooperative cancellation of long running scripts or
+    /// to implement execution time limits (see also [`set_execution_deadline`]).
+    ///
+    /// The interrupt function applies to all threads in the same way as hooks set with
+    /// [`set_hook`]. Note that compiled code in LuaJIT does not trigger the interrupt function.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use std::sync::atomic::{AtomicBool, Ordering};
+    /// # use std::sync::Arc;
+    /// # use mlua::{Error, InterruptAction, Lua, Result};
+    /// # fn main() -> Result<()> {
+    /// let lua = Lua::new();
+    /// let cancelled = Arc::new(AtomicBool::new(false));
+    ///
+    /// let cancelled2 = cancelled.clone();
+    /// lua.set_interrupt(move |_lua| {
+    ///     if cancelled2.load(Ordering::Relaxed) {
+    ///         return InterruptAction::Error(Error::RuntimeError("cancelled".to_string()));
+    ///     }
+    ///     InterruptAction::Continue
+    /// })?;
+    ///
+    /// cancelled.store(true, Ordering::Relaxed);
+    /// assert!(lua.load("while true do end").exec().is_err());
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`InterruptAction`]: enum.InterruptAction.html
+    /// [`set_hook`]: #method.set_hook
+    /// [`set_execution_deadline`]: #method.set_execution_deadline
+    pub fn set_interrupt<F>(&self, callback: F) -> Result<()>
+    where
+        F: 'static + MaybeSend + FnMut(&Lua) -> InterruptAction,
+    {
+        self.main_state.ok_or(Error::MainThreadNotAvailable)?;
+        {
+            let mut extra = mlua_expect!(self.extra.lock(), "extra is poisoned");
+            extra.interrupt_callback = Some(Arc::new(RefCell::new(callback)));
+        }
+        unsafe { self.update_hooks() };
+        Ok(())
+    }
+
+    /// Removes any interrupt function previously set by `set_interrupt`.
+    ///
+    /// This function has no effect if an interrupt function was not previously set.
+    pub fn remove_interrupt(&self) {
+        if self.main_state.is_none() {
+            return;
+        }
+        let interrupt_cb = {
+            let mut extra = mlua_expect!(self.extra.lock(), "extra is poisoned");
+            extra.interrupt_callback.take()
+        };
+        unsafe { self.update_hooks() };
+        drop(interrupt_cb);
+    }
+
+    /// Sets a deadline for executing Lua code.
+    ///
+    /// Once the deadline has passed, any running Lua code is stopped with the
+    /// [`Error::DeadlineExceeded`] error (wrapped into [`Error::CallbackError`]). The deadline is
+    /// checked alongside the interrupt function (see [`set_interrupt`]) and applies to all code
+    /// executed after that moment until a new deadline is set or it is removed using
+    /// [`remove_execution_deadline`].
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use std::time::{Duration, Instant};
+    /// # use mlua::{Error, Lua, Result};
+    /// # fn main() -> Result<()> {
+    /// let lua = Lua::new();
+    /// lua.set_execution_deadline(Instant::now() + Duration::from_millis(10))?;
+    ///
+    /// match lua.load("while true do end").exec() {
+    ///     Err(Error::CallbackError { cause, .. }) => {
+    ///         assert!(matches!(*cause, Error::DeadlineExceeded))
+    ///     }
+    ///     r => panic!("unexpected result: {:?}", r),
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`Error::DeadlineExceeded`]: enum.Error.html#variant.DeadlineExceeded
+    /// [`Error::CallbackError`]: enum.Error.html#variant.CallbackError
+    /// [`set_interrupt`]: #method.set_interrupt
+    /// [`remove_execution_deadline`]: #method.remove_execution_deadline
+    pub fn set_execution_deadline(&self, deadline: Instant) -> Result<()> {
+        self.main_state.ok_or(Error::MainThreadNotAvailable)?;
+        mlua_expect!(self.extra.lock(), "extra is poisoned").execution_deadline = Some(deadline);
+        unsafe { self.update_hooks() };
+        Ok(())
+    }
+
+    /// Removes the execution deadline previously set by `set_execution_deadline`.
+    pub fn remove_execution_deadline(&self) {
+        if self.main_state.is_none() {
+            return;
+        }
+        mlua_expect!(self.extra.lock(), "extra is poisoned").execution_deadline = None;
+        unsafe { self.update_hooks() };
+    }
+
+    /// Sets a warning function that will be called with the warnings emitted by Lua.
+    ///
+    /// The function receives the message and a flag telling whether the message is continued by
+    /// the next call (see [`warning`]). Warnings are emitted by the `warn` function in Lua code or
+    /// by [`warning`] from Rust. Control messages (starting with `@`) are passed through as is.
+    ///
+    /// The warning function replaces the default one, which prints warnings to `stderr`.
+    /// A panic in the warning function cannot unwind through Lua, so it is caught and resumed once
+    /// Lua returns control to Rust, for example when the running [`Function::call`] or
+    /// [`Thread::resume`] returns.
+    ///
+    /// Warnings emitted by the garbage collector (for example, by a failing `__gc` metamethod)
+    /// while `Lua` is busy updating its internal state are dropped without calling the warning
+    /// function.
+    ///
+    /// Requires `feature = "lua54"`
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use std::sync::{Arc, Mutex};
+    /// # use mlua::{Lua, Result};
+    /// # fn main() -> Result<()> {
+    /// let lua = Lua::new();
+    /// let warnings = Arc::new(Mutex::new(Vec::new()));
+    ///
+    /// let warnings2 = warnings.clone();
+    /// lua.set_warning_function(move |_lua, msg, _to_continue| {
+    ///     warnings2.lock().unwrap().push(String::from_utf8_lossy(msg).into_owned());
+    /// })?;
+    ///
+    /// lua.load(r#"warn("something is wrong")"#).exec()?;
+    /// assert_eq!(*warnings.lock().unwrap(), vec!["something is wrong"]);
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`warning`]: #method.warning
+    /// [`Function::call`]: struct.Function.html#method.call
+    /// [`Thread::resume`]: struct.Thread.html#method.resume
+    #[cfg(any(feature = "lua54", doc))]
+    pub fn set_warning_function<F>(&self, callback: F) -> Result<()>
+    where
+        F: 'static + MaybeSend + Fn(&Lua, &[u8], bool),
+    {
+        let state = self.main_state.ok_or(Error::MainThreadNotAvailable)?;
+        unsafe {
+            let _sg = StackGuard::new(self.state);
+            assert_stack(self.state, 2);
+
+            // Lua does not pass the state that raised a warning to the warning function, so the
+            // callback runs on a dedicated thread instead of the main state, which may be in the
+            // middle of resuming a coroutine
+            let thread = protect_lua_closure(self.state, 0, 1, |state| ffi::lua_newthread(state))?;
+            protect_lua_closure(self.state, 1, 0, |state| {
+                let key = &WARN_THREAD_REGISTRY_KEY as *const u8 as *mut c_void;
+                ffi::lua_rawsetp(state, ffi::LUA_REGISTRYINDEX, key);
+            })?;
+
+            let mut extra = mlua_expect!(self.extra.lock(), "extra is poisoned");
+            extra.warn_callback = Some(Arc::new(callback));
+            ffi::lua_setwarnf(state, Some(warn_proc), thread as *mut c_void);
+        }
+        Ok(())
+    }
+
+    /// Removes the warning function previously set by `set_warning_function`.
+    ///
+    /// After that, all warnings are discarded.
+    ///
+    /// Requires `feature = "lua54"`
+    #[cfg(any(feature = "lua54", doc))]
+    pub fn remove_warning_function(&self) {
         let state = match self.main_state {
             Some(state) => state,
             None => return,
         };
         let mut extra = mlua_expect!(self.extra.lock(), "extra is poisoned");
+        extra.warn_callback = None;
         unsafe {
-            extra.hook_callback = None;
-            ffi::lua_sethook(state, None, 0, 0);
+            ffi::lua_setwarnf(state, None, ptr::null_mut());
+            let _sg = StackGuard::new(self.state);
+            assert_stack(self.state, 1);
+            ffi::lua_pushnil(self.state);
+            let key = &WARN_THREAD_REGISTRY_KEY as *const u8 as *mut c_void;
+            ffi::lua_rawsetp(self.state, ffi::LUA_REGISTRYINDEX, key);
         }
     }
 
+    /// Emits a warning with the given message.
+    ///
+    /// A message split into several parts should be emitted with `to_continue` set to `true` for
+    /// all parts except the last one. This is the same as the `warn` function in Lua code.
+    ///
+    /// Requires `feature = "lua54"`
+    #[cfg(any(feature = "lua54", doc))]
+    pub fn warning<S: ?Sized + AsRef<[u8]>>(&self, msg: &S, to_continue: bool) -> Result<()> {
+        let msg = CString::new(msg.as_ref().to_vec()).map_err(|e| Error::ToLuaConversionError {
+            from: "&str",
+            to: "string",
+            message: Some(e.to_string()),
+        })?;
+        unsafe { ffi::lua_warning(self.state, msg.as_ptr(), to_continue as c_int) };
+        self.resume_warn_panic();
+        Ok(())
+    }
+
     /// Returns the amount of memory (in bytes) currently used inside this Lua state.
     pub fn used_memory(&self) -> usize {
         let extra = mlua_expect!(self.extra.lock(), "extra is poisoned");
-        let state = self.main_state.unwrap_or(self.state);
-        if extra.mem_info.is_null() {
+        if extra.mem_info.is_null() || cfg!(feature = "luajit") {
             // Get data from the Lua GC
-            unsafe {
-                let used_kbytes = ffi::lua_gc(state, ffi::LUA_GCCOUNT, 0);
-                let used_kbytes_rem = ffi::lua_gc(state, ffi::LUA_GCCOUNTB, 0);
-                return (used_kbytes as usize) * 1024 + (used_kbytes_rem as usize);
-            }
+            return unsafe { gc_used_memory(self.main_state.unwrap_or(self.state)) };
         }
         unsafe { (*extra.mem_info).used_memory as usize }
     }
@@ -500,17 +1421,128 @@ impl Lua {
     ///
     /// Does not work on module mode where Lua state is managed externally.
     ///
-    /// Requires `feature = "lua54/lua53/lua52"`
-    #[cfg(any(feature = "lua54", feature = "lua53", feature = "lua52", doc))]
+    /// In LuaJIT the limit is soft: memory usage is checked after each garbage collection cycle,
+    /// and once the limit is exceeded the running Lua code is stopped with `Error::MemoryError`
+    /// (wrapped into `Error::CallbackError`) as soon as possible. JIT-compiled code is not
+    /// interrupted.
     pub fn set_memory_limit(&self, memory_limit: usize) -> Result<usize> {
-        let mut extra = mlua_expect!(self.extra.lock(), "extra is poisoned");
+        let mem_info = {
+            let extra = mlua_expect!(self.extra.lock(), "extra is poisoned");
+            if extra.mem_info.is_null() {
+                return Err(Error::MemoryLimitNotAvailable);
+            }
+            extra.mem_info
+        };
+        unsafe {
+            let prev_limit = (*mem_info).memory_limit as usize;
+            (*mem_info).memory_limit = memory_limit as isize;
+            #[cfg(feature = "luajit")]
+            {
+                if memory_limit > 0 && !(*mem_info).gc_check_armed {
+                    let _sg = StackGuard::new(self.state);
+                    assert_stack(self.state, 3);
+                    protect_lua_closure(self.state, 0, 0, |state| {
+                        push_gc_check(state, mem_info);
+                    })?;
+                    (*mem_info).gc_check_armed = true;
+                }
+            }
+            Ok(prev_limit)
+        }
+    }
+
+    /// Starts collecting memory statistics of this Lua state.
+    ///
+    /// The allocator counts allocations, reallocations and deallocations, tracks the peak memory
+    /// usage and builds a histogram of the requested block sizes. The collected statistics can be
+    /// retrieved with [`memory_stats`].
+    ///
+    /// If `sample_interval` is set, the running Lua function is sampled every `sample_interval` VM
+    /// instructions using the debug API, and the allocations made since the previous sample are
+    /// attributed to it. This is an approximation: the more often the function is sampled, the
+    /// more precise the attribution, but the slower the code execution.
+    ///
+    /// Calling this function again resets the statistics.
+    ///
+    /// Returns [`Error::MemoryStatsNotAvailable`] in LuaJIT or if the Lua state was not created by
+    /// us.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use mlua::{Lua, Result};
+    /// # fn main() -> Result<()> {
+    /// # #[cfg(not(feature = "luajit"))]
+    /// # {
+    /// let lua = Lua::new();
+    /// lua.enable_memory_stats(Some(100))?;
+    ///
+    /// lua.load(
+    ///     r#"
+    ///     function fill()
+    ///         local t = {}
+    ///         for i = 1, 1000 do t[i] = {} end
+    ///     end
+    ///     fill()
+    /// "#,
+    /// )
+    /// .exec()?;
+    ///
+    /// let stats = lua.memory_stats().unwrap();
+    /// assert!(stats.allocations >= 1000);
+    /// assert_eq!(stats.functions[0].name.as_deref(), Some("fill"));
+    /// # }
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`memory_stats`]: #method.memory_stats
+    /// [`Error::MemoryStatsNotAvailable`]: enum.Error.html#variant.MemoryStatsNotAvailable
+    pub fn enable_memory_stats(&self, sample_interval: Option<u32>) -> Result<()> {
+        self.main_state.ok_or(Error::MainThreadNotAvailable)?;
+        {
+            let mut extra = mlua_expect!(self.extra.lock(), "extra is poisoned");
+            if extra.mem_info.is_null() || cfg!(feature = "luajit") {
+                return Err(Error::MemoryStatsNotAvailable);
+            }
+            unsafe {
+                let used_memory = (*extra.mem_info).used_memory;
+                let stats = AllocStats::new(used_memory, sample_interval);
+                (*extra.mem_info).stats = Some(Box::new(stats));
+            }
+        }
+        unsafe { self.update_hooks() };
+        Ok(())
+    }
+
+    /// Stops collecting memory statistics and discards the collected ones.
+    pub fn disable_memory_stats(&self) {
+        if self.main_state.is_none() {
+            return;
+        }
+        {
+            let mut extra = mlua_expect!(self.extra.lock(), "extra is poisoned");
+            if extra.mem_info.is_null() {
+                return;
+            }
+            unsafe { (*extra.mem_info).stats = None };
+        }
+        unsafe { self.update_hooks() };
+    }
+
+    /// Returns a snapshot of the memory statistics collected since [`enable_memory_stats`].
+    ///
+    /// Returns `None` if collecting memory statistics is not enabled.
+    ///
+    /// [`enable_memory_stats`]: #method.enable_memory_stats
+    pub fn memory_stats(&self) -> Option<MemoryStats> {
+        let extra = mlua_expect!(self.extra.lock(), "extra is poisoned");
         if extra.mem_info.is_null() {
-            return Err(Error::MemoryLimitNotAvailable);
+            return None;
         }
         unsafe {
-            let prev_limit = (*extra.mem_info).memory_limit as usize;
-            (*extra.mem_info).memory_limit = memory_limit as isize;
-            Ok(prev_limit)
+            let mem_info = &*extra.mem_info;
+            Some(mem_info.stats.as_ref()?.snapshot(mem_info.used_memory))
         }
     }
 
@@ -684,6 +1716,11 @@ impl Lua {
         env: Option<Value<'lua>>,
         mode: Option<ChunkMode>,
     ) -> Result<Function<'lua>> {
+        let env = match env {
+            Some(env) => Some(env),
+            None => self.sandbox_env()?.map(Value::Table),
+        };
+
         unsafe {
             let _sg = StackGuard::new(self.state);
             assert_stack(self.state, 1);
@@ -743,14 +1780,23 @@ impl Lua {
 
     /// Creates and returns a new table.
     pub fn create_table(&self) -> Result<Table> {
+        self.create_table_with_capacity(0, 0)
+    }
+
+    /// Creates and returns a new empty table, with preallocated space for `narr` sequence elements
+    /// and `nrec` other elements.
+    ///
+    /// This avoids reallocations when the number of elements the table will have is known.
+    /// Capacities larger than `c_int::MAX` are clamped.
+    pub fn create_table_with_capacity(&self, narr: usize, nrec: usize) -> Result<Table<'_>> {
+        let narr = cmp::min(narr, c_int::MAX as usize) as c_int;
+        let nrec = cmp::min(nrec, c_int::MAX as usize) as c_int;
         unsafe {
             let _sg = StackGuard::new(self.state);
             assert_stack(self.state, 3);
-            unsafe extern "C" fn new_table(state: *mut ffi::lua_State) -> c_int {
-                ffi::lua_newtable(state);
-                1
-            }
-            protect_lua(self.state, 0, new_table)?;
+            protect_lua_closure(self.state, 0, 1, |state| {
+                ffi::lua_createtable(state, narr, nrec)
+            })?;
             Ok(Table(self.pop_ref()))
         }
     }
@@ -849,7 +1895,7 @@ impl Lua {
         F: 'static + MaybeSend + Fn(&'callback Lua, A) -> Result<R>,
     {
         self.create_callback(Box::new(move |lua, args| {
-            func(lua, A::from_lua_multi(args, lua)?)?.to_lua_multi(lua)
+            func(lua, lua.callback_args(args, 1)?)?.to_lua_multi(lua)
         }))
     }
 
@@ -930,7 +1976,7 @@ impl Lua {
         FR: 'lua + Future<Output = Result<R>>,
     {
         self.create_async_callback(Box::new(move |lua, args| {
-            let args = match A::from_lua_multi(args, lua) {
+            let args = match lua.async_callback_args(args, 1) {
                 Ok(args) => args,
                 Err(e) => return Box::pin(future::err(e)),
             };
@@ -963,6 +2009,28 @@ impl Lua {
         unsafe { self.make_userdata(data) }
     }
 
+    /// Allows userdata of type `T` created in this Lua state to be copied by [`copy_value`].
+    ///
+    /// The copy is a new userdata holding a clone of the Rust value. User values are not copied.
+    ///
+    /// [`copy_value`]: #method.copy_value
+    pub fn register_cloneable_userdata<T>(&self)
+    where
+        T: 'static + MaybeSend + UserData + Clone,
+    {
+        fn clone_userdata<'lua, T>(ud: &AnyUserData, lua: &'lua Lua) -> Result<AnyUserData<'lua>>
+        where
+            T: 'static + MaybeSend + UserData + Clone,
+        {
+            let data = ud.borrow::<T>()?.clone();
+            lua.create_userdata(data)
+        }
+
+        mlua_expect!(self.extra.lock(), "extra is poisoned")
+            .userdata_cloners
+            .insert(TypeId::of::<T>(), clone_userdata::<T>);
+    }
+
     /// Returns a handle to the global environment.
     pub fn globals(&self) -> Table {
         unsafe {
@@ -985,6 +2053,77 @@ impl Lua {
         }
     }
 
+    /// Makes a deep copy of a value, which may belong to another Lua state, in this Lua state.
+    ///
+    /// Nil, booleans, numbers, light userdata and errors are copied as is, strings are copied byte
+    /// by byte. Tables are copied recursively together with their metatables, preserving cycles
+    /// and shared references: a table reachable by several paths is copied only once.
+    ///
+    /// Lua functions are copied through their bytecode and must not have upvalues, except `_ENV`
+    /// set to the global environment of the source state, which is bound to the global environment
+    /// of this state (as well as the environment of functions in Lua 5.1 and LuaJIT). Userdata can
+    /// only be copied if its type is registered with [`register_cloneable_userdata`] in the source
+    /// state.
+    ///
+    /// Returns [`Error::CopyError`] if the value contains anything that cannot be copied, such as
+    /// Rust functions, coroutines or userdata of other types.
+    ///    /// Returns [`Error::SafetyError`] if a function is copied from a state created with
+    /// [`unsafe_new`] into a safe one, as the bytecode is not checked.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mlua::{Lua, Result, Table};
+    /// # fn main() -> Result<()> {
+    /// let lua1 = Lua::new();
+    /// let lua2 = Lua::new();
+    ///
+    /// let config = lua1.load(r#"
+    ///     local config = { name = "tenant1", limits = { 10, 20 } }
+    ///     config.self = config
+    ///     return config
+    /// "#).eval()?;
+    ///
+    /// let config: Table = lua2.unpack(lua2.copy_value(config)?)?;
+    /// assert_eq!(config.get::<_, String>("name")?, "tenant1");
+    /// assert!(config.get::<_, Table>("self")?.equals(&config)?);
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`register_cloneable_userdata`]: #method.register_cloneable_userdata
+    /// [`Error::CopyError`]: enum.Error.html#variant.CopyError
+    /// [`Error::SafetyError`]: enum.Error.html#variant.SafetyError
+    /// [`unsafe_new`]: #method.unsafe_new
+    pub fn copy_value<'lua>(&'lua self, value: Value) -> Result<Value<'lua>> {
+        let mut copies = HashMap::new();
+        let mut tables = Vec::new();
+        let mut metatables = Vec::new();
+
+        // Tables are filled iteratively to not overflow the stack on deeply nested tables
+        let copy = self.copy_value_shallow(value, &mut copies, &mut tables)?;
+        while let Some((src, dst)) = tables.pop() {
+            for pair in src.clone().pairs::<Value, Value>() {
+                let (key, value) = pair?;
+                let key = self.copy_value_shallow(key, &mut copies, &mut tables)?;
+                let value = self.copy_value_shallow(value, &mut copies, &mut tables)?;
+                dst.raw_set(key, value)?;
+            }
+            if let Some(mt) = src.get_metatable() {
+                match self.copy_value_shallow(Value::Table(mt), &mut copies, &mut tables)? {
+                    Value::Table(mt) => metatables.push((dst, mt)),
+                    _ => unreachable!(),
+                }
+            }
+        }
+        // Metatables are set once filled, for finalizers to be marked
+        for (table, mt) in metatables {
+            table.set_metatable(Some(mt));
+        }
+
+        Ok(copy)
+    }
+
     /// Calls the given function with a `Scope` parameter, giving the function the ability to create
     /// userdata and callbacks from rust types that are !Send or non-'static.
     ///
@@ -1297,6 +2436,81 @@ impl Lua {
         }
     }
 
+    /// Sets or replaces an application data object of type `T`.
+    ///
+    /// Application data can be accessed at any time (for example, from Rust callbacks) by using
+    /// [`app_data_ref`] or [`app_data_mut`] methods where `T` is the data type.
+    /// Returns the previously stored object of type `T`, if any.
+    ///
+    /// # Panics
+    ///
+    /// Panics if any of the app data objects is currently borrowed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mlua::{Lua, Result};
+    /// # fn main() -> Result<()> {
+    /// let lua = Lua::new();
+    /// lua.set_app_data(Vec::<String>::new());
+    ///
+    /// let log = lua.create_function(|lua, msg: String| {
+    ///     lua.app_data_mut::<Vec<String>>().unwrap().push(msg);
+    ///     Ok(())
+    /// })?;
+    /// log.call::<_, ()>("hello")?;
+    ///
+    /// assert_eq!(*lua.app_data_ref::<Vec<String>>().unwrap(), vec!["hello"]);
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`app_data_ref`]: #method.app_data_ref
+    /// [`app_data_mut`]: #method.app_data_mut
+    pub fn set_app_data<T: 'static + MaybeSend>(&self, data: T) -> Option<T> {
+        self.app_data().insert(data)
+    }
+
+    /// Gets a reference to an application data object stored by [`set_app_data`] of type `T`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the data object of type `T` is currently mutably borrowed. Multiple immutable
+    /// reads can be taken out at the same time.
+    ///
+    /// [`set_app_data`]: #method.set_app_data
+    pub fn app_data_ref<T: 'static>(&self) -> Option<AppDataRef<'_, T>> {
+        self.app_data().borrow()
+    }
+
+    /// Gets a mutable reference to an application data object stored by [`set_app_data`] of
+    /// type `T`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the data object of type `T` is currently borrowed.
+    ///
+    /// [`set_app_data`]: #method.set_app_data
+    pub fn app_data_mut<T: 'static>(&self) -> Option<AppDataRefMut<'_, T>> {
+        self.app_data().borrow_mut()
+    }
+
+    /// Removes an application data of type `T` and returns it.
+    ///
+    /// # Panics
+    ///
+    /// Panics if any of the app data objects is currently borrowed.
+    pub fn remove_app_data<T: 'static>(&self) -> Option<T> {
+        self.app_data().remove()
+    }
+
+    // The app data container is never replaced and lives as long as `ExtraData`, which is kept
+    // alive by `self`, so it's safe to access it without holding the `extra` lock.
+    fn app_data(&self) -> &AppData {
+        let extra = mlua_expect!(self.extra.lock(), "extra is poisoned");
+        unsafe { &*(&*extra.app_data as *const AppData) }
+    }
+
     // Uses 2 stack spaces, does not call checkstack
     pub(crate) unsafe fn push_value(&self, value: Value) -> Result<()> {
         match value {
@@ -1431,6 +2645,14 @@ impl Lua {
         LuaRef { lua: self, index }
     }
 
+    // Pops the topmost element of the stack and stores it in place of the value referenced by
+    // `lref`, reusing its slot.
+    pub(crate) unsafe fn replace_ref(&self, lref: &LuaRef) {
+        let extra = mlua_expect!(self.extra.lock(), "extra is poisoned");
+        ffi::lua_xmove(self.state, extra.ref_thread, 1);
+        ffi::lua_replace(extra.ref_thread, lref.index);
+    }
+
     pub(crate) fn clone_ref<'lua>(&'lua self, lref: &LuaRef<'lua>) -> LuaRef<'lua> {
         unsafe {
             let mut extra = mlua_expect!(self.extra.lock(), "extra is poisoned");
@@ -1449,6 +2671,82 @@ impl Lua {
         }
     }
 
+    // Stores the referenced value in the registry and returns an owned reference to it, which
+    // keeps the main state alive.
+    #[cfg(not(feature = "send"))]
+    pub(crate) fn make_owned_ref(&self, lref: &LuaRef) -> Result<OwnedRef> {
+        unsafe {
+            let _sg = StackGuard::new(self.state);
+            assert_stack(self.state, 4);
+            self.push_ref(lref);
+            let registry_id = protect_lua_closure(self.state, 1, 0, |state| {
+                ffi::luaL_ref(state, ffi::LUA_REGISTRYINDEX)
+            })?;
+            Ok(OwnedRef {
+                lua: self.to_owner(),
+                registry_id,
+                _no_send: PhantomData,
+            })
+        }
+    }
+
+    #[cfg(not(feature = "send"))]
+    pub(crate) unsafe fn push_owned_ref(&self, oref: &OwnedRef) {
+        assert!(
+            Arc::ptr_eq(&oref.lua.extra, &self.extra),
+            "Lua instance passed Value created from a different main Lua state"
+        );
+        ffi::lua_rawgeti(
+            self.state,
+            ffi::LUA_REGISTRYINDEX,
+            oref.registry_id as ffi::lua_Integer,
+        );
+    }
+
+    #[cfg(not(feature = "send"))]
+    pub(crate) fn drop_owned_ref(&self, registry_id: c_int) {
+        // Without an owner the state can be closed at any moment, so the reference is released
+        // later, the same way as for `RegistryKey`
+        if self.owner.is_some() {
+            unsafe {
+                let _sg = StackGuard::new(self.state);
+                if check_stack(self.state, 3).is_ok()
+                    && protect_lua_closure(self.state, 0, 0, |state| {
+                        ffi::luaL_unref(state, ffi::LUA_REGISTRYINDEX, registry_id);
+                    })
+                    .is_ok()
+                {
+                    return;
+                }
+            }
+        }
+        let extra = mlua_expect!(self.extra.lock(), "extra is poisoned");
+        let mut unref_list = mlua_expect!(extra.registry_unref_list.lock(), "unref list poisoned");
+        if let Some(list) = unref_list.as_mut() {
+            list.push(registry_id);
+        }
+    }
+
+    // Returns a new `Lua` instance sharing the main state, which keeps it alive if the state was
+    // created by us.
+    #[cfg(not(feature = "send"))]
+    pub(crate) fn to_owner(&self) -> Lua {
+        let owner = match self.owner {
+            Some(ref owner) => Some(owner.clone()),
+            None => mlua_expect!(self.extra.lock(), "extra is poisoned")
+                .owner
+                .upgrade(),
+        };
+        Lua {
+            state: self.main_state.unwrap_or(self.state),
+            main_state: self.main_state,
+            extra: self.extra.clone(),
+            owner,
+            safe: self.safe,
+            _no_ref_unwind_safe: PhantomData,
+        }
+    }
+
     pub(crate) unsafe fn userdata_metatable<T: 'static + UserData>(&self) -> Result<c_int> {
         if let Some(table_id) = mlua_expect!(self.extra.lock(), "extra is poisoned")
             .registered_userdata
@@ -1458,14 +2756,17 @@ impl Lua {
         }
 
         let _sg = StackGuard::new(self.state);
-        assert_stack(self.state, 8);
+        assert_stack(self.state, 11);
 
+        let mut fields = StaticUserDataFields::default();
         let mut methods = StaticUserDataMethods::default();
+        T::add_fields(&mut fields);
         T::add_methods(&mut methods);
 
         protect_lua_closure(self.state, 0, 1, |state| {
             ffi::lua_newtable(state);
         })?;
+        let metatable_index = ffi::lua_absindex(self.state, -1);
         for (k, m) in methods.meta_methods {
             push_string(self.state, k.name())?;
             self.push_value(Value::Function(self.create_callback(m)?))?;
@@ -1475,13 +2776,49 @@ impl Lua {
             })?;
         }
 
+        let mut extra_tables_count = 0;
+
+        let field_getters_index = if fields.field_getters.is_empty() {
+            None
+        } else {
+            protect_lua_closure(self.state, 0, 1, |state| {
+                ffi::lua_newtable(state);
+            })?;
+            for (k, m) in fields.field_getters {
+                push_string(self.state, &k)?;
+                self.push_value(Value::Function(self.create_callback(m)?))?;
+                protect_lua_closure(self.state, 3, 1, |state| {
+                    ffi::lua_rawset(state, -3);
+                })?;
+            }
+            extra_tables_count += 1;
+            Some(ffi::lua_absindex(self.state, -1))
+        };
+
+        let field_setters_index = if fields.field_setters.is_empty() {
+            None
+        } else {
+            protect_lua_closure(self.state, 0, 1, |state| {
+                ffi::lua_newtable(state);
+            })?;
+            for (k, m) in fields.field_setters {
+                push_string(self.state, &k)?;
+                self.push_value(Value::Function(self.create_callback(m)?))?;
+                protect_lua_closure(self.state, 3, 1, |state| {
+                    ffi::lua_rawset(state, -3);
+                })?;
+            }
+            extra_tables_count += 1;
+            Some(ffi::lua_absindex(self.state, -1))
+        };
+
         #[cfg(feature = "async")]
         let no_methods = methods.methods.is_empty() && methods.async_methods.is_empty();
         #[cfg(not(feature = "async"))]
         let no_methods = methods.methods.is_empty();
 
-        if no_methods {
-            init_userdata_metatable::<RefCell<T>>(self.state, -1, None)?;
+        let methods_index = if no_methods {
+            None
         } else {
             protect_lua_closure(self.state, 0, 1, |state| {
                 ffi::lua_newtable(state);
@@ -1501,10 +2838,18 @@ impl Lua {
                     ffi::lua_rawset(state, -3);
                 })?;
             }
+            extra_tables_count += 1;
+            Some(ffi::lua_absindex(self.state, -1))
+        };
 
-            init_userdata_metatable::<RefCell<T>>(self.state, -2, Some(-1))?;
-            ffi::lua_pop(self.state, 1);
-        }
+        init_userdata_metatable::<RefCell<T>>(
+            self.state,
+            metatable_index,
+            field_getters_index,
+            field_setters_index,
+            methods_index,
+        )?;
+        ffi::lua_pop(self.state, extra_tables_count);
 
         let id = protect_lua_closure(self.state, 1, 0, |state| {
             ffi::luaL_ref(state, ffi::LUA_REGISTRYINDEX)
@@ -1571,7 +2916,7 @@ impl Lua {
             assert_stack(self.state, 6);
 
             push_meta_gc_userdata::<Callback, _>(self.state, func)?;
-            push_gc_userdata(self.state, self.clone())?;
+            push_gc_userdata(self.state, self.clone_ephemeral())?;
 
             protect_lua_closure(self.state, 2, 1, |state| {
                 ffi::lua_pushcclosure(state, call_callback, 2);
@@ -1581,6 +2926,60 @@ impl Lua {
         }
     }
 
+    // Converts the arguments of a running Rust callback, starting from position `pos`.
+    // Conversion errors are reported with the name of the callback taken from debug information,
+    // the same way as in `luaL_argerror`.
+    pub(crate) fn callback_args<'a, A: FromLuaMulti<'a>>(
+        &'a self,
+        args: MultiValue<'a>,
+        pos: usize,
+    ) -> Result<A> {
+        self.callback_args_at(args, pos, 0)
+    }
+
+    // Same as `callback_args`, but for async callbacks, which are called from the Lua function
+    // polling the future (one level above).
+    #[cfg(feature = "async")]
+    pub(crate) fn async_callback_args<'a, A: FromLuaMulti<'a>>(
+        &'a self,
+        args: MultiValue<'a>,
+        pos: usize,
+    ) -> Result<A> {
+        self.callback_args_at(args, pos, 1)
+    }
+
+    fn callback_args_at<'a, A: FromLuaMulti<'a>>(
+        &'a self,
+        args: MultiValue<'a>,
+        pos: usize,
+        level: c_int,
+    ) -> Result<A> {
+        A::from_lua_args(args, pos, self).map_err(|err| match err {
+            Error::BadArgument {
+                mut pos,
+                name: None,
+                cause,
+            } => unsafe {
+                let mut name = None;
+                let mut ar: ffi::lua_Debug = mem::zeroed();
+                if ffi::lua_getstack(self.state, level, &mut ar) != 0
+                    && ffi::lua_getinfo(self.state, cstr!("n"), &mut ar) != 0
+                {
+                    if !ar.namewhat.is_null() && CStr::from_ptr(ar.namewhat).to_bytes() == b"method"
+                    {
+                        // Do not count `self`
+                        pos = pos.saturating_sub(1).max(1);
+                    }
+                    if !ar.name.is_null() {
+                        name = Some(CStr::from_ptr(ar.name).to_string_lossy().into_owned());
+                    }
+                }
+                Error::BadArgument { pos, name, cause }
+            },
+            err => err,
+        })
+    }
+
     #[cfg(feature = "async")]
     pub(crate) fn create_async_callback<'lua, 'callback>(
         &'lua self,
@@ -1618,7 +3017,7 @@ impl Lua {
 
                 let fut = (*func)(lua, args);
                 push_gc_userdata(state, fut)?;
-                push_gc_userdata(state, lua.clone())?;
+                push_gc_userdata(state, lua.clone_ephemeral())?;
 
                 ffi::lua_pushcclosure(state, poll_future, 2);
 
@@ -1677,7 +3076,7 @@ impl Lua {
             assert_stack(self.state, 6);
 
             push_meta_gc_userdata::<AsyncCallback, _>(self.state, func)?;
-            push_gc_userdata(self.state, self.clone())?;
+            push_gc_userdata(self.state, self.clone_ephemeral())?;
 
             protect_lua_closure(self.state, 2, 1, |state| {
                 ffi::lua_pushcclosure(state, call_callback, 2);
@@ -1737,71 +3136,447 @@ impl Lua {
         Ok(AnyUserData(self.pop_ref()))
     }
 
-    pub(crate) fn clone(&self) -> Self {
+    // Returns a `Lua` instance that does not keep the state alive, as it is stored in the state
+    // itself (for example, as an upvalue of a callback).
+    pub(crate) fn clone_ephemeral(&self) -> Self {
         Lua {
             state: self.state,
             main_state: self.main_state,
             extra: self.extra.clone(),
-            ephemeral: true,
+            owner: None,
             safe: self.safe,
             _no_ref_unwind_safe: PhantomData,
         }
     }
 
-    fn disable_c_modules(&self) -> Result<()> {
-        let package: Table = self.globals().get("package")?;
+    fn disable_c_modules(&self) -> Result<()> {
+        let package: Table = self.globals().get("package")?;
+
+        package.set(
+            "loadlib",
+            self.create_function(|_, ()| -> Result<()> {
+                Err(Error::SafetyError(
+                    "package.loadlib is disabled in safe mode".to_string(),
+                ))
+            })?,
+        )?;
+
+        #[cfg(any(feature = "lua54", feature = "lua53", feature = "lua52"))]
+        let searchers: Table = package.get("searchers")?;
+        #[cfg(any(feature = "lua51", feature = "luajit"))]
+        let searchers: Table = package.get("loaders")?;
+
+        let loader = self.create_function(|_, ()| Ok("\n\tcan't load C modules in safe mode"))?;
+
+        // The third and fourth searchers looks for a loader as a C library
+        searchers.raw_set(3, loader.clone())?;
+        searchers.raw_remove(4)?;
+
+        Ok(())
+    }
+
+    pub(crate) unsafe fn make_from_ptr(state: *mut ffi::lua_State) -> Self {
+        let _sg = StackGuard::new(state);
+        assert_stack(state, 3);
+
+        ffi::lua_rawgetp(
+            state,
+            ffi::LUA_REGISTRYINDEX,
+            &EXTRA_REGISTRY_KEY as *const u8 as *mut c_void,
+        );
+        let extra = mlua_expect!(
+            (*get_gc_userdata::<Weak<Mutex<ExtraData>>>(state, -1)).upgrade(),
+            "extra is destroyed"
+        );
+        ffi::lua_pop(state, 1);
+
+        Lua {
+            state,
+            main_state: get_main_state(state),
+            extra,
+            owner: None,
+            safe: true, // TODO: Inherit the attribute
+            _no_ref_unwind_safe: PhantomData,
+        }
+    }
+
+    // Creates a new environment for a chunk loaded in the sandbox mode.
+    // Returns `None` if the sandbox mode is disabled.
+    fn sandbox_env(&self) -> Result<Option<Table>> {
+        let env_mt = match mlua_expect!(self.extra.lock(), "extra is poisoned").sandbox_env_mt {
+            Some(id) => id,
+            None => return Ok(None),
+        };
+
+        unsafe {
+            let _sg = StackGuard::new(self.state);
+            assert_stack(self.state, 2);
+
+            protect_lua_closure(self.state, 0, 1, |state| {
+                ffi::lua_newtable(state);
+                ffi::lua_rawgeti(state, ffi::LUA_REGISTRYINDEX, env_mt as ffi::lua_Integer);
+                ffi::lua_setmetatable(state, -2);
+            })?;
+            Ok(Some(Table(self.pop_ref())))
+        }
+    }
+
+    // Copies a value to this state, deferring the copy of the contents of tables.
+    fn copy_value_shallow<'lua, 'a>(
+        &'lua self,
+        value: Value<'a>,
+        copies: &mut HashMap<*const c_void, Value<'lua>>,
+        tables: &mut Vec<(Table<'a>, Table<'lua>)>,
+    ) -> Result<Value<'lua>> {
+        let ptr = match value {
+            Value::Nil => return Ok(Value::Nil),
+            Value::Boolean(b) => return Ok(Value::Boolean(b)),
+            Value::LightUserData(ud) => return Ok(Value::LightUserData(ud)),
+            Value::Integer(i) => return Ok(Value::Integer(i)),
+            Value::Number(n) => return Ok(Value::Number(n)),
+            Value::String(s) => return Ok(Value::String(self.create_string(s.as_bytes())?)),
+            Value::Error(err) => return Ok(Value::Error(err)),
+            Value::Thread(_) => {
+                return Err(Error::CopyError {
+                    from: value.type_name(),
+                    message: None,
+                })
+            }
+            Value::Table(Table(ref lref))
+            | Value::Function(Function(ref lref))
+            | Value::UserData(AnyUserData(ref lref)) => lref.to_pointer(),
+        };
+        if let Some(copy) = copies.get(&ptr) {
+            return Ok(copy.clone());
+        }
+
+        let copy = match value {
+            Value::Table(table) => {
+                let copy = self.create_table()?;
+                tables.push((table, copy.clone()));
+                Value::Table(copy)
+            }
+            Value::Function(func) => Value::Function(self.copy_function(&func)?),
+            Value::UserData(ud) => Value::UserData(self.copy_userdata(&ud)?),
+            _ => unreachable!(),
+        };
+        copies.insert(ptr, copy.clone());
+        Ok(copy)
+    }
+
+    fn copy_function<'lua>(&'lua self, func: &Function) -> Result<Function<'lua>> {
+        let copy_error = |message: &str| Error::CopyError {
+            from: "function",
+            message: Some(message.to_string()),
+        };
+
+        let src = func.0.lua;
+        // Functions are copied as binary chunks, which an unsafe state may have loaded as is
+        if self.safe && !src.safe {
+            return Err(Error::SafetyError(
+                "cannot copy functions from an unsafe state in safe mode".to_string(),
+            ));
+        }
+        let globals = src.globals();
+        let has_env = unsafe {
+            let _sg = StackGuard::new(src.state);
+            assert_stack(src.state, 3);
+
+            src.push_ref(&func.0);
+            if ffi::lua_iscfunction(src.state, -1) != 0 {
+                return Err(copy_error("Rust and C functions cannot be copied"));
+            }
+            let mut upvalues = 0;
+            while !ffi::lua_getupvalue(src.state, -1, upvalues + 1).is_null() {
+                ffi::lua_pop(src.state, 1);
+                upvalues += 1;
+            }
+            let has_env = match upvalues {
+                // Functions in Lua 5.1 and LuaJIT always have an environment
+                0 => cfg!(any(feature = "lua51", feature = "luajit")),
+                #[cfg(any(feature = "lua54", feature = "lua53", feature = "lua52"))]
+                1 if CStr::from_ptr(ffi::lua_getupvalue(src.state, -1, 1)).to_bytes()
+                    == b"_ENV" =>
+                {
+                    ffi::lua_pop(src.state, 1);
+                    true
+                }
+                _ => return Err(copy_error("functions with upvalues cannot be copied")),
+            };
+            // The environment is replaced in the copy, so it must be the global one
+            if has_env {
+                #[cfg(any(feature = "lua54", feature = "lua53", feature = "lua52"))]
+                ffi::lua_getupvalue(src.state, -1, 1);
+                #[cfg(any(feature = "lua51", feature = "luajit"))]
+                ffi::lua_getfenv(src.state, -1);
+                src.push_ref(&globals.0);
+                if ffi::lua_rawequal(src.state, -1, -2) == 0 {
+                    return Err(copy_error(
+                        "functions with a custom environment cannot be copied",
+                    ));
+                }
+            }
+            has_env
+        };
+        let bytecode = func.dump(false)?;
+        let env = if has_env { self.sandbox_env()? } else { None };
+
+        unsafe {
+            let _sg = StackGuard::new(self.state);
+            assert_stack(self.state, 2);
+
+            match ffi::luaL_loadbufferx(
+                self.state,
+                bytecode.as_ptr() as *const c_char,
+                bytecode.len(),
+                ptr::null(),
+                cstr!("b"),
+            ) {
+                ffi::LUA_OK => {}
+                err => return Err(pop_error(self.state, err)),
+            }
+            if let Some(env) = env {
+                self.push_ref(&env.0);
+                #[cfg(any(feature = "lua54", feature = "lua53", feature = "lua52"))]
+                ffi::lua_setupvalue(self.state, -2, 1);
+                #[cfg(any(feature = "lua51", feature = "luajit"))]
+                ffi::lua_setfenv(self.state, -2);
+            }
+            Ok(Function(self.pop_ref()))
+        }
+    }
+
+    fn copy_userdata<'lua>(&'lua self, ud: &AnyUserData) -> Result<AnyUserData<'lua>> {
+        let cloners = mlua_expect!(ud.0.lua.extra.lock(), "extra is poisoned")
+            .userdata_cloners
+            .values()
+            .copied()
+            .collect::<Vec<_>>();
+        for clone_userdata in cloners {
+            match clone_userdata(ud, self) {
+                Err(Error::UserDataTypeMismatch) => continue,
+                res => return res,
+            }
+        }
+        Err(Error::CopyError {
+            from: "userdata",
+            message: Some("userdata type is not registered as cloneable".to_string()),
+        })
+    }
+
+    // Returns the hooks to call for the given event of the running thread, and whether the
+    // interrupt callback must be called. Updates the hook of the thread for the next events.
+    pub(crate) unsafe fn hook_targets(&self, event: c_int) -> Result<HookTargets> {
+        let _sg = StackGuard::new(self.state);
+        assert_stack(self.state, 6);
+
+        ffi::lua_pushthread(self.state);
+        let thread = &mut *get_thread_hook(self.state, -1, true)?;
+        let hooks = &*get_shared_hooks(self.state);
+
+        // The instruction count of the thread is advanced by the count set with `lua_sethook`
+        let (prev, next) = if event == ffi::LUA_HOOKCOUNT {
+            let prev = thread.instructions;
+            thread.instructions += ffi::lua_gethookcount(self.state) as u64;
+            (prev, thread.instructions)
+        } else {
+            (0, 0)
+        };
+        let triggered = |triggers: &HookTriggers| {
+            if event == ffi::LUA_HOOKCOUNT {
+                count_triggered(triggers.every_nth_instruction, prev, next)
+            } else {
+                triggers.triggered_by(event)
+            }
+        };
+
+        let thread_hook = thread.hook.as_ref().map(|(triggers, hook_cb)| (triggers, hook_cb));
+        let callbacks = (hooks.hooks.iter().map(|(_, triggers, hook_cb)| (triggers, hook_cb)))
+            .chain(thread_hook)
+            .filter(|(triggers, _)| triggered(triggers))
+            .map(|(_, hook_cb)| hook_cb.clone())
+            .collect();
+
+        let mut interrupt = hooks.memory_limit_exceeded();
+        if event == ffi::LUA_HOOKCOUNT {
+            if count_triggered(hooks.memory_sample_interval(), prev, next) {
+                if let Some(stats) = (*hooks.mem_info).stats.as_mut() {
+                    stats.sample(self.state);
+                }
+            }
+            interrupt = interrupt
+                || (hooks.has_interrupt()
+                    && count_triggered(Some(INTERRUPT_INSTRUCTIONS), prev, next));
+        }
+
+        // Lua 5.2/5.3 restart line tracking when a hook is set, so if line events are enabled the
+        // count is updated on line events only to not generate an extra line event after a count
+        // event.
+        let (mask, count) = hook_mask(hooks, thread);
+        let line_events =
+            cfg!(any(feature = "lua53", feature = "lua52")) && mask & ffi::LUA_MASKLINE != 0;
+        let count_event = if line_events {
+            ffi::LUA_HOOKLINE
+        } else {
+            ffi::LUA_HOOKCOUNT
+        };
+        if mask != ffi::lua_gethookmask(self.state)
+            || (event == count_event && count != ffi::lua_gethookcount(self.state) as u32)
+        {
+            set_hook_mask(self.state, mask, count);
+        }
+
+        Ok(HookTargets {
+            callbacks,
+            interrupt,
+        })
+    }
+
+    // Sets or removes the hook of the given thread.
+    // The thread hook is combined with the hooks and the interrupt callback set for all threads.
+    pub(crate) unsafe fn set_thread_hook(
+        &self,
+        thread: &Thread,
+        hook: Option<(HookTriggers, HookCallback)>,
+    ) -> Result<()> {
+        let _sg = StackGuard::new(self.state);
+        assert_stack(self.state, 5);
 
-        package.set(
-            "loadlib",
-            self.create_function(|_, ()| -> Result<()> {
-                Err(Error::SafetyError(
-                    "package.loadlib is disabled in safe mode".to_string(),
-                ))
-            })?,
-        )?;
+        self.push_ref(&thread.0);
+        // Removing a hook does not create a new thread hook state, so cannot fail
+        let thread_hook = get_thread_hook(self.state, -1, hook.is_some())?;
+        if thread_hook.is_null() {
+            return Ok(());
+        }
+        (*thread_hook).hook = hook;
+        self.install_thread_hook(-1);
+        Ok(())
+    }
 
-        #[cfg(any(feature = "lua54", feature = "lua53", feature = "lua52"))]
-        let searchers: Table = package.get("searchers")?;
-        #[cfg(any(feature = "lua51", feature = "luajit"))]
-        let searchers: Table = package.get("loaders")?;
+    // Stores the hooks and the interrupt callback shared by all threads in the registry, and
+    // installs `hook_proc` on the main thread, the running thread and the threads that already
+    // have a hook state with the combined triggers, or removes it if none is set.
+    // Other threads pick up the changes at their next hook event. Threads with no hook installed
+    // get it when resumed by `Thread::resume`, or inherit it from the thread creating them.
+    pub(crate) unsafe fn update_hooks(&self) {
+        let hooks = {
+            let extra = mlua_expect!(self.extra.lock(), "extra is poisoned");
+            SharedHooks {
+                hooks: extra.hooks.clone(),
+                interrupt_callback: extra.interrupt_callback.clone(),
+                execution_deadline: extra.execution_deadline,
+                mem_info: extra.mem_info,
+            }
+        };
 
-        let loader = self.create_function(|_, ()| Ok("\n\tcan't load C modules in safe mode"))?;
+        let _sg = StackGuard::new(self.state);
+        assert_stack(self.state, 6);
 
-        // The third and fourth searchers looks for a loader as a C library
-        searchers.raw_set(3, loader.clone())?;
-        searchers.raw_remove(4)?;
+        *get_shared_hooks(self.state) = hooks;
 
-        Ok(())
-    }
+        if let Some(main_state) = self.main_state {
+            if main_state != self.state && ffi::lua_checkstack(main_state, 1) != 0 {
+                ffi::lua_pushthread(main_state);
+                ffi::lua_xmove(main_state, self.state, 1);
+                self.install_thread_hook(-1);
+                ffi::lua_pop(self.state, 1);
+            }
+        }
 
-    pub(crate) unsafe fn make_from_ptr(state: *mut ffi::lua_State) -> Self {
-        let _sg = StackGuard::new(state);
-        assert_stack(state, 3);
+        ffi::lua_pushthread(self.state);
+        self.install_thread_hook(-1);
+        ffi::lua_pop(self.state, 1);
 
         ffi::lua_rawgetp(
-            state,
+            self.state,
             ffi::LUA_REGISTRYINDEX,
-            &EXTRA_REGISTRY_KEY as *const u8 as *mut c_void,
-        );
-        let extra = mlua_expect!(
-            (*get_gc_userdata::<Weak<Mutex<ExtraData>>>(state, -1)).upgrade(),
-            "extra is destroyed"
+            &THREAD_HOOKS_REGISTRY_KEY as *const u8 as *mut c_void,
         );
-        ffi::lua_pop(state, 1);
+        ffi::lua_pushnil(self.state);
+        while ffi::lua_next(self.state, -2) != 0 {
+            ffi::lua_pop(self.state, 1);
+            self.install_thread_hook(-1);
+        }
+    }
 
-        Lua {
-            state,
-            main_state: get_main_state(state),
-            extra,
-            ephemeral: true,
-            safe: true, // TODO: Inherit the attribute
-            _no_ref_unwind_safe: PhantomData,
+    // Installs `hook_proc` on the thread at the given index with the combined triggers of the
+    // hooks shared by all threads and the hook set for this thread with `Thread::set_hook`,
+    // or removes it if none is set.
+    pub(crate) unsafe fn install_thread_hook(&self, index: c_int) {
+        let _sg = StackGuard::new(self.state);
+        assert_stack(self.state, 4);
+
+        let thread_state = ffi::lua_tothread(self.state, index);
+        let no_thread_hook = ThreadHook {
+            hook: None,
+            instructions: 0,
+        };
+        // Looking up an existing thread hook state cannot fail
+        let thread = match get_thread_hook(self.state, index, false) {
+            Ok(thread) if !thread.is_null() => &*thread,
+            _ => &no_thread_hook,
+        };
+        let (mask, count) = hook_mask(&*get_shared_hooks(self.state), thread);
+        set_hook_mask(thread_state, mask, count);
+    }
+
+    // Resumes the panic of the warning function (if any) once Lua has returned control to Rust.
+    pub(crate) fn resume_warn_panic(&self) {
+        #[cfg(feature = "lua54")]
+        {
+            let panic = mlua_expect!(self.extra.lock(), "extra is poisoned")
+                .warn_panic
+                .take();
+            if let Some(p) = panic {
+                resume_unwind(p);
+            }
         }
     }
 
-    pub(crate) unsafe fn hook_callback(&self) -> Option<HookCallback> {
-        let extra = mlua_expect!(self.extra.lock(), "extra is poisoned");
-        extra.hook_callback.clone()
+    // Checks the execution deadline and calls the interrupt callback.
+    // Returns `true` if the running thread should yield.
+    pub(crate) fn interrupt(&self) -> Result<bool> {
+        #[cfg(feature = "luajit")]
+        unsafe {
+            let mem_info = (*get_shared_hooks(self.state)).mem_info;
+            if !mem_info.is_null() && (*mem_info).limit_exceeded {
+                (*mem_info).limit_exceeded = false;
+                self.update_hooks();
+                let memory_limit = (*mem_info).memory_limit;
+                if memory_limit > 0 && gc_used_memory(self.state) > memory_limit as usize {
+                    return Err(Error::MemoryError("not enough memory".to_string()));
+                }
+            }
+        }
+
+        let (deadline, interrupt_cb) = unsafe {
+            let _sg = StackGuard::new(self.state);
+            assert_stack(self.state, 1);
+            let hooks = &*get_shared_hooks(self.state);
+            (hooks.execution_deadline, hooks.interrupt_callback.clone())
+        };
+
+        if let Some(deadline) = deadline {
+            if Instant::now() >= deadline {
+                return Err(Error::DeadlineExceeded);
+            }
+        }
+
+        let interrupt_cb = match interrupt_cb {
+            Some(interrupt_cb) => interrupt_cb,
+            None => return Ok(false),
+        };
+        #[allow(clippy::match_wild_err_arm)]
+        let action = match interrupt_cb.try_borrow_mut() {
+            Ok(mut b) => (*b)(self),
+            Err(_) => mlua_panic!("Lua should not allow hooks to be called within another hook"),
+        };
+        match action {
+            InterruptAction::Continue => Ok(false),
+            #[cfg(any(feature = "lua54", feature = "lua53"))]
+            InterruptAction::Yield => Ok(true),
+            InterruptAction::Error(err) => Err(err),
+        }
     }
 }
 
@@ -1981,6 +3756,814 @@ impl<'lua, 'a> Chunk<'lua, 'a> {
     }
 }
 
+// Calls the warning function set by `Lua::set_warning_function`. `ud` is the thread dedicated to
+// the warning function.
+#[cfg(feature = "lua54")]
+unsafe extern "C" fn warn_proc(ud: *mut c_void, msg: *const c_char, tocont: c_int) {
+    let lua = Lua::make_from_ptr(ud as *mut ffi::lua_State);
+    // Warnings can be raised from `__gc` metamethods while `extra` is locked, they are skipped then
+    let warn_cb = match lua.extra.try_lock() {
+        Ok(extra) => extra.warn_callback.clone(),
+        Err(_) => return,
+    };
+    let warn_cb = match warn_cb {
+        Some(warn_cb) => warn_cb,
+        None => return,
+    };
+    // Lua does not tell which thread raised the warning, so a panic cannot be turned into a Lua
+    // error and is resumed by `resume_warn_panic` instead
+    let msg = CStr::from_ptr(msg).to_bytes();
+    if let Err(p) = catch_unwind(AssertUnwindSafe(|| warn_cb(&lua, msg, tocont != 0))) {
+        let mut extra = mlua_expect!(lua.extra.lock(), "extra is poisoned");
+        extra.warn_panic.get_or_insert(p);
+    }
+}
+
+#[cfg_attr(feature = "luajit", allow(dead_code))]
+unsafe extern "C" fn allocator_proc(
+    extra_data: *mut c_void,
+    ptr: *mut c_void,
+    osize: usize,
+    nsize: usize,
+) -> *mut c_void {
+    let mem_info = &mut *(extra_data as *mut MemoryInfo);
+
+    if nsize == 0 {
+        // Free memory
+        if !ptr.is_null() {
+            let layout = Layout::from_size_align_unchecked(osize, ffi::SYS_MIN_ALIGN);
+            mem_info.allocator.free(ptr as *mut u8, layout);
+            mem_info.used_memory -= osize as isize;
+            if let Some(stats) = mem_info.stats.as_mut() {
+                stats.record_free();
+            }
+        }
+        return ptr::null_mut();
+    }
+
+    // Are we fit to the memory limits?
+    let mut mem_diff = nsize as isize;
+    if !ptr.is_null() {
+        mem_diff -= osize as isize;
+    }
+    let new_used_memory = mem_info.used_memory + mem_diff;
+    if mem_info.memory_limit > 0 && new_used_memory > mem_info.memory_limit {
+        #[cfg(feature = "lua51")]
+        let ignore_limit = mem_info.ignore_limit;
+        #[cfg(not(feature = "lua51"))]
+        let ignore_limit = false;
+        if !ignore_limit {
+            if let Some(stats) = mem_info.stats.as_mut() {
+                stats.record_failure();
+            }
+            return ptr::null_mut();
+        }
+    }
+
+    let new_layout = Layout::from_size_align_unchecked(nsize, ffi::SYS_MIN_ALIGN);
+
+    let (new_ptr, old_size) = if ptr.is_null() {
+        // Allocate new memory
+        let new_ptr = mem_info.allocator.alloc(new_layout) as *mut c_void;
+        (new_ptr, None)
+    } else {
+        // Reallocate memory
+        let old_layout = Layout::from_size_align_unchecked(osize, ffi::SYS_MIN_ALIGN);
+        let new_ptr =
+            (mem_info.allocator).realloc(ptr as *mut u8, old_layout, nsize) as *mut c_void;
+        (new_ptr, Some(osize))
+    };
+
+    if !new_ptr.is_null() {
+        mem_info.used_memory += mem_diff;
+        if let Some(stats) = mem_info.stats.as_mut() {
+            stats.record_alloc(old_size, nsize, mem_info.used_memory);
+        }
+    } else {
+        if let Some(stats) = mem_info.stats.as_mut() {
+            stats.record_failure();
+        }
+        if !ptr.is_null() && nsize < osize {
+            // Should not happend
+            alloc::handle_alloc_error(new_layout);
+        }
+    }
+
+    new_ptr
+}
+
+// Runs `f` allowing the allocations made by it to exceed the memory limit.
+// Lua 5.1 raises memory errors even from API functions called outside of a protected call, which
+// aborts the process, so this is used around the few allocations that mlua cannot protect.
+#[cfg(feature = "lua51")]
+pub(crate) unsafe fn relax_memory_limit<R>(state: *mut ffi::lua_State, f: impl FnOnce() -> R) -> R {
+    let mut ud = ptr::null_mut();
+    if ffi::lua_getallocf(state, &mut ud) as usize != allocator_proc as ffi::lua_Alloc as usize {
+        return f();
+    }
+    let mem_info = ud as *mut MemoryInfo;
+    let prev = (*mem_info).ignore_limit;
+    (*mem_info).ignore_limit = true;
+    let r = f();
+    (*mem_info).ignore_limit = prev;
+    r
+}
+
+#[cfg(not(feature = "lua51"))]
+#[inline(always)]
+pub(crate) unsafe fn relax_memory_limit<R>(
+    _state: *mut ffi::lua_State,
+    f: impl FnOnce() -> R,
+) -> R {
+    f()
+}
+
+// Returns the amount of memory (in bytes) used by the Lua state, according to the GC.
+unsafe fn gc_used_memory(state: *mut ffi::lua_State) -> usize {
+    let used_kbytes = ffi::lua_gc(state, ffi::LUA_GCCOUNT, 0);
+    let used_kbytes_rem = ffi::lua_gc(state, ffi::LUA_GCCOUNTB, 0);
+    (used_kbytes as usize) * 1024 + (used_kbytes_rem as usize)
+}
+
+// Pushes a sentinel userdata that is finalized (and recreated) on every GC cycle to check the
+// memory limit. Used in LuaJIT, where the allocator cannot be replaced.
+#[cfg(feature = "luajit")]
+unsafe fn push_gc_check(state: *mut ffi::lua_State, mem_info: *mut MemoryInfo) {
+    let ud = ffi::lua_newuserdata(state, mem::size_of::<*mut MemoryInfo>());
+    *(ud as *mut *mut MemoryInfo) = mem_info;
+    ffi::lua_newtable(state);
+    ffi::lua_pushcfunction(state, gc_check_memory);
+    ffi::lua_setfield(state, -2, cstr!("__gc"));
+    ffi::lua_setmetatable(state, -2);
+}
+
+// Finalizer of the GC sentinel. Errors cannot be raised from finalizers, so the running code is
+// stopped from the hook (see `Lua::interrupt`).
+#[cfg(feature = "luajit")]
+unsafe extern "C" fn gc_check_memory(state: *mut ffi::lua_State) -> c_int {
+    let mem_info = *(ffi::lua_touserdata(state, 1) as *mut *mut MemoryInfo);
+    if (*mem_info).memory_limit <= 0 {
+        (*mem_info).gc_check_armed = false;
+        return 0;
+    }
+
+    // Recreate the sentinel for the next cycle
+    let ud = ffi::lua_newuserdata(state, mem::size_of::<*mut MemoryInfo>());
+    *(ud as *mut *mut MemoryInfo) = mem_info;
+    ffi::lua_getmetatable(state, 1);
+    ffi::lua_setmetatable(state, -2);
+    ffi::lua_pop(state, 1);
+
+    if gc_used_memory(state) > (*mem_info).memory_limit as usize {
+        (*mem_info).limit_exceeded = true;
+        let mask = ffi::lua_gethookmask(state) | ffi::LUA_MASKCOUNT;
+        ffi::lua_sethook(state, Some(hook_proc), mask, 1);
+    }
+    0
+}
+
+// Returns the hooks and the interrupt callback shared by all threads.
+// Uses 1 stack space, does not call lua_checkstack.
+unsafe fn get_shared_hooks(state: *mut ffi::lua_State) -> *mut SharedHooks {
+    ffi::lua_rawgetp(
+        state,
+        ffi::LUA_REGISTRYINDEX,
+        &HOOKS_REGISTRY_KEY as *const u8 as *mut c_void,
+    );
+    let hooks = get_gc_userdata::<SharedHooks>(state, -1);
+    ffi::lua_pop(state, 1);
+    mlua_assert!(!hooks.is_null(), "shared hooks are not set");
+    hooks
+}
+
+// Returns the hook state of the thread at the given index, stored in the registry.
+// If the thread has no hook state, a new one is created if `create` is `true`, otherwise a null
+// pointer is returned.
+// Uses 4 stack spaces, does not call lua_checkstack.
+unsafe fn get_thread_hook(
+    state: *mut ffi::lua_State,
+    index: c_int,
+    create: bool,
+) -> Result<*mut ThreadHook> {
+    let index = ffi::lua_absindex(state, index);
+    ffi::lua_rawgetp(
+        state,
+        ffi::LUA_REGISTRYINDEX,
+        &THREAD_HOOKS_REGISTRY_KEY as *const u8 as *mut c_void,
+    );
+    ffi::lua_pushvalue(state, index);
+    ffi::lua_rawget(state, -2);
+    let thread_hook = get_gc_userdata::<ThreadHook>(state, -1);
+    ffi::lua_pop(state, 1);
+    if !thread_hook.is_null() || !create {
+        ffi::lua_pop(state, 1);
+        return Ok(thread_hook);
+    }
+
+    ffi::lua_pushvalue(state, index);
+    push_gc_userdata(
+        state,
+        ThreadHook {
+            hook: None,
+            instructions: 0,
+        },
+    )?;
+    let thread_hook = get_gc_userdata::<ThreadHook>(state, -1);
+    protect_lua_closure(state, 3, 0, |state| {
+        ffi::lua_rawset(state, -3);
+    })?;
+    Ok(thread_hook)
+}
+
+// Returns the hook mask and the number of VM instructions until the next count event of a thread,
+// combined from the shared hooks, the interrupt callback and the hook of the thread.
+fn hook_mask(hooks: &SharedHooks, thread: &ThreadHook) -> (c_int, u32) {
+    let executed = thread.instructions;
+    let mut mask = 0;
+    let mut count = 0;
+    let thread_hook = thread.hook.as_ref().map(|(triggers, _)| triggers);
+    for triggers in (hooks.hooks.iter().map(|(_, triggers, _)| triggers)).chain(thread_hook) {
+        mask |= triggers.mask();
+        count = min_count(
+            count,
+            count_distance(triggers.every_nth_instruction, executed),
+        );
+    }
+    if hooks.has_interrupt() {
+        mask |= ffi::LUA_MASKCOUNT;
+        count = min_count(
+            count,
+            count_distance(Some(INTERRUPT_INSTRUCTIONS), executed),
+        );
+    }
+    if hooks.memory_limit_exceeded() {
+        mask |= ffi::LUA_MASKCOUNT;
+        count = 1;
+    }
+    if let Some(n) = hooks.memory_sample_interval() {
+        mask |= ffi::LUA_MASKCOUNT;
+        count = min_count(count, count_distance(Some(n), executed));
+    }
+    (mask, count)
+}
+
+// Returns the smaller of two instruction counts, where zero means no count.
+fn min_count(count: u32, n: u32) -> u32 {
+    match (count, n) {
+        (count, 0) => count,
+        (0, n) => n,
+        (count, n) => count.min(n),
+    }
+}
+
+unsafe fn set_hook_mask(state: *mut ffi::lua_State, mask: c_int, count: u32) {
+    if mask != 0 {
+        ffi::lua_sethook(state, Some(hook_proc), mask, count as c_int);
+    } else {
+        ffi::lua_sethook(state, None, 0, 0);
+    }
+}
+
+// Indices of the values shared by the functions of the sandbox mode. They are stored in a table
+// passed as the first upvalue to each function.
+const SANDBOX_GLOBALS: ffi::lua_Integer = 1;
+// Read-only proxies to their tables (weak keys)
+const SANDBOX_ORIGINALS: ffi::lua_Integer = 2;
+// Tables to their read-only proxies (weak keys)
+const SANDBOX_PROXIES: ffi::lua_Integer = 3;
+// Globals replaced in the sandbox
+const SANDBOX_OVERRIDES: ffi::lua_Integer = 4;
+// Names of the hidden globals
+const SANDBOX_HIDDEN: ffi::lua_Integer = 5;
+// Functions of the `table` library to their guarded versions (weak keys)
+const SANDBOX_GUARDS: ffi::lua_Integer = 6;
+
+const SANDBOX_HIDDEN_NAMES: &[&str] = &[
+    "debug", "ffi", "io", "package", "dofile", "loadfile", "module", "require",
+];
+const SANDBOX_OS_NAMES: &[&str] = &["clock", "date", "difftime", "time"];
+// On some Lua versions `table.insert`, `table.remove` and `table.sort` use raw access, so they
+// would write into the (shared) proxies themselves
+const SANDBOX_TABLE_MODIFIERS: &[&str] = &["insert", "remove", "sort"];
+
+// Builds the read-only view of the globals for the sandbox mode.
+// Receives the global table and the sandboxed `load` function, and returns the metatable for the
+// environments of sandboxed chunks.
+unsafe extern "C" fn sandbox_init(state: *mut ffi::lua_State) -> c_int {
+    ffi::luaL_checkstack(state, 8, ptr::null());
+    ffi::lua_settop(state, 2);
+
+    // Shared values at index 3
+    ffi::lua_createtable(state, 6, 0);
+    ffi::lua_pushvalue(state, 1);
+    ffi::lua_rawseti(state, 3, SANDBOX_GLOBALS);
+    for &i in &[SANDBOX_ORIGINALS, SANDBOX_PROXIES, SANDBOX_GUARDS] {
+        ffi::lua_newtable(state);
+        ffi::lua_newtable(state);
+        ffi::lua_pushstring(state, cstr!("k"));
+        ffi::lua_setfield(state, -2, cstr!("__mode"));
+        ffi::lua_setmetatable(state, -2);
+        ffi::lua_rawseti(state, 3, i);
+    }
+    ffi::lua_newtable(state);
+    for name in SANDBOX_HIDDEN_NAMES {
+        ffi::lua_pushlstring(state, name.as_ptr() as *const c_char, name.len());
+        ffi::lua_pushboolean(state, 1);
+        ffi::lua_rawset(state, -3);
+    }
+    ffi::lua_rawseti(state, 3, SANDBOX_HIDDEN);
+
+    // Overrides at index 4
+    ffi::lua_newtable(state);
+    ffi::lua_pushvalue(state, -1);
+    ffi::lua_rawseti(state, 3, SANDBOX_OVERRIDES);
+
+    ffi::lua_pushvalue(state, 2);
+    ffi::lua_setfield(state, 4, cstr!("load"));
+    if ffi::lua_getfield(state, 1, cstr!("loadstring")) != ffi::LUA_TNIL {
+        ffi::lua_pushvalue(state, 2);
+        ffi::lua_setfield(state, 4, cstr!("loadstring"));
+    }
+    ffi::lua_pop(state, 1);
+
+    ffi::lua_pushvalue(state, 3);
+    ffi::lua_pushcclosure(state, sandbox_rawset, 1);
+    ffi::lua_setfield(state, 4, cstr!("rawset"));
+
+    if ffi::lua_getfield(state, 1, cstr!("os")) == ffi::LUA_TTABLE {
+        ffi::lua_createtable(state, 0, SANDBOX_OS_NAMES.len() as c_int);
+        for name in SANDBOX_OS_NAMES {
+            ffi::lua_pushlstring(state, name.as_ptr() as *const c_char, name.len());
+            ffi::lua_pushvalue(state, -1);
+            ffi::lua_gettable(state, -4);
+            ffi::lua_rawset(state, -3);
+        }
+        ffi::lua_pushstring(state, cstr!("os"));
+        sandbox_wrap(state, 3, -2, 0, -1);
+        ffi::lua_setfield(state, 4, cstr!("os"));
+        ffi::lua_pop(state, 2);
+    }
+    ffi::lua_pop(state, 1);
+
+    #[cfg(any(feature = "lua51", feature = "luajit"))]
+    {
+        ffi::lua_getfield(state, 1, cstr!("getfenv"));
+        ffi::lua_getfield(state, 1, cstr!("setfenv"));
+        if ffi::lua_isnil(state, -1) == 0 {
+            ffi::lua_pushvalue(state, 3);
+            ffi::lua_pushvalue(state, -3);
+            ffi::lua_pushcclosure(state, sandbox_getfenv, 2);
+            ffi::lua_setfield(state, 4, cstr!("getfenv"));
+            ffi::lua_pushvalue(state, 3);
+            ffi::lua_pushvalue(state, -3);
+            ffi::lua_pushvalue(state, -3);
+            ffi::lua_pushcclosure(state, sandbox_setfenv, 3);
+            ffi::lua_setfield(state, 4, cstr!("setfenv"));
+        }
+        ffi::lua_pop(state, 2);
+    }
+
+    // Read-only views of the `table` library and the globals
+    ffi::lua_rawgeti(state, 3, SANDBOX_PROXIES);
+    if ffi::lua_getfield(state, 1, cstr!("table")) == ffi::LUA_TTABLE {
+        ffi::lua_pushstring(state, cstr!("table"));
+        sandbox_readonly(state, 3, -2, -1, sandbox_tablib_index);
+        ffi::lua_remove(state, -2);
+        ffi::lua_rawset(state, -3);
+    } else {
+        ffi::lua_pop(state, 1);
+    }
+    ffi::lua_pushvalue(state, 1);
+    ffi::lua_pushstring(state, cstr!("_G"));
+    sandbox_readonly(state, 3, 1, -1, sandbox_globals_index);
+    ffi::lua_remove(state, -2);
+    ffi::lua_pushvalue(state, -1);
+    ffi::lua_insert(state, -4);
+    ffi::lua_rawset(state, -3);
+    ffi::lua_pop(state, 1);
+
+    ffi::lua_createtable(state, 0, 2);
+    ffi::lua_insert(state, -2);
+    ffi::lua_setfield(state, -2, cstr!("__index"));
+    ffi::lua_pushboolean(state, 0);
+    ffi::lua_setfield(state, -2, cstr!("__metatable"));
+    1
+}
+
+// Pushes the read-only proxy of the value at index `value` if it's a table (creating it if needed),
+// or the value itself. The name of the proxy is made of the name at index `parent` (if not zero)
+// and the key at index `key`.
+// Uses 5 stack spaces, does not call lua_checkstack.
+unsafe fn sandbox_wrap(
+    state: *mut ffi::lua_State,
+    shared: c_int,
+    value: c_int,
+    parent: c_int,
+    key: c_int,
+) {
+    let shared = ffi::lua_absindex(state, shared);
+    let value = ffi::lua_absindex(state, value);
+    let key = ffi::lua_absindex(state, key);
+    if ffi::lua_type(state, value) != ffi::LUA_TTABLE {
+        ffi::lua_pushvalue(state, value);
+        return;
+    }
+
+    ffi::lua_rawgeti(state, shared, SANDBOX_PROXIES);
+    ffi::lua_pushvalue(state, value);
+    if ffi::lua_rawget(state, -2) != ffi::LUA_TNIL {
+        ffi::lua_remove(state, -2);
+        return;
+    }
+    ffi::lua_pop(state, 1);
+
+    if parent != 0 {
+        ffi::lua_pushvalue(state, parent);
+        ffi::lua_pushstring(state, cstr!("."));
+        ffi::luaL_tolstring(state, key, ptr::null_mut());
+        ffi::lua_concat(state, 3);
+    } else {
+        ffi::luaL_tolstring(state, key, ptr::null_mut());
+    }
+    sandbox_readonly(state, shared, value, -1, sandbox_readonly_index);
+    ffi::lua_remove(state, -2);
+    ffi::lua_pushvalue(state, value);
+    ffi::lua_pushvalue(state, -2);
+    ffi::lua_rawset(state, -4);
+    ffi::lua_remove(state, -2);
+}
+
+// Pushes a new read-only proxy of the table at index `table`, named by the string at index `name`
+// for error messages. `index` is the `__index` metamethod of the proxy, and its upvalues are the
+// shared values, the table and the name.
+// Uses 5 stack spaces, does not call lua_checkstack.
+unsafe fn sandbox_readonly(
+    state: *mut ffi::lua_State,
+    shared: c_int,
+    table: c_int,
+    name: c_int,
+    index: ffi::lua_CFunction,
+) {
+    let shared = ffi::lua_absindex(state, shared);
+    let table = ffi::lua_absindex(state, table);
+    let name = ffi::lua_absindex(state, name);
+
+    ffi::lua_newtable(state);
+    let proxy = ffi::lua_gettop(state);
+    ffi::lua_rawgeti(state, shared, SANDBOX_ORIGINALS);
+    ffi::lua_pushvalue(state, proxy);
+    ffi::lua_pushvalue(state, table);
+    ffi::lua_rawset(state, -3);
+    ffi::lua_pop(state, 1);
+
+    ffi::lua_createtable(state, 0, 5);
+    ffi::lua_pushvalue(state, shared);
+    ffi::lua_pushvalue(state, table);
+    ffi::lua_pushvalue(state, name);
+    ffi::lua_pushcclosure(state, index, 3);
+    ffi::lua_pushvalue(state, table);
+    ffi::lua_pushvalue(state, -2);
+    ffi::lua_pushcclosure(state, sandbox_readonly_pairs, 2);
+    ffi::lua_setfield(state, -3, cstr!("__pairs"));
+    ffi::lua_setfield(state, -2, cstr!("__index"));
+    ffi::lua_pushvalue(state, name);
+    ffi::lua_pushcclosure(state, sandbox_readonly_newindex, 1);
+    ffi::lua_setfield(state, -2, cstr!("__newindex"));
+    ffi::lua_pushvalue(state, table);
+    ffi::lua_pushcclosure(state, sandbox_readonly_len, 1);
+    ffi::lua_setfield(state, -2, cstr!("__len"));
+    ffi::lua_pushboolean(state, 0);
+    ffi::lua_setfield(state, -2, cstr!("__metatable"));
+    ffi::lua_setmetatable(state, proxy);
+}
+
+// Returns `true` if the table at index `table` is a read-only proxy.
+// Uses 2 stack spaces, does not call lua_checkstack.
+unsafe fn sandbox_is_readonly(state: *mut ffi::lua_State, shared: c_int, table: c_int) -> bool {
+    let table = ffi::lua_absindex(state, table);
+    ffi::lua_rawgeti(state, shared, SANDBOX_ORIGINALS);
+    ffi::lua_pushvalue(state, table);
+    let readonly = ffi::lua_rawget(state, -2) != ffi::LUA_TNIL;
+    ffi::lua_pop(state, 2);
+    readonly
+}
+
+// `__index` metamethod of read-only proxies, returns the read-only view of the value.
+// Upvalues are: the shared values, the table and its name.
+unsafe extern "C" fn sandbox_readonly_index(state: *mut ffi::lua_State) -> c_int {
+    ffi::luaL_checkstack(state, 6, ptr::null());
+    ffi::lua_settop(state, 2);
+    ffi::lua_pushvalue(state, 2);
+    ffi::lua_gettable(state, ffi::lua_upvalueindex(2));
+    sandbox_wrap(
+        state,
+        ffi::lua_upvalueindex(1),
+        3,
+        ffi::lua_upvalueindex(3),
+        2,
+    );
+    1
+}
+
+// `__index` metamethod of the read-only view of the globals, which hides and replaces some of them.
+// Upvalues are: the shared values, the global table and its name.
+unsafe extern "C" fn sandbox_globals_index(state: *mut ffi::lua_State) -> c_int {
+    ffi::luaL_checkstack(state, 6, ptr::null());
+    ffi::lua_settop(state, 2);
+    ffi::lua_rawgeti(state, ffi::lua_upvalueindex(1), SANDBOX_OVERRIDES);
+    ffi::lua_pushvalue(state, 2);
+    if ffi::lua_rawget(state, -2) != ffi::LUA_TNIL {
+        return 1;
+    }
+    ffi::lua_rawgeti(state, ffi::lua_upvalueindex(1), SANDBOX_HIDDEN);
+    ffi::lua_pushvalue(state, 2);
+    if ffi::lua_rawget(state, -2) != ffi::LUA_TNIL {
+        ffi::lua_pushnil(state);
+        return 1;
+    }
+    ffi::lua_settop(state, 2);
+    ffi::lua_pushvalue(state, 2);
+    ffi::lua_gettable(state, ffi::lua_upvalueindex(2));
+    sandbox_wrap(state, ffi::lua_upvalueindex(1), 3, 0, 2);
+    1
+}
+
+// `__index` metamethod of the read-only view of the `table` library, which guards the functions
+// modifying tables from being used on read-only proxies.
+// Upvalues are: the shared values, the `table` library and its name.
+unsafe extern "C" fn sandbox_tablib_index(state: *mut ffi::lua_State) -> c_int {
+    ffi::luaL_checkstack(state, 6, ptr::null());
+    ffi::lua_settop(state, 2);
+    ffi::lua_pushvalue(state, 2);
+    ffi::lua_gettable(state, ffi::lua_upvalueindex(2));
+
+    let is_modifier = ffi::lua_type(state, 2) == ffi::LUA_TSTRING && {
+        let name = CStr::from_ptr(ffi::lua_tostring(state, 2)).to_bytes();
+        SANDBOX_TABLE_MODIFIERS.iter().any(|&m| m.as_bytes() == name)
+    };
+    if !is_modifier || ffi::lua_type(state, 3) != ffi::LUA_TFUNCTION {
+        sandbox_wrap(
+            state,
+            ffi::lua_upvalueindex(1),
+            3,
+            ffi::lua_upvalueindex(3),
+            2,
+        );
+        return 1;
+    }
+
+    ffi::lua_rawgeti(state, ffi::lua_upvalueindex(1), SANDBOX_GUARDS);
+    ffi::lua_pushvalue(state, 3);
+    if ffi::lua_rawget(state, 4) != ffi::LUA_TNIL {
+        return 1;
+    }
+    ffi::lua_pop(state, 1);
+    ffi::lua_pushvalue(state, 3);
+    ffi::lua_pushvalue(state, ffi::lua_upvalueindex(1));
+    ffi::lua_pushvalue(state, 3);
+    ffi::lua_pushcclosure(state, sandbox_table_guard, 2);
+    ffi::lua_pushvalue(state, -1);
+    ffi::lua_insert(state, -3);
+    ffi::lua_rawset(state, 4);
+    1
+}
+
+// Guarded version of a `table` function modifying its first argument.
+// Upvalues are: the shared values and the function.
+unsafe extern "C" fn sandbox_table_guard(state: *mut ffi::lua_State) -> c_int {
+    ffi::luaL_checkstack(state, 3, ptr::null());
+    if sandbox_is_readonly(state, ffi::lua_upvalueindex(1), 1) {
+        ffi::luaL_error(state, cstr!("attempt to modify a read-only table"));
+    }
+    ffi::lua_pushvalue(state, ffi::lua_upvalueindex(2));
+    ffi::lua_insert(state, 1);
+    ffi::lua_call(state, ffi::lua_gettop(state) - 1, ffi::LUA_MULTRET);
+    ffi::lua_gettop(state)
+}
+
+// `__newindex` metamethod of read-only proxies.
+// Upvalues are: the name of the table.
+unsafe extern "C" fn sandbox_readonly_newindex(state: *mut ffi::lua_State) -> c_int {
+    ffi::luaL_error(
+        state,
+        cstr!("attempt to modify a read-only table '%s'"),
+        ffi::lua_tostring(state, ffi::lua_upvalueindex(1)),
+    )
+}
+
+// `__len` metamethod of read-only proxies.
+// Upvalues are: the table.
+unsafe extern "C" fn sandbox_readonly_len(state: *mut ffi::lua_State) -> c_int {
+    ffi::luaL_checkstack(state, 1, ptr::null());
+    ffi::lua_len(state, ffi::lua_upvalueindex(1));
+    1
+}
+
+// `__pairs` metamethod of read-only proxies.
+// Upvalues are: the table and the `__index` metamethod of the proxy.
+unsafe extern "C" fn sandbox_readonly_pairs(state: *mut ffi::lua_State) -> c_int {
+    ffi::luaL_checkstack(state, 3, ptr::null());
+    ffi::lua_pushvalue(state, ffi::lua_upvalueindex(1));
+    ffi::lua_pushvalue(state, ffi::lua_upvalueindex(2));
+    ffi::lua_pushcclosure(state, sandbox_readonly_next, 2);
+    ffi::lua_pushvalue(state, 1);
+    ffi::lua_pushnil(state);
+    3
+}
+
+// Iterator returned by `sandbox_readonly_pairs`. Values are read through the `__index`
+// metamethod of the proxy, and keys it maps to `nil` (the hidden globals) are skipped.
+// Upvalues are: the table and the `__index` metamethod of the proxy.
+unsafe extern "C" fn sandbox_readonly_next(state: *mut ffi::lua_State) -> c_int {
+    ffi::luaL_checkstack(state, 4, ptr::null());
+    ffi::lua_settop(state, 2);
+    loop {
+        if ffi::lua_next(state, ffi::lua_upvalueindex(1)) == 0 {
+            ffi::lua_pushnil(state);
+            return 1;
+        }
+        ffi::lua_pop(state, 1);
+        ffi::lua_pushvalue(state, ffi::lua_upvalueindex(2));
+        ffi::lua_pushvalue(state, 1);
+        ffi::lua_pushvalue(state, 2);
+        ffi::lua_call(state, 2, 1);
+        if ffi::lua_isnil(state, -1) == 0 {
+            return 2;
+        }
+        ffi::lua_pop(state, 1);
+    }
+}
+
+// `rawset` function available in the sandbox mode, which refuses to modify read-only proxies.
+// Upvalues are: the shared values.
+unsafe extern "C" fn sandbox_rawset(state: *mut ffi::lua_State) -> c_int {
+    ffi::luaL_checkstack(state, 2, ptr::null());
+    ffi::luaL_checktype(state, 1, ffi::LUA_TTABLE);
+    ffi::luaL_checkany(state, 2);
+    ffi::luaL_checkany(state, 3);
+    ffi::lua_settop(state, 3);
+    if sandbox_is_readonly(state, ffi::lua_upvalueindex(1), 1) {
+        ffi::luaL_error(state, cstr!("attempt to modify a read-only table"));
+    }
+    ffi::lua_rawset(state, 1);
+    1
+}
+
+// `getfenv` function available in the sandbox mode, which returns the read-only view instead of
+// the global table.
+// Upvalues are: the shared values and the original `getfenv` function.
+#[cfg(any(feature = "lua51", feature = "luajit"))]
+unsafe extern "C" fn sandbox_getfenv(state: *mut ffi::lua_State) -> c_int {
+    ffi::luaL_checkstack(state, 4, ptr::null());
+    ffi::lua_settop(state, 1);
+    // Skip the level of this function
+    if ffi::lua_isnil(state, 1) != 0 {
+        ffi::lua_pushinteger(state, 2);
+        ffi::lua_replace(state, 1);
+    } else if ffi::lua_type(state, 1) == ffi::LUA_TNUMBER && ffi::lua_tonumber(state, 1) > 0.0 {
+        ffi::lua_pushnumber(state, ffi::lua_tonumber(state, 1) + 1.0);
+        ffi::lua_replace(state, 1);
+    }
+    ffi::lua_pushvalue(state, ffi::lua_upvalueindex(2));
+    ffi::lua_pushvalue(state, 1);
+    ffi::lua_call(state, 1, 1);
+
+    ffi::lua_rawgeti(state, ffi::lua_upvalueindex(1), SANDBOX_GLOBALS);
+    if ffi::lua_rawequal(state, 2, 3) != 0 {
+        ffi::lua_rawgeti(state, ffi::lua_upvalueindex(1), SANDBOX_PROXIES);
+        ffi::lua_pushvalue(state, 2);
+        ffi::lua_rawget(state, -2);
+        return 1;
+    }
+    ffi::lua_settop(state, 2);
+    1
+}
+
+// `setfenv` function available in the sandbox mode, which refuses to change the environment of the
+// running thread and of functions outside of the sandbox.
+// Upvalues are: the shared values, the original `getfenv` and `setfenv` functions.
+#[cfg(any(feature = "lua51", feature = "luajit"))]
+unsafe extern "C" fn sandbox_setfenv(state: *mut ffi::lua_State) -> c_int {
+    ffi::luaL_checkstack(state, 4, ptr::null());
+    ffi::lua_settop(state, 2);
+    if ffi::lua_type(state, 1) == ffi::LUA_TNUMBER {
+        let level = ffi::lua_tonumber(state, 1);
+        if level == 0.0 {
+            ffi::luaL_error(
+                state,
+                cstr!("cannot change the environment of the running thread"),
+            );
+        }
+        // Skip the level of this function
+        ffi::lua_pushnumber(state, level + 1.0);
+        ffi::lua_replace(state, 1);
+    }
+    ffi::lua_pushvalue(state, ffi::lua_upvalueindex(2));
+    ffi::lua_pushvalue(state, 1);
+    ffi::lua_call(state, 1, 1);
+    ffi::lua_rawgeti(state, ffi::lua_upvalueindex(1), SANDBOX_GLOBALS);
+    if ffi::lua_rawequal(state, 3, 4) != 0 {
+        ffi::luaL_error(
+            state,
+            cstr!("cannot change the environment of a function outside of the sandbox"),
+        );
+    }
+    ffi::lua_settop(state, 2);
+    ffi::lua_pushvalue(state, ffi::lua_upvalueindex(3));
+    ffi::lua_insert(state, 1);
+    ffi::lua_call(state, 2, 1);
+    1
+}
+
+// `load` function available in the sandbox mode. Compiles text chunks only and gives them a new
+// isolated environment if not provided explicitly.
+fn sandbox_load<'lua>(
+    lua: &'lua Lua,
+    (chunk, name, _mode, env): (
+        Value<'lua>,
+        Option<String<'lua>>,
+        Value<'lua>,
+        Variadic<Value<'lua>>,
+    ),
+) -> Result<(Option<Function<'lua>>, Option<std::string::String>)> {
+    let source = match chunk {
+        Value::String(s) => s,
+        Value::Function(reader) => {
+            // The pieces are joined in Lua memory to respect the memory limit. Like `luaL_Buffer`,
+            // merge a piece with the previous one while it's not larger, to avoid quadratic copying.
+            let mut pieces: Vec<String> = Vec::new();
+            while let Some(piece) = reader.call::<_, Option<String>>(())? {
+                if piece.as_bytes().is_empty() {
+                    break;
+                }
+                let mut piece = piece;
+                while let Some(prev) = pieces.pop() {
+                    if prev.as_bytes().len() > piece.as_bytes().len() {
+                        pieces.push(prev);
+                        break;
+                    }
+                    piece = concat_strings(lua, &prev, &piece)?;
+                }
+                pieces.push(piece);
+            }
+            let mut source = lua.create_string("")?;
+            while let Some(prev) = pieces.pop() {
+                source = concat_strings(lua, &prev, &source)?;
+            }
+            source
+        }
+        _ => {
+            return Err(Error::BadArgument {
+                pos: 1,
+                name: Some("load".to_string()),
+                cause: Arc::new(Error::FromLuaConversionError {
+                    from: chunk.type_name(),
+                    to: "string or function",
+                    message: None,
+                }),
+            })
+        }
+    };
+
+    let name = match name {
+        Some(name) => CString::new(name.as_bytes().to_vec()).ok(),
+        None => None,
+    };
+    let name = name.unwrap_or_else(|| CString::new("=(load)").unwrap());
+
+    // As with the stock `load`, an explicit `nil` environment leaves `_ENV` unset, while Lua 5.1
+    // and LuaJIT use the default environment for anything but tables
+    let env = env.into_iter().next();
+    #[cfg(any(feature = "lua51", feature = "luajit"))]
+    let env = env.filter(|env| match env {
+        Value::Table(_) => true,
+        _ => false,
+    });
+
+    match lua.load_chunk(source.as_bytes(), Some(&name), env, Some(ChunkMode::Text)) {
+        Ok(func) => Ok((Some(func), None)),
+        Err(Error::SyntaxError { message, .. }) => Ok((None, Some(message))),
+        Err(err) => Err(err),
+    }
+}
+
+// Concatenates two Lua strings without copying them to Rust memory.
+fn concat_strings<'lua>(
+    lua: &'lua Lua,
+    a: &String<'lua>,
+    b: &String<'lua>,
+) -> Result<String<'lua>> {
+    unsafe {
+        let _sg = StackGuard::new(lua.state);
+        assert_stack(lua.state, 2);
+
+        lua.push_ref(&a.0);
+        lua.push_ref(&b.0);
+        protect_lua_closure(lua.state, 2, 1, |state| ffi::lua_concat(state, 2))?;
+        Ok(String(lua.pop_ref()))
+    }
+}
+
 unsafe fn load_from_std_lib(state: *mut ffi::lua_State, libs: StdLib) {
     #[cfg(feature = "luajit")]
     // Stop collector during library initialization
@@ -2243,7 +4826,7 @@ impl<'lua, T: 'static + UserData> StaticUserDataMethods<'lua, T> {
             if let Some(front) = args.pop_front() {
                 let userdata = AnyUserData::from_lua(front, lua)?;
                 let userdata = userdata.borrow::<T>()?;
-                method(lua, &userdata, A::from_lua_multi(args, lua)?)?.to_lua_multi(lua)
+                method(lua, &userdata, lua.callback_args(args, 2)?)?.to_lua_multi(lua)
             } else {
                 Err(Error::FromLuaConversionError {
                     from: "missing argument",
@@ -2268,7 +4851,7 @@ impl<'lua, T: 'static + UserData> StaticUserDataMethods<'lua, T> {
                 let mut method = method
                     .try_borrow_mut()
                     .map_err(|_| Error::RecursiveMutCallback)?;
-                (&mut *method)(lua, &mut userdata, A::from_lua_multi(args, lua)?)?.to_lua_multi(lua)
+                (&mut *method)(lua, &mut userdata, lua.callback_args(args, 2)?)?.to_lua_multi(lua)
             } else {
                 Err(Error::FromLuaConversionError {
                     from: "missing argument",
@@ -2293,7 +4876,7 @@ impl<'lua, T: 'static + UserData> StaticUserDataMethods<'lua, T> {
                 if let Some(front) = args.pop_front() {
                     let userdata = AnyUserData::from_lua(front, lua)?;
                     let userdata = userdata.borrow::<T>()?.clone();
-                    Ok(method(lua, userdata, A::from_lua_multi(args, lua)?))
+                    Ok(method(lua, userdata, lua.async_callback_args(args, 2)?))
                 } else {
                     Err(Error::FromLuaConversionError {
                         from: "missing argument",
@@ -2315,7 +4898,7 @@ impl<'lua, T: 'static + UserData> StaticUserDataMethods<'lua, T> {
         R: ToLuaMulti<'lua>,
         F: 'static + MaybeSend + Fn(&'lua Lua, A) -> Result<R>,
     {
-        Box::new(move |lua, args| function(lua, A::from_lua_multi(args, lua)?)?.to_lua_multi(lua))
+        Box::new(move |lua, args| function(lua, lua.callback_args(args, 1)?)?.to_lua_multi(lua))
     }
 
     fn box_function_mut<A, R, F>(function: F) -> Callback<'lua, 'static>
@@ -2329,7 +4912,7 @@ impl<'lua, T: 'static + UserData> StaticUserDataMethods<'lua, T> {
             let function = &mut *function
                 .try_borrow_mut()
                 .map_err(|_| Error::RecursiveMutCallback)?;
-            function(lua, A::from_lua_multi(args, lua)?)?.to_lua_multi(lua)
+            function(lua, lua.callback_args(args, 1)?)?.to_lua_multi(lua)
         })
     }
 
@@ -2342,7 +4925,7 @@ impl<'lua, T: 'static + UserData> StaticUserDataMethods<'lua, T> {
         FR: 'lua + Future<Output = Result<R>>,
     {
         Box::new(move |lua, args| {
-            let args = match A::from_lua_multi(args, lua) {
+            let args = match lua.async_callback_args(args, 1) {
                 Ok(args) => args,
                 Err(e) => return Box::pin(future::err(e)),
             };
@@ -2350,3 +4933,71 @@ impl<'lua, T: 'static + UserData> StaticUserDataMethods<'lua, T> {
         })
     }
 }
+
+struct StaticUserDataFields<'lua, T: 'static + UserData> {
+    field_getters: Vec<(Vec<u8>, Callback<'lua, 'static>)>,
+    field_setters: Vec<(Vec<u8>, Callback<'lua, 'static>)>,
+    _type: PhantomData<T>,
+}
+
+impl<'lua, T: 'static + UserData> Default for StaticUserDataFields<'lua, T> {
+    fn default() -> StaticUserDataFields<'lua, T> {
+        StaticUserDataFields {
+            field_getters: Vec::new(),
+            field_setters: Vec::new(),
+            _type: PhantomData,
+        }
+    }
+}
+
+impl<'lua, T: 'static + UserData> UserDataFields<'lua, T> for StaticUserDataFields<'lua, T> {
+    fn add_field_method_get<S, R, M>(&mut self, name: &S, method: M)
+    where
+        S: AsRef<[u8]> + ?Sized,
+        R: ToLua<'lua>,
+        M: 'static + MaybeSend + Fn(&'lua Lua, &T) -> Result<R>,
+    {
+        self.field_getters.push((
+            name.as_ref().to_vec(),
+            StaticUserDataMethods::box_method(move |lua, data, ()| method(lua, data)),
+        ));
+    }
+
+    fn add_field_method_set<S, A, M>(&mut self, name: &S, method: M)
+    where
+        S: AsRef<[u8]> + ?Sized,
+        A: FromLua<'lua>,
+        M: 'static + MaybeSend + FnMut(&'lua Lua, &mut T, A) -> Result<()>,
+    {
+        self.field_setters.push((
+            name.as_ref().to_vec(),
+            StaticUserDataMethods::box_method_mut(method),
+        ));
+    }
+
+    fn add_field_function_get<S, R, F>(&mut self, name: &S, function: F)
+    where
+        S: AsRef<[u8]> + ?Sized,
+        R: ToLua<'lua>,
+        F: 'static + MaybeSend + Fn(&'lua Lua, AnyUserData<'lua>) -> Result<R>,
+    {
+        self.field_getters.push((
+            name.as_ref().to_vec(),
+            StaticUserDataMethods::<T>::box_function(function),
+        ));
+    }
+
+    fn add_field_function_set<S, A, F>(&mut self, name: &S, mut function: F)
+    where
+        S: AsRef<[u8]> + ?Sized,
+        A: FromLua<'lua>,
+        F: 'static + MaybeSend + FnMut(&'lua Lua, AnyUserData<'lua>, A) -> Result<()>,
+    {
+        self.field_setters.push((
+            name.as_ref().to_vec(),
+            StaticUserDataMethods::<T>::box_function_mut(move |lua, (data, val)| {
+                function(lua, data, val)
+            }),
+        ));
+    }
+}
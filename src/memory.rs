@@ -0,0 +1,182 @@
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::mem;
+use std::os::raw::c_int;
+
+use crate::ffi::{self, lua_Debug, lua_State};
+use crate::hook::{bytes_to_string, ptr_to_str};
+
+// Number of size classes: one for each power of two up to the largest `usize`.
+const SIZE_CLASSES: usize = mem::size_of::<usize>() * 8 + 1;
+
+/// A snapshot of the memory statistics of a Lua state.
+///
+/// The statistics are collected after calling [`Lua::enable_memory_stats`] and returned by
+/// [`Lua::memory_stats`].
+///
+/// [`Lua::enable_memory_stats`]: struct.Lua.html#method.enable_memory_stats
+/// [`Lua::memory_stats`]: struct.Lua.html#method.memory_stats
+#[derive(Clone, Debug, Default)]
+pub struct MemoryStats {
+    /// The amount of memory (in bytes) currently used by the Lua state.
+    pub used_memory: usize,
+    /// The highest amount of memory (in bytes) used since the statistics were enabled.
+    pub peak_memory: usize,
+    /// Number of allocated memory blocks.
+    pub allocations: u64,
+    /// Number of resized memory blocks.
+    pub reallocations: u64,
+    /// Number of freed memory blocks.
+    pub deallocations: u64,
+    /// Number of allocations and reallocations that failed, including the ones rejected by the
+    /// memory limit.
+    pub failed_allocations: u64,
+    /// Total number of bytes requested by allocations and growing reallocations.
+    pub allocated_bytes: u64,
+    /// Number of allocations and reallocations in each size class.
+    ///
+    /// Every entry is the upper bound (a power of two) of block sizes of the class, and the number
+    /// of blocks of that size requested from the allocator. Only non-empty classes are listed,
+    /// in ascending order.
+    pub size_classes: Vec<(usize, u64)>,
+    /// Memory allocated by Lua functions, sorted by the number of allocated bytes in descending
+    /// order.
+    ///
+    /// Empty unless sampling of the running functions is enabled.
+    pub functions: Vec<FunctionMemoryStats>,
+}
+
+/// Memory allocated while a Lua function was running, as seen by sampling.
+#[derive(Clone, Debug)]
+pub struct FunctionMemoryStats {
+    /// A "printable" version of the source of the function.
+    pub source: String,
+    /// The line number where the definition of the function starts.
+    pub line_defined: Option<i32>,
+    /// A reasonable name of the function (from the last sample).
+    pub name: Option<String>,
+    /// Number of allocations and reallocations attributed to the function.
+    pub allocations: u64,
+    /// Number of bytes allocated by the function.
+    pub allocated_bytes: u64,
+}
+
+// Statistics collected by the allocator
+pub(crate) struct AllocStats {
+    pub(crate) sample_interval: Option<u32>,
+    peak_memory: isize,
+    allocations: u64,
+    reallocations: u64,
+    deallocations: u64,
+    failed_allocations: u64,
+    allocated_bytes: u64,
+    size_classes: [u64; SIZE_CLASSES],
+    // Counters at the moment of the last sample
+    sampled_allocations: u64,
+    sampled_bytes: u64,
+    functions: HashMap<(String, c_int), FunctionMemoryStats>,
+}
+
+impl AllocStats {
+    pub(crate) fn new(used_memory: isize, sample_interval: Option<u32>) -> Self {
+        AllocStats {
+            sample_interval: sample_interval.filter(|&n| n > 0),
+            peak_memory: used_memory,
+            allocations: 0,
+            reallocations: 0,
+            deallocations: 0,
+            failed_allocations: 0,
+            allocated_bytes: 0,
+            size_classes: [0; SIZE_CLASSES],
+            sampled_allocations: 0,
+            sampled_bytes: 0,
+            functions: HashMap::new(),
+        }
+    }
+
+    // Records a successful allocation (`osize` is `None`) or reallocation of a block.
+    pub(crate) fn record_alloc(&mut self, osize: Option<usize>, nsize: usize, used_memory: isize) {
+        match osize {
+            None => {
+                self.allocations += 1;
+                self.allocated_bytes += nsize as u64;
+            }
+            Some(osize) => {
+                self.reallocations += 1;
+                self.allocated_bytes += nsize.saturating_sub(osize) as u64;
+            }
+        }
+        // Index of the smallest power of two not less than `nsize`
+        let class = mem::size_of::<usize>() * 8 - (nsize - 1).leading_zeros() as usize;
+        self.size_classes[class] += 1;
+        self.peak_memory = self.peak_memory.max(used_memory);
+    }
+
+    pub(crate) fn record_failure(&mut self) {
+        self.failed_allocations += 1;
+    }
+
+    pub(crate) fn record_free(&mut self) {
+        self.deallocations += 1;
+    }
+
+    // Attributes the allocations made since the last sample to the running Lua function.
+    pub(crate) unsafe fn sample(&mut self, state: *mut lua_State) {
+        let allocations = self.allocations + self.reallocations;
+        let new_allocations = allocations - self.sampled_allocations;
+        let new_bytes = self.allocated_bytes - self.sampled_bytes;
+        self.sampled_allocations = allocations;
+        self.sampled_bytes = self.allocated_bytes;
+        if new_allocations == 0 {
+            return;
+        }
+
+        let mut ar: lua_Debug = mem::zeroed();
+        if ffi::lua_getstack(state, 0, &mut ar) == 0
+            || ffi::lua_getinfo(state, cstr!("Sn"), &mut ar) == 0
+        {
+            return;
+        }
+        let source = ptr_to_str(ar.short_src.as_ptr())
+            .map(bytes_to_string)
+            .unwrap_or_default();
+        let line_defined = ar.linedefined;
+        let name = ptr_to_str(ar.name).map(bytes_to_string);
+
+        let function = (self.functions)
+            .entry((source, line_defined))
+            .or_insert_with_key(|(source, line_defined)| FunctionMemoryStats {
+                source: source.clone(),
+                line_defined: Some(*line_defined).filter(|&l| l > 0),
+                name: None,
+                allocations: 0,
+                allocated_bytes: 0,
+            });
+        if name.is_some() {
+            function.name = name;
+        }
+        function.allocations += new_allocations;
+        function.allocated_bytes += new_bytes;
+    }
+
+    pub(crate) fn snapshot(&self, used_memory: isize) -> MemoryStats {
+        let size_classes = (self.size_classes.iter().enumerate())
+            .filter(|&(_, &count)| count > 0)
+            .map(|(i, &count)| (1usize.checked_shl(i as u32).unwrap_or(usize::MAX), count))
+            .collect();
+        let mut functions = self.functions.values().cloned().collect::<Vec<_>>();
+        functions.sort_by_key(|f| Reverse(f.allocated_bytes));
+
+        MemoryStats {
+            used_memory: used_memory as usize,
+            peak_memory: self.peak_memory.max(used_memory) as usize,
+            allocations: self.allocations,
+            reallocations: self.reallocations,
+            deallocations: self.deallocations,
+            failed_allocations: self.failed_allocations,
+            allocated_bytes: self.allocated_bytes,
+            size_classes,
+            functions,
+        }
+    }
+}
@@ -2,7 +2,7 @@ use std::iter::FromIterator;
 use std::ops::{Deref, DerefMut};
 use std::result::Result as StdResult;
 
-use crate::error::Result;
+use crate::error::{Error, Result};
 use crate::lua::Lua;
 use crate::value::{FromLua, FromLuaMulti, MultiValue, Nil, ToLua, ToLuaMulti};
 
@@ -36,6 +36,11 @@ impl<'lua, T: FromLua<'lua>> FromLuaMulti<'lua> for T {
     fn from_lua_multi(mut values: MultiValue<'lua>, lua: &'lua Lua) -> Result<Self> {
         Ok(T::from_lua(values.pop_front().unwrap_or(Nil), lua)?)
     }
+
+    fn from_lua_args(mut values: MultiValue<'lua>, pos: usize, lua: &'lua Lua) -> Result<Self> {
+        T::from_lua(values.pop_front().unwrap_or(Nil), lua)
+            .map_err(|err| Error::bad_argument(pos, err))
+    }
 }
 
 impl<'lua> ToLuaMulti<'lua> for MultiValue<'lua> {
@@ -135,6 +140,15 @@ impl<'lua, T: FromLua<'lua>> FromLuaMulti<'lua> for Variadic<T> {
             .collect::<Result<Vec<T>>>()
             .map(Variadic)
     }
+
+    fn from_lua_args(values: MultiValue<'lua>, pos: usize, lua: &'lua Lua) -> Result<Self> {
+        values
+            .into_iter()
+            .enumerate()
+            .map(|(i, e)| T::from_lua(e, lua).map_err(|err| Error::bad_argument(pos + i, err)))
+            .collect::<Result<Vec<T>>>()
+            .map(Variadic)
+    }
 }
 
 macro_rules! impl_tuple {
@@ -179,6 +193,19 @@ macro_rules! impl_tuple {
                 let $last = FromLuaMulti::from_lua_multi(values, lua)?;
                 Ok(($(FromLua::from_lua($name, lua)?,)* $last,))
             }
+
+            #[allow(unused_mut)]
+            #[allow(non_snake_case)]
+            fn from_lua_args(mut values: MultiValue<'lua>, mut pos: usize, lua: &'lua Lua) -> Result<Self> {
+                $(
+                    let $name = values.pop_front().unwrap_or(Nil);
+                    let $name = FromLua::from_lua($name, lua)
+                        .map_err(|err| Error::bad_argument(pos, err))?;
+                    pos += 1;
+                )*
+                let $last = FromLuaMulti::from_lua_args(values, pos, lua)?;
+                Ok(($($name,)* $last,))
+            }
         }
     );
 }
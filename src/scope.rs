@@ -10,12 +10,12 @@ use crate::ffi;
 use crate::function::Function;
 use crate::lua::Lua;
 use crate::types::{Callback, LuaRef, MaybeSend};
-use crate::userdata::{AnyUserData, MetaMethod, UserData, UserDataMethods};
+use crate::userdata::{AnyUserData, MetaMethod, UserData, UserDataFields, UserDataMethods};
 use crate::util::{
     assert_stack, init_userdata_metatable, protect_lua_closure, push_string, push_userdata,
     take_userdata, StackGuard,
 };
-use crate::value::{FromLuaMulti, MultiValue, ToLuaMulti, Value};
+use crate::value::{FromLua, FromLuaMulti, MultiValue, ToLua, ToLuaMulti, Value};
 
 #[cfg(feature = "async")]
 use {
@@ -71,7 +71,7 @@ impl<'lua, 'scope> Scope<'lua, 'scope> {
         // scope, and owned inside the callback itself.
         unsafe {
             self.create_callback(Box::new(move |lua, args| {
-                func(lua, A::from_lua_multi(args, lua)?)?.to_lua_multi(lua)
+                func(lua, lua.callback_args(args, 1)?)?.to_lua_multi(lua)
             }))
         }
     }
@@ -124,7 +124,7 @@ impl<'lua, 'scope> Scope<'lua, 'scope> {
     {
         unsafe {
             self.create_async_callback(Box::new(move |lua, args| {
-                let args = match A::from_lua_multi(args, lua) {
+                let args = match lua.async_callback_args(args, 1) {
                     Ok(args) => args,
                     Err(e) => return Box::pin(future::err(e)),
                 };
@@ -272,13 +272,15 @@ impl<'lua, 'scope> Scope<'lua, 'scope> {
             }
         }
 
+        let mut ud_fields = NonStaticUserDataFields::default();
         let mut ud_methods = NonStaticUserDataMethods::default();
+        T::add_fields(&mut ud_fields);
         T::add_methods(&mut ud_methods);
 
         unsafe {
             let lua = self.lua;
             let _sg = StackGuard::new(lua.state);
-            assert_stack(lua.state, 6);
+            assert_stack(lua.state, 11);
 
             push_userdata(lua.state, ())?;
             #[cfg(any(feature = "lua54", feature = "lua53"))]
@@ -295,6 +297,7 @@ impl<'lua, 'scope> Scope<'lua, 'scope> {
             protect_lua_closure(lua.state, 0, 1, move |state| {
                 ffi::lua_newtable(state);
             })?;
+            let metatable_index = ffi::lua_absindex(lua.state, -1);
 
             for (k, m) in ud_methods.meta_methods {
                 push_string(lua.state, k.name())?;
@@ -305,8 +308,44 @@ impl<'lua, 'scope> Scope<'lua, 'scope> {
                 })?;
             }
 
-            if ud_methods.methods.is_empty() {
-                init_userdata_metatable::<()>(lua.state, -1, None)?;
+            let mut extra_tables_count = 0;
+
+            let field_getters_index = if ud_fields.field_getters.is_empty() {
+                None
+            } else {
+                protect_lua_closure(lua.state, 0, 1, |state| {
+                    ffi::lua_newtable(state);
+                })?;
+                for (k, m) in ud_fields.field_getters {
+                    push_string(lua.state, &k)?;
+                    lua.push_value(Value::Function(wrap_method(self, data.clone(), m)?))?;
+                    protect_lua_closure(lua.state, 3, 1, |state| {
+                        ffi::lua_rawset(state, -3);
+                    })?;
+                }
+                extra_tables_count += 1;
+                Some(ffi::lua_absindex(lua.state, -1))
+            };
+
+            let field_setters_index = if ud_fields.field_setters.is_empty() {
+                None
+            } else {
+                protect_lua_closure(lua.state, 0, 1, |state| {
+                    ffi::lua_newtable(state);
+                })?;
+                for (k, m) in ud_fields.field_setters {
+                    push_string(lua.state, &k)?;
+                    lua.push_value(Value::Function(wrap_method(self, data.clone(), m)?))?;
+                    protect_lua_closure(lua.state, 3, 1, |state| {
+                        ffi::lua_rawset(state, -3);
+                    })?;
+                }
+                extra_tables_count += 1;
+                Some(ffi::lua_absindex(lua.state, -1))
+            };
+
+            let methods_index = if ud_methods.methods.is_empty() {
+                None
             } else {
                 protect_lua_closure(lua.state, 0, 1, |state| {
                     ffi::lua_newtable(state);
@@ -318,10 +357,18 @@ impl<'lua, 'scope> Scope<'lua, 'scope> {
                         ffi::lua_rawset(state, -3);
                     })?;
                 }
+                extra_tables_count += 1;
+                Some(ffi::lua_absindex(lua.state, -1))
+            };
 
-                init_userdata_metatable::<()>(lua.state, -2, Some(-1))?;
-                ffi::lua_pop(lua.state, 1);
-            }
+            init_userdata_metatable::<()>(
+                lua.state,
+                metatable_index,
+                field_getters_index,
+                field_setters_index,
+                methods_index,
+            )?;
+            ffi::lua_pop(lua.state, extra_tables_count);
 
             ffi::lua_setmetatable(lua.state, -2);
 
@@ -462,7 +509,7 @@ impl<'lua, T: UserData> UserDataMethods<'lua, T> for NonStaticUserDataMethods<'l
         self.methods.push((
             name.as_ref().to_vec(),
             NonStaticMethod::Method(Box::new(move |lua, ud, args| {
-                method(lua, ud, A::from_lua_multi(args, lua)?)?.to_lua_multi(lua)
+                method(lua, ud, lua.callback_args(args, 2)?)?.to_lua_multi(lua)
             })),
         ));
     }
@@ -477,7 +524,7 @@ impl<'lua, T: UserData> UserDataMethods<'lua, T> for NonStaticUserDataMethods<'l
         self.methods.push((
             name.as_ref().to_vec(),
             NonStaticMethod::MethodMut(Box::new(move |lua, ud, args| {
-                method(lua, ud, A::from_lua_multi(args, lua)?)?.to_lua_multi(lua)
+                method(lua, ud, lua.callback_args(args, 2)?)?.to_lua_multi(lua)
             })),
         ));
     }
@@ -507,7 +554,7 @@ impl<'lua, T: UserData> UserDataMethods<'lua, T> for NonStaticUserDataMethods<'l
         self.methods.push((
             name.as_ref().to_vec(),
             NonStaticMethod::Function(Box::new(move |lua, args| {
-                function(lua, A::from_lua_multi(args, lua)?)?.to_lua_multi(lua)
+                function(lua, lua.callback_args(args, 1)?)?.to_lua_multi(lua)
             })),
         ));
     }
@@ -522,7 +569,7 @@ impl<'lua, T: UserData> UserDataMethods<'lua, T> for NonStaticUserDataMethods<'l
         self.methods.push((
             name.as_ref().to_vec(),
             NonStaticMethod::FunctionMut(Box::new(move |lua, args| {
-                function(lua, A::from_lua_multi(args, lua)?)?.to_lua_multi(lua)
+                function(lua, lua.callback_args(args, 1)?)?.to_lua_multi(lua)
             })),
         ));
     }
@@ -551,7 +598,7 @@ impl<'lua, T: UserData> UserDataMethods<'lua, T> for NonStaticUserDataMethods<'l
         self.meta_methods.push((
             meta,
             NonStaticMethod::Method(Box::new(move |lua, ud, args| {
-                method(lua, ud, A::from_lua_multi(args, lua)?)?.to_lua_multi(lua)
+                method(lua, ud, lua.callback_args(args, 2)?)?.to_lua_multi(lua)
             })),
         ));
     }
@@ -565,7 +612,7 @@ impl<'lua, T: UserData> UserDataMethods<'lua, T> for NonStaticUserDataMethods<'l
         self.meta_methods.push((
             meta,
             NonStaticMethod::MethodMut(Box::new(move |lua, ud, args| {
-                method(lua, ud, A::from_lua_multi(args, lua)?)?.to_lua_multi(lua)
+                method(lua, ud, lua.callback_args(args, 2)?)?.to_lua_multi(lua)
             })),
         ));
     }
@@ -579,7 +626,7 @@ impl<'lua, T: UserData> UserDataMethods<'lua, T> for NonStaticUserDataMethods<'l
         self.meta_methods.push((
             meta,
             NonStaticMethod::Function(Box::new(move |lua, args| {
-                function(lua, A::from_lua_multi(args, lua)?)?.to_lua_multi(lua)
+                function(lua, lua.callback_args(args, 1)?)?.to_lua_multi(lua)
             })),
         ));
     }
@@ -593,7 +640,80 @@ impl<'lua, T: UserData> UserDataMethods<'lua, T> for NonStaticUserDataMethods<'l
         self.meta_methods.push((
             meta,
             NonStaticMethod::FunctionMut(Box::new(move |lua, args| {
-                function(lua, A::from_lua_multi(args, lua)?)?.to_lua_multi(lua)
+                function(lua, lua.callback_args(args, 1)?)?.to_lua_multi(lua)
+            })),
+        ));
+    }
+}
+
+struct NonStaticUserDataFields<'lua, T: UserData> {
+    field_getters: Vec<(Vec<u8>, NonStaticMethod<'lua, T>)>,
+    field_setters: Vec<(Vec<u8>, NonStaticMethod<'lua, T>)>,
+}
+
+impl<'lua, T: UserData> Default for NonStaticUserDataFields<'lua, T> {
+    fn default() -> NonStaticUserDataFields<'lua, T> {
+        NonStaticUserDataFields {
+            field_getters: Vec::new(),
+            field_setters: Vec::new(),
+        }
+    }
+}
+
+impl<'lua, T: UserData> UserDataFields<'lua, T> for NonStaticUserDataFields<'lua, T> {
+    fn add_field_method_get<S, R, M>(&mut self, name: &S, method: M)
+    where
+        S: AsRef<[u8]> + ?Sized,
+        R: ToLua<'lua>,
+        M: 'static + MaybeSend + Fn(&'lua Lua, &T) -> Result<R>,
+    {
+        self.field_getters.push((
+            name.as_ref().to_vec(),
+            NonStaticMethod::Method(Box::new(move |lua, ud, _| {
+                method(lua, ud)?.to_lua_multi(lua)
+            })),
+        ));
+    }
+
+    fn add_field_method_set<S, A, M>(&mut self, name: &S, mut method: M)
+    where
+        S: AsRef<[u8]> + ?Sized,
+        A: FromLua<'lua>,
+        M: 'static + MaybeSend + FnMut(&'lua Lua, &mut T, A) -> Result<()>,
+    {
+        self.field_setters.push((
+            name.as_ref().to_vec(),
+            NonStaticMethod::MethodMut(Box::new(move |lua, ud, args| {
+                method(lua, ud, A::from_lua_multi(args, lua)?)?.to_lua_multi(lua)
+            })),
+        ));
+    }
+
+    fn add_field_function_get<S, R, F>(&mut self, name: &S, function: F)
+    where
+        S: AsRef<[u8]> + ?Sized,
+        R: ToLua<'lua>,
+        F: 'static + MaybeSend + Fn(&'lua Lua, AnyUserData<'lua>) -> Result<R>,
+    {
+        self.field_getters.push((
+            name.as_ref().to_vec(),
+            NonStaticMethod::Function(Box::new(move |lua, args| {
+                function(lua, AnyUserData::from_lua_multi(args, lua)?)?.to_lua_multi(lua)
+            })),
+        ));
+    }
+
+    fn add_field_function_set<S, A, F>(&mut self, name: &S, mut function: F)
+    where
+        S: AsRef<[u8]> + ?Sized,
+        A: FromLua<'lua>,
+        F: 'static + MaybeSend + FnMut(&'lua Lua, AnyUserData<'lua>, A) -> Result<()>,
+    {
+        self.field_setters.push((
+            name.as_ref().to_vec(),
+            NonStaticMethod::FunctionMut(Box::new(move |lua, args| {
+                let (ud, val) = <(AnyUserData, A)>::from_lua_multi(args, lua)?;
+                function(lua, ud, val)?.to_lua_multi(lua)
             })),
         ));
     }
@@ -0,0 +1,684 @@
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::fmt;
+use std::os::raw::c_void;
+use std::rc::Rc;
+use std::string::String as StdString;
+
+use serde::de::{self, IntoDeserializer};
+
+use super::ser::EnumTagging;
+use super::LuaSerdeExt;
+use crate::error::{Error, Result};
+use crate::table::{Table, TablePairs, TableSequence};
+use crate::types::Integer;
+use crate::value::Value;
+
+/// A struct for deserializing Lua values into Rust values.
+pub struct Deserializer<'lua> {
+    value: Value<'lua>,
+    context: Context<'lua>,
+}
+
+/// A struct with options to change default deserializer behavior.
+#[derive(Debug, Clone, Copy)]
+#[non_exhaustive]
+pub struct Options {
+    /// If true, an attempt to deserialize values of unsupported types (functions, threads,
+    /// userdata and non-null lightuserdata) returns an error.
+    /// Otherwise such values are deserialized as unit.
+    ///
+    /// Values of unsupported types are always accepted in ignored fields.
+    ///
+    /// Default: **true**
+    pub deny_unsupported_types: bool,
+
+    /// Representation of enum variants. Should match the one used for serialization.
+    ///
+    /// Default: [`EnumTagging::External`]
+    ///
+    /// [`EnumTagging::External`]: ../ser/enum.EnumTagging.html#variant.External
+    pub enum_tagging: EnumTagging,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Options {
+    /// Returns a new instance of `Options` with default parameters.
+    pub fn new() -> Self {
+        Options {
+            deny_unsupported_types: true,
+            enum_tagging: EnumTagging::External,
+        }
+    }
+
+    /// Sets [`deny_unsupported_types`] option.
+    ///
+    /// [`deny_unsupported_types`]: #structfield.deny_unsupported_types
+    pub fn deny_unsupported_types(mut self, enabled: bool) -> Self {
+        self.deny_unsupported_types = enabled;
+        self
+    }
+
+    /// Sets [`enum_tagging`] option.
+    ///
+    /// [`enum_tagging`]: #structfield.enum_tagging
+    pub fn enum_tagging(mut self, tagging: EnumTagging) -> Self {
+        self.enum_tagging = tagging;
+        self
+    }
+}
+
+// State shared between all deserializers created for a single top-level value.
+#[derive(Default)]
+struct State {
+    // Tables currently being deserialized, to detect cycles
+    visited: HashSet<*const c_void>,
+    // Set when an error has been tagged with the path to the failed value
+    error_path_set: bool,
+}
+
+// A key in the chain of tables leading to the value being deserialized.
+struct PathNode<'lua> {
+    parent: Option<Rc<PathNode<'lua>>>,
+    key: Value<'lua>,
+}
+
+#[derive(Clone)]
+struct Context<'lua> {
+    options: Options,
+    state: Rc<RefCell<State>>,
+    path: Option<Rc<PathNode<'lua>>>,
+}
+
+impl<'lua> Deserializer<'lua> {
+    /// Creates a new Lua Deserializer for the `Value` with default options.
+    pub fn new(value: Value<'lua>) -> Self {
+        Self::new_with_options(value, Options::default())
+    }
+
+    /// Creates a new Lua Deserializer for the `Value` with custom options.
+    pub fn new_with_options(value: Value<'lua>, options: Options) -> Self {
+        Deserializer {
+            value,
+            context: Context {
+                options,
+                state: Rc::new(RefCell::new(State::default())),
+                path: None,
+            },
+        }
+    }
+}
+
+impl<'lua> Context<'lua> {
+    // Deserializes `value` found under `key` of the current table, tagging conversion errors
+    // with the path to the innermost value that failed.
+    fn deserialize_child<T, F>(&self, key: Value<'lua>, value: Value<'lua>, f: F) -> Result<T>
+    where
+        F: FnOnce(Deserializer<'lua>) -> Result<T>,
+    {
+        let from = value.type_name();
+        let path = Rc::new(PathNode {
+            parent: self.path.clone(),
+            key,
+        });
+        let deserializer = Deserializer {
+            value,
+            context: Context {
+                options: self.options,
+                state: self.state.clone(),
+                path: Some(path.clone()),
+            },
+        };
+
+        f(deserializer).map_err(|err| {
+            let mut state = self.state.borrow_mut();
+            match err {
+                Error::FromLuaConversionError { to, message, .. } if !state.error_path_set => {
+                    state.error_path_set = true;
+                    let message = match message {
+                        Some(message) => format!("{}: {}", path, message),
+                        None => path.to_string(),
+                    };
+                    Error::FromLuaConversionError {
+                        from,
+                        to,
+                        message: Some(message),
+                    }
+                }
+                err => err,
+            }
+        })
+    }
+
+    fn enter_table(&self, table: &Table<'lua>) -> Result<TableGuard> {
+        let ptr = table.0.to_pointer();
+        if !self.state.borrow_mut().visited.insert(ptr) {
+            return Err(de::Error::custom("recursive table detected"));
+        }
+        Ok(TableGuard {
+            state: self.state.clone(),
+            ptr,
+        })
+    }
+}
+
+// Removes the table from the visited set when it's fully deserialized.
+struct TableGuard {
+    state: Rc<RefCell<State>>,
+    ptr: *const c_void,
+}
+
+impl Drop for TableGuard {
+    fn drop(&mut self) {
+        self.state.borrow_mut().visited.remove(&self.ptr);
+    }
+}
+
+impl<'lua> fmt::Display for PathNode<'lua> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(parent) = &self.parent {
+            write!(fmt, "{}", parent)?;
+        }
+        match &self.key {
+            Value::String(s) => {
+                let s = StdString::from_utf8_lossy(s.as_bytes());
+                let is_ident = s.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+                    && !s.starts_with(|c: char| c.is_ascii_digit())
+                    && !s.is_empty();
+                if !is_ident {
+                    write!(fmt, "[{:?}]", s)
+                } else if self.parent.is_some() {
+                    write!(fmt, ".{}", s)
+                } else {
+                    write!(fmt, "{}", s)
+                }
+            }
+            Value::Integer(i) => write!(fmt, "[{}]", i),
+            Value::Number(n) => write!(fmt, "[{}]", n),
+            Value::Boolean(b) => write!(fmt, "[{}]", b),
+            key => write!(fmt, "[<{}>]", key.type_name()),
+        }
+    }
+}
+
+fn is_array<'lua>(table: &Table<'lua>) -> Result<bool> {
+    let lua = table.0.lua;
+    if table.get_metatable() == Some(lua.array_metatable()) {
+        return Ok(true);
+    }
+
+    let len = table.raw_len();
+    if len == 0 {
+        return Ok(false);
+    }
+    let mut count = 0;
+    for pair in table.clone().pairs::<Value, Value>() {
+        match pair?.0 {
+            Value::Integer(i) if i >= 1 && i <= len => count += 1,
+            _ => return Ok(false),
+        }
+    }
+    Ok(count == len)
+}
+
+fn unexpected<'a>(value: &'a Value) -> de::Unexpected<'a> {
+    match *value {
+        Value::Nil => de::Unexpected::Unit,
+        Value::Boolean(b) => de::Unexpected::Bool(b),
+        Value::Integer(i) => de::Unexpected::Signed(i),
+        Value::Number(n) => de::Unexpected::Float(n),
+        Value::Table(_) => de::Unexpected::Map,
+        ref value => de::Unexpected::Other(value.type_name()),
+    }
+}
+
+impl<'lua> Deserializer<'lua> {
+    fn deserialize_integer<'de, V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: de::Visitor<'de>,
+    {
+        match self.value {
+            // Lua 5.1 and LuaJIT (and Lua 5.3+ with float keys) can store integers as numbers
+            Value::Number(n)
+                if n.fract() == 0.0 && n >= Integer::MIN as f64 && n < 2f64.powi(63) =>
+            {
+                visitor.visit_i64(n as i64)
+            }
+            _ => de::Deserializer::deserialize_any(self, visitor),
+        }
+    }
+
+    fn deserialize_table_seq<'de, V>(self, table: Table<'lua>, visitor: V) -> Result<V::Value>
+    where
+        V: de::Visitor<'de>,
+    {
+        let _guard = self.context.enter_table(&table)?;
+        let len = table.raw_len() as usize;
+        let mut deserializer = SeqDeserializer {
+            seq: table.sequence_values(),
+            index: 0,
+            context: self.context,
+        };
+        let value = visitor.visit_seq(&mut deserializer)?;
+        if deserializer.seq.next().is_some() {
+            return Err(de::Error::invalid_length(
+                len,
+                &"fewer elements in the table",
+            ));
+        }
+        Ok(value)
+    }
+
+    fn deserialize_table_map<'de, V>(self, table: Table<'lua>, visitor: V) -> Result<V::Value>
+    where
+        V: de::Visitor<'de>,
+    {
+        let _guard = self.context.enter_table(&table)?;
+        let mut deserializer = MapDeserializer {
+            pairs: table.pairs(),
+            value: None,
+            context: self.context,
+        };
+        visitor.visit_map(&mut deserializer)
+    }
+}
+
+macro_rules! lua_deserialize_integer {
+    ($($name:ident),*) => {
+        $(
+            #[inline]
+            fn $name<V>(self, visitor: V) -> Result<V::Value>
+            where
+                V: de::Visitor<'de>,
+            {
+                self.deserialize_integer(visitor)
+            }
+        )*
+    };
+}
+
+impl<'lua, 'de> de::Deserializer<'de> for Deserializer<'lua> {
+    type Error = Error;
+
+    fn deserialize_any<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: de::Visitor<'de>,
+    {
+        match self.value {
+            Value::Nil => visitor.visit_unit(),
+            Value::Boolean(b) => visitor.visit_bool(b),
+            Value::Integer(i) => visitor.visit_i64(i),
+            Value::Number(n) => visitor.visit_f64(n),
+            Value::String(ref s) => match s.to_str() {
+                Ok(s) => visitor.visit_str(s),
+                Err(_) => visitor.visit_bytes(s.as_bytes()),
+            },
+            Value::Table(ref t) => {
+                let t = t.clone();
+                if is_array(&t)? {
+                    self.deserialize_table_seq(t, visitor)
+                } else {
+                    self.deserialize_table_map(t, visitor)
+                }
+            }
+            Value::LightUserData(ud) if ud.0.is_null() => visitor.visit_unit(),
+            ref value if self.context.options.deny_unsupported_types => Err(de::Error::custom(
+                format!("unsupported value type `{}`", value.type_name()),
+            )),
+            _ => visitor.visit_unit(),
+        }
+    }
+
+    lua_deserialize_integer!(
+        deserialize_i8,
+        deserialize_i16,
+        deserialize_i32,
+        deserialize_i64,
+        deserialize_i128,
+        deserialize_u8,
+        deserialize_u16,
+        deserialize_u32,
+        deserialize_u64,
+        deserialize_u128
+    );
+
+    #[inline]
+    fn deserialize_bytes<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: de::Visitor<'de>,
+    {
+        match self.value {
+            Value::String(ref s) => visitor.visit_bytes(s.as_bytes()),
+            _ => self.deserialize_any(visitor),
+        }
+    }
+
+    #[inline]
+    fn deserialize_byte_buf<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: de::Visitor<'de>,
+    {
+        self.deserialize_bytes(visitor)
+    }
+
+    #[inline]
+    fn deserialize_option<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: de::Visitor<'de>,
+    {
+        match self.value {
+            Value::Nil => visitor.visit_none(),
+            Value::LightUserData(ud) if ud.0.is_null() => visitor.visit_none(),
+            _ => visitor.visit_some(self),
+        }
+    }
+
+    #[inline]
+    fn deserialize_newtype_struct<V>(self, _name: &'static str, visitor: V) -> Result<V::Value>
+    where
+        V: de::Visitor<'de>,
+    {
+        visitor.visit_newtype_struct(self)
+    }
+
+    #[inline]
+    fn deserialize_seq<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: de::Visitor<'de>,
+    {
+        match self.value {
+            Value::Table(ref t) => {
+                let t = t.clone();
+                self.deserialize_table_seq(t, visitor)
+            }
+            _ => self.deserialize_any(visitor),
+        }
+    }
+
+    #[inline]
+    fn deserialize_tuple<V>(self, _len: usize, visitor: V) -> Result<V::Value>
+    where
+        V: de::Visitor<'de>,
+    {
+        self.deserialize_seq(visitor)
+    }
+
+    #[inline]
+    fn deserialize_tuple_struct<V>(
+        self,
+        _name: &'static str,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value>
+    where
+        V: de::Visitor<'de>,
+    {
+        self.deserialize_seq(visitor)
+    }
+
+    #[inline]
+    fn deserialize_map<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: de::Visitor<'de>,
+    {
+        match self.value {
+            Value::Table(ref t) => {
+                let t = t.clone();
+                self.deserialize_table_map(t, visitor)
+            }
+            _ => self.deserialize_any(visitor),
+        }
+    }
+
+    #[inline]
+    fn deserialize_struct<V>(
+        self,
+        _name: &'static str,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value>
+    where
+        V: de::Visitor<'de>,
+    {
+        self.deserialize_map(visitor)
+    }
+
+    fn deserialize_enum<V>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value>
+    where
+        V: de::Visitor<'de>,
+    {
+        // The variant content can refer back to the table, so keep it in the visited set until
+        // the content is deserialized
+        let _guard = match self.value {
+            Value::Table(ref t) => Some(self.context.enter_table(t)?),
+            _ => None,
+        };
+        let (variant, content) = match self.value {
+            Value::String(ref s) => (s.to_str()?.to_owned(), None),
+            Value::Table(ref t) => match self.context.options.enum_tagging {
+                EnumTagging::External => {
+                    let mut pairs = t.clone().pairs::<Value, Value>();
+                    let (key, value) = match pairs.next() {
+                        Some(pair) => pair?,
+                        None => {
+                            return Err(de::Error::invalid_value(
+                                de::Unexpected::Map,
+                                &"map with a single key",
+                            ))
+                        }
+                    };
+                    if pairs.next().is_some() {
+                        return Err(de::Error::invalid_value(
+                            de::Unexpected::Map,
+                            &"map with a single key",
+                        ));
+                    }
+                    let variant = match key {
+                        Value::String(ref s) => s.to_str()?.to_owned(),
+                        ref key => return Err(de::Error::invalid_type(unexpected(key), &"string")),
+                    };
+                    (variant, Some((key, value)))
+                }
+                EnumTagging::Adjacent { tag, content } => {
+                    let variant = match t.raw_get::<_, Value>(tag)? {
+                        Value::String(s) => s.to_str()?.to_owned(),
+                        Value::Nil => return Err(de::Error::missing_field(tag)),
+                        ref value => {
+                            return Err(de::Error::invalid_type(unexpected(value), &"string"))
+                        }
+                    };
+                    match t.raw_get::<_, Value>(content)? {
+                        Value::Nil => (variant, None),
+                        value => {
+                            let key = Value::String(t.0.lua.create_string(content)?);
+                            (variant, Some((key, value)))
+                        }
+                    }
+                }
+            },
+            ref value => return Err(de::Error::invalid_type(unexpected(value), &"string or map")),
+        };
+
+        visitor.visit_enum(EnumDeserializer {
+            variant,
+            content,
+            context: self.context,
+        })
+    }
+
+    #[inline]
+    fn deserialize_ignored_any<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: de::Visitor<'de>,
+    {
+        visitor.visit_unit()
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool f32 f64 char str string unit unit_struct identifier
+    }
+}
+
+struct SeqDeserializer<'lua> {
+    seq: TableSequence<'lua, Value<'lua>>,
+    index: Integer,
+    context: Context<'lua>,
+}
+
+impl<'lua, 'de> de::SeqAccess<'de> for SeqDeserializer<'lua> {
+    type Error = Error;
+
+    fn next_element_seed<T>(&mut self, seed: T) -> Result<Option<T::Value>>
+    where
+        T: de::DeserializeSeed<'de>,
+    {
+        match self.seq.next() {
+            Some(value) => {
+                let value = value?;
+                self.index += 1;
+                let key = Value::Integer(self.index);
+                self.context
+                    .deserialize_child(key, value, |de| seed.deserialize(de))
+                    .map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+struct MapDeserializer<'lua> {
+    pairs: TablePairs<'lua, Value<'lua>, Value<'lua>>,
+    value: Option<(Value<'lua>, Value<'lua>)>,
+    context: Context<'lua>,
+}
+
+impl<'lua, 'de> de::MapAccess<'de> for MapDeserializer<'lua> {
+    type Error = Error;
+
+    fn next_key_seed<K>(&mut self, seed: K) -> Result<Option<K::Value>>
+    where
+        K: de::DeserializeSeed<'de>,
+    {
+        match self.pairs.next() {
+            Some(pair) => {
+                let (key, value) = pair?;
+                self.value = Some((key.clone(), value));
+                let deserializer = Deserializer {
+                    value: key,
+                    context: self.context.clone(),
+                };
+                seed.deserialize(deserializer).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<T>(&mut self, seed: T) -> Result<T::Value>
+    where
+        T: de::DeserializeSeed<'de>,
+    {
+        match self.value.take() {
+            Some((key, value)) => self
+                .context
+                .deserialize_child(key, value, |de| seed.deserialize(de)),
+            None => Err(de::Error::custom("value is missing")),
+        }
+    }
+}
+
+struct EnumDeserializer<'lua> {
+    variant: StdString,
+    content: Option<(Value<'lua>, Value<'lua>)>,
+    context: Context<'lua>,
+}
+
+impl<'lua, 'de> de::EnumAccess<'de> for EnumDeserializer<'lua> {
+    type Error = Error;
+    type Variant = VariantDeserializer<'lua>;
+
+    fn variant_seed<T>(self, seed: T) -> Result<(T::Value, Self::Variant)>
+    where
+        T: de::DeserializeSeed<'de>,
+    {
+        let variant = self.variant.into_deserializer();
+        let variant_access = VariantDeserializer {
+            content: self.content,
+            context: self.context,
+        };
+        seed.deserialize(variant).map(|v| (v, variant_access))
+    }
+}
+
+struct VariantDeserializer<'lua> {
+    content: Option<(Value<'lua>, Value<'lua>)>,
+    context: Context<'lua>,
+}
+
+impl<'lua, 'de> de::VariantAccess<'de> for VariantDeserializer<'lua> {
+    type Error = Error;
+
+    fn unit_variant(self) -> Result<()> {
+        match self.content {
+            None => Ok(()),
+            Some((_, Value::LightUserData(ud))) if ud.0.is_null() => Ok(()),
+            Some((_, ref value)) => {
+                Err(de::Error::invalid_type(unexpected(value), &"unit variant"))
+            }
+        }
+    }
+
+    fn newtype_variant_seed<T>(self, seed: T) -> Result<T::Value>
+    where
+        T: de::DeserializeSeed<'de>,
+    {
+        match self.content {
+            Some((key, value)) => self
+                .context
+                .deserialize_child(key, value, |de| seed.deserialize(de)),
+            None => Err(de::Error::invalid_type(
+                de::Unexpected::UnitVariant,
+                &"newtype variant",
+            )),
+        }
+    }
+
+    fn tuple_variant<V>(self, _len: usize, visitor: V) -> Result<V::Value>
+    where
+        V: de::Visitor<'de>,
+    {
+        match self.content {
+            Some((key, value)) => self.context.deserialize_child(key, value, |de| {
+                de::Deserializer::deserialize_seq(de, visitor)
+            }),
+            None => Err(de::Error::invalid_type(
+                de::Unexpected::UnitVariant,
+                &"tuple variant",
+            )),
+        }
+    }
+
+    fn struct_variant<V>(self, _fields: &'static [&'static str], visitor: V) -> Result<V::Value>
+    where
+        V: de::Visitor<'de>,
+    {
+        match self.content {
+            Some((key, value)) => self.context.deserialize_child(key, value, |de| {
+                de::Deserializer::deserialize_map(de, visitor)
+            }),
+            None => Err(de::Error::invalid_type(
+                de::Unexpected::UnitVariant,
+                &"struct variant",
+            )),
+        }
+    }
+}
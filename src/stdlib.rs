@@ -50,6 +50,9 @@ impl StdLib {
     /// (unsafe) All standard libraries
     pub const ALL: StdLib = StdLib(u32::MAX);
     /// The safe subset of the standard libraries
+    ///
+    /// Excludes only the libraries that can break memory safety (`debug` and `ffi`). The `io`,
+    /// `os` and `package` libraries are included and give access to the host system.
     pub const ALL_SAFE: StdLib = StdLib((1 << 30) - 1);
 
     pub fn contains(self, lib: Self) -> bool {
@@ -5,12 +5,34 @@ use crate::error::{Error, Result};
 use crate::ffi;
 use crate::function::Function;
 use crate::types::{Integer, LuaRef};
-use crate::util::{assert_stack, protect_lua, protect_lua_closure, StackGuard};
+use crate::util::{assert_stack, check_stack, protect_lua, protect_lua_closure, StackGuard};
 use crate::value::{FromLua, FromLuaMulti, Nil, ToLua, ToLuaMulti, Value};
 
+#[cfg(not(feature = "send"))]
+use crate::types::OwnedRef;
+
 #[cfg(feature = "async")]
 use {futures_core::future::LocalBoxFuture, futures_util::future};
 
+// Maximum number of entries written by a single protected call in bulk operations
+const BULK_WRITE_SIZE: c_int = 128;
+
+// Calls `lua_next` in a protected way with the table and the key on top of the stack. Leaves the
+// table, the next key and its value on the stack, or only the table if there are no more pairs.
+unsafe fn table_next(state: *mut ffi::lua_State) -> Result<bool> {
+    unsafe extern "C" fn next(state: *mut ffi::lua_State) -> c_int {
+        if ffi::lua_next(state, -2) != 0 {
+            3
+        } else {
+            1
+        }
+    }
+
+    let top = ffi::lua_gettop(state);
+    protect_lua(state, 2, next)?;
+    Ok(ffi::lua_gettop(state) > top)
+}
+
 /// Handle to an internal Lua table.
 #[derive(Clone, Debug)]
 pub struct Table<'lua>(pub(crate) LuaRef<'lua>);
@@ -70,6 +92,71 @@ impl<'lua> Table<'lua> {
         }
     }
 
+    /// Sets multiple key-value pairs in the table.
+    ///
+    /// This is equivalent to calling [`set`] for every pair, but much faster, as the pairs are
+    /// written in batches using a single protected call for each batch instead of one per key.
+    /// Later pairs override earlier pairs with the same key.
+    ///
+    /// This might invoke the `__newindex` metamethod. On error, the pairs preceding the failed one
+    /// may have been already written.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mlua::{Lua, Result};
+    /// # fn main() -> Result<()> {
+    /// # let lua = Lua::new();
+    /// let config = lua.create_table()?;
+    /// config.set_many(vec![("host", "localhost"), ("port", "8080")])?;
+    ///
+    /// assert_eq!(config.get::<_, String>("port")?, "8080");
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`set`]: #method.set
+    pub fn set_many<K, V, I>(&self, pairs: I) -> Result<()>
+    where
+        K: ToLua<'lua>,
+        V: ToLua<'lua>,
+        I: IntoIterator<Item = (K, V)>,
+    {
+        let lua = self.0.lua;
+        let mut pairs = pairs.into_iter();
+        unsafe {
+            let _sg = StackGuard::new(lua.state);
+            // Conversions require at least LUA_MINSTACK slots to be available on top of the batch
+            check_stack(lua.state, 2 * BULK_WRITE_SIZE + 4 + ffi::LUA_MINSTACK)?;
+
+            lua.push_ref(&self.0);
+            loop {
+                let mut n = 0;
+                for (key, value) in pairs.by_ref().take(BULK_WRITE_SIZE as usize) {
+                    lua.push_value(key.to_lua(lua)?)?;
+                    lua.push_value(value.to_lua(lua)?)?;
+                    n += 1;
+                }
+                if n == 0 {
+                    return Ok(());
+                }
+
+                // The table is kept on the stack for the next batch
+                protect_lua_closure(lua.state, 2 * n + 1, 1, |state| {
+                    for i in 0..n {
+                        ffi::lua_pushvalue(state, 2 * i + 2);
+                        ffi::lua_pushvalue(state, 2 * i + 3);
+                        ffi::lua_settable(state, 1);
+                    }
+                    ffi::lua_settop(state, 1);
+                })?;
+                if n < BULK_WRITE_SIZE {
+                    return Ok(());
+                }
+            }
+        }
+    }
+
     /// Gets the value associated to `key` from the table.
     ///
     /// If no value is associated to `key`, returns the `nil` value.
@@ -261,6 +348,98 @@ impl<'lua> Table<'lua> {
         }
     }
 
+    /// Appends a value to the back of the table, without invoking metamethods.
+    pub fn raw_push<V: ToLua<'lua>>(&self, value: V) -> Result<()> {
+        let lua = self.0.lua;
+        let value = value.to_lua(lua)?;
+        unsafe {
+            let _sg = StackGuard::new(lua.state);
+            assert_stack(lua.state, 5);
+
+            lua.push_ref(&self.0);
+            lua.push_value(value)?;
+
+            protect_lua_closure(lua.state, 2, 0, |state| {
+                let len = ffi::lua_rawlen(state, -2) as Integer;
+                ffi::lua_rawseti(state, -2, len + 1);
+            })
+        }
+    }
+
+    /// Removes the last element from the table and returns it, without invoking metamethods.
+    ///
+    /// Returns `nil` if the table is empty. The element is removed only if it was successfully
+    /// converted to `V`.
+    pub fn raw_pop<V: FromLua<'lua>>(&self) -> Result<V> {
+        let lua = self.0.lua;
+        let len = self.raw_len();
+        if len == 0 {
+            return V::from_lua(Nil, lua);
+        }
+
+        let value = V::from_lua(self.raw_get(len)?, lua)?;
+        self.raw_set(len, Nil)?;
+        Ok(value)
+    }
+
+    /// Appends all values of an iterator to the back of the table, without invoking metamethods.
+    ///
+    /// The values are written in batches, using a single protected call for each batch. On error,
+    /// the values preceding the failed one may have been already appended.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mlua::{Lua, Result};
+    /// # fn main() -> Result<()> {
+    /// # let lua = Lua::new();
+    /// let list = lua.create_sequence_from(vec![1, 2])?;
+    /// list.extend_sequence(3..=5)?;
+    ///
+    /// assert_eq!(list.raw_len(), 5);
+    /// assert_eq!(list.get::<_, i32>(5)?, 5);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn extend_sequence<V, I>(&self, values: I) -> Result<()>
+    where
+        V: ToLua<'lua>,
+        I: IntoIterator<Item = V>,
+    {
+        let lua = self.0.lua;
+        let mut values = values.into_iter();
+        unsafe {
+            let _sg = StackGuard::new(lua.state);
+            // Conversions require at least LUA_MINSTACK slots to be available on top of the batch
+            check_stack(lua.state, BULK_WRITE_SIZE + 3 + ffi::LUA_MINSTACK)?;
+
+            lua.push_ref(&self.0);
+            loop {
+                let mut n = 0;
+                for value in values.by_ref().take(BULK_WRITE_SIZE as usize) {
+                    lua.push_value(value.to_lua(lua)?)?;
+                    n += 1;
+                }
+                if n == 0 {
+                    return Ok(());
+                }
+
+                // The table is kept on the stack for the next batch
+                protect_lua_closure(lua.state, n + 1, 1, |state| {
+                    let len = ffi::lua_rawlen(state, 1) as Integer;
+                    for i in 1..=n {
+                        ffi::lua_pushvalue(state, i + 1);
+                        ffi::lua_rawseti(state, 1, len + i as Integer);
+                    }
+                    ffi::lua_settop(state, 1);
+                })?;
+                if n < BULK_WRITE_SIZE {
+                    return Ok(());
+                }
+            }
+        }
+    }
+
     /// Removes a key from the table.
     ///
     /// If `key` is an integer, mlua shifts down the elements from `table[key+1]`,
@@ -297,6 +476,29 @@ impl<'lua> Table<'lua> {
         }
     }
 
+    /// Removes all key-value pairs from the table, without invoking metamethods.
+    ///
+    /// The metatable of the table is kept.
+    pub fn clear(&self) -> Result<()> {
+        let lua = self.0.lua;
+        unsafe {
+            let _sg = StackGuard::new(lua.state);
+            assert_stack(lua.state, 4);
+
+            lua.push_ref(&self.0);
+            protect_lua_closure(lua.state, 1, 0, |state| {
+                // Assigning nil to existing fields is allowed during traversal
+                ffi::lua_pushnil(state);
+                while ffi::lua_next(state, -2) != 0 {
+                    ffi::lua_pop(state, 1);
+                    ffi::lua_pushvalue(state, -1);
+                    ffi::lua_pushnil(state);
+                    ffi::lua_rawset(state, -4);
+                }
+            })
+        }
+    }
+
     /// Returns the result of the Lua `#` operator.
     ///
     /// This might invoke the `__len` metamethod. Use the [`raw_len`] method if that is not desired.
@@ -402,6 +604,98 @@ impl<'lua> Table<'lua> {
         }
     }
 
+    /// Iterates over the pairs of the table, calling `f` for each key-value pair.
+    ///
+    /// This works like [`pairs`], but does not consume the table and is faster: the traversal
+    /// state is kept on the Lua stack, so no references are created to track the current key.
+    /// The iteration stops at the first error returned by `f` or by a conversion.
+    ///
+    /// As with [`pairs`], assigning to new fields of the table while the iteration is in
+    /// progress is not allowed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mlua::{Lua, Result, Table};
+    /// # fn main() -> Result<()> {
+    /// # let lua = Lua::new();
+    /// let scores: Table = lua.load("{ alice = 10, bob = 20 }").eval()?;
+    ///
+    /// let mut total = 0;
+    /// scores.for_each(|_: String, score: i64| {
+    ///     total += score;
+    ///     Ok(())
+    /// })?;
+    /// assert_eq!(total, 30);
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`pairs`]: #method.pairs
+    pub fn for_each<K, V, F>(&self, mut f: F) -> Result<()>
+    where
+        K: FromLua<'lua>,
+        V: FromLua<'lua>,
+        F: FnMut(K, V) -> Result<()>,
+    {
+        let lua = self.0.lua;
+        unsafe {
+            let _sg = StackGuard::new(lua.state);
+            check_stack(lua.state, 6)?;
+
+            lua.push_ref(&self.0);
+            ffi::lua_pushnil(lua.state);
+            while table_next(lua.state)? {
+                ffi::lua_pushvalue(lua.state, -2);
+                let key = lua.pop_value();
+                let value = lua.pop_value();
+                f(K::from_lua(key, lua)?, V::from_lua(value, lua)?)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns an iterator over the pairs of the table, without consuming it.
+    ///
+    /// This works like [`pairs`], but reuses a single reference to track the current key during
+    /// the whole iteration instead of creating a new one on every step. As with [`for_each`], the
+    /// iteration stops at the first error, including conversion errors.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mlua::{Lua, Result};
+    /// # fn main() -> Result<()> {
+    /// # let lua = Lua::new();
+    /// let table = lua.create_sequence_from(vec![1, 2, 3])?;
+    ///
+    /// let sum = table
+    ///     .pairs_ref::<i64, i64>()
+    ///     .map(|pair| pair.map(|(_, v)| v))
+    ///     .sum::<Result<i64>>()?;
+    /// assert_eq!(sum, 6);
+    /// assert_eq!(table.raw_len(), 3);
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`pairs`]: #method.pairs
+    /// [`for_each`]: #method.for_each
+    pub fn pairs_ref<K: FromLua<'lua>, V: FromLua<'lua>>(&self) -> TablePairsRef<'_, 'lua, K, V> {
+        let lua = self.0.lua;
+        let key = unsafe {
+            let _sg = StackGuard::new(lua.state);
+            assert_stack(lua.state, 1);
+            ffi::lua_pushnil(lua.state);
+            lua.pop_ref()
+        };
+        TablePairsRef {
+            table: &self.0,
+            key: Some(key),
+            _phantom: PhantomData,
+        }
+    }
+
     /// Consume this table and return an iterator over all values in the sequence part of the table.
     ///
     /// The iterator will yield all values `t[1]`, `t[2]`, and so on, until a `nil` value is
@@ -450,6 +744,19 @@ impl<'lua> Table<'lua> {
             _phantom: PhantomData,
         }
     }
+
+    /// Converts this table into an owned handle, which is not bound to the lifetime of `Lua`.
+    ///
+    /// See [`OwnedTable`] for details.
+    ///
+    /// Not available with `feature = "send"`.
+    ///
+    /// [`OwnedTable`]: struct.OwnedTable.html
+    #[cfg(not(feature = "send"))]
+    #[cfg_attr(docsrs, doc(cfg(not(feature = "send"))))]
+    pub fn into_owned(self) -> Result<OwnedTable> {
+        Ok(OwnedTable(self.0.lua.make_owned_ref(&self.0)?))
+    }
 }
 
 impl<'lua> PartialEq for Table<'lua> {
@@ -465,6 +772,36 @@ impl<'lua> AsRef<Table<'lua>> for Table<'lua> {
     }
 }
 
+/// Owned handle to an internal Lua table.
+///
+/// Unlike [`Table`], it is not bound to the lifetime of [`Lua`]. Use [`to_ref`] to get a borrowed
+/// [`Table`] back. See [`OwnedFunction`] for details about owned handles.
+///
+/// [`Table`]: struct.Table.html
+/// [`Lua`]: struct.Lua.html
+/// [`to_ref`]: #method.to_ref
+/// [`OwnedFunction`]: struct.OwnedFunction.html
+#[cfg(not(feature = "send"))]
+#[cfg_attr(docsrs, doc(cfg(not(feature = "send"))))]
+#[derive(Debug)]
+pub struct OwnedTable(pub(crate) OwnedRef);
+
+#[cfg(not(feature = "send"))]
+impl OwnedTable {
+    /// Returns a borrowed handle to the table.
+    pub fn to_ref(&self) -> Table<'_> {
+        Table(self.0.to_ref())
+    }
+
+    /// Creates a new owned handle to the same table.
+    ///
+    /// Unlike cloning a borrowed handle, this stores the table in a new registry slot, which can
+    /// fail with a memory error.
+    pub fn try_clone(&self) -> Result<Self> {
+        Ok(OwnedTable(self.0.try_clone()?))
+    }
+}
+
 /// An extension trait for `Table`s that provides a variety of convenient functionality.
 pub trait TableExt<'lua> {
     /// Gets the function associated to `key` from the table and executes it,
@@ -643,6 +980,68 @@ where
     }
 }
 
+/// An iterator over the pairs of a Lua table, borrowing the table.
+///
+/// This struct is created by the [`Table::pairs_ref`] method.
+///
+/// [`Table::pairs_ref`]: struct.Table.html#method.pairs_ref
+pub struct TablePairsRef<'a, 'lua, K, V> {
+    table: &'a LuaRef<'lua>,
+    // Reference to the current key, updated in place on every step
+    key: Option<LuaRef<'lua>>,
+    _phantom: PhantomData<(K, V)>,
+}
+
+impl<'a, 'lua, K, V> Iterator for TablePairsRef<'a, 'lua, K, V>
+where
+    K: FromLua<'lua>,
+    V: FromLua<'lua>,
+{
+    type Item = Result<(K, V)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let key = self.key.as_ref()?;
+        let lua = self.table.lua;
+
+        let res = unsafe {
+            let _sg = StackGuard::new(lua.state);
+            assert_stack(lua.state, 6);
+
+            lua.push_ref(self.table);
+            lua.push_ref(key);
+            table_next(lua.state).map(|next| {
+                if next {
+                    ffi::lua_pushvalue(lua.state, -2);
+                    lua.replace_ref(key);
+                    let value = lua.pop_value();
+                    let key = lua.pop_value();
+                    Some((key, value))
+                } else {
+                    None
+                }
+            })
+        };
+
+        match res {
+            Ok(Some((key, value))) => {
+                let res = K::from_lua(key, lua).and_then(|key| Ok((key, V::from_lua(value, lua)?)));
+                if res.is_err() {
+                    self.key = None;
+                }
+                Some(res)
+            }
+            Ok(None) => {
+                self.key = None;
+                None
+            }
+            Err(e) => {
+                self.key = None;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
 /// An iterator over the sequence part of a Lua table.
 ///
 /// This struct is created by the [`Table::sequence_values`] method.
@@ -1,8 +1,12 @@
+use std::cell::RefCell;
 use std::os::raw::c_int;
+use std::sync::Arc;
 
 use crate::error::{Error, Result};
 use crate::ffi;
-use crate::types::LuaRef;
+use crate::hook::{Debug, HookTriggers, Traceback};
+use crate::lua::Lua;
+use crate::types::{LuaRef, MaybeSend};
 use crate::util::{
     assert_stack, check_stack, error_traceback, pop_error, protect_lua_closure, StackGuard,
 };
@@ -12,13 +16,12 @@ use crate::value::{FromLuaMulti, MultiValue, ToLuaMulti};
 use {
     crate::{
         error::ExternalError,
-        lua::{AsyncPollPending, Lua, WAKER_REGISTRY_KEY},
+        lua::{AsyncPollPending, WAKER_REGISTRY_KEY},
         util::{get_gc_userdata, push_gc_userdata},
         value::Value,
     },
     futures_core::{future::Future, stream::Stream},
     std::{
-        cell::RefCell,
         marker::PhantomData,
         os::raw::c_void,
         pin::Pin,
@@ -120,6 +123,10 @@ impl<'lua> Thread<'lua> {
                 return Err(Error::CoroutineInactive);
             }
 
+            // Coroutines created before any hook was set have no hook installed
+            if ffi::lua_gethook(thread_state).is_none() {
+                lua.install_thread_hook(-1);
+            }
             ffi::lua_pop(lua.state, 1);
 
             let nargs = args.len() as c_int;
@@ -134,6 +141,7 @@ impl<'lua> Thread<'lua> {
             let mut nresults = 0;
 
             let ret = ffi::lua_resume(thread_state, lua.state, nargs, &mut nresults as *mut c_int);
+            lua.resume_warn_panic();
             if ret != ffi::LUA_OK && ret != ffi::LUA_YIELD {
                 protect_lua_closure(lua.state, 0, 0, |_| {
                     error_traceback(thread_state);
@@ -176,6 +184,85 @@ impl<'lua> Thread<'lua> {
         }
     }
 
+    /// Captures a traceback of the call stack of this thread.
+    ///
+    /// This is useful to find out where a suspended coroutine is waiting, for example a coroutine
+    /// that has yielded or an async task that has stalled. A thread that is not started or has
+    /// finished has no frames.
+    ///
+    /// See [`Lua::traceback`] for more details.
+    ///
+    /// [`Lua::traceback`]: struct.Lua.html#method.traceback
+    pub fn traceback(&self) -> Traceback {
+        let lua = self.0.lua;
+        unsafe {
+            let _sg = StackGuard::new(lua.state);
+            assert_stack(lua.state, 1);
+
+            lua.push_ref(&self.0);
+            let thread_state = ffi::lua_tothread(lua.state, -1);
+            Traceback::new(thread_state, None, 0)
+        }
+    }
+
+    /// Sets a 'hook' function that will periodically be called as Lua code executes within this
+    /// thread.
+    ///
+    /// Works like [`Lua::set_hook`], but the hook is called only for events of this thread, which
+    /// makes it possible to step through a single coroutine or to limit the number of instructions
+    /// executed by it. Hooks set with [`Lua::set_hook`] or [`Lua::add_hook`] and the interrupt
+    /// function are still called for this thread as well, whether they are set before or after
+    /// calling this function.
+    ///
+    /// The hook is installed on the thread right away, so it is called whether the thread is
+    /// resumed from Rust or from Lua code. Calling this function again replaces the hook previously
+    /// set for this thread. The hook is removed once the thread is garbage collected.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use mlua::{Error, HookTriggers, Lua, Result, Thread};
+    /// # fn main() -> Result<()> {
+    /// let lua = Lua::new();
+    /// let thread: Thread = lua.load("coroutine.create(function() while true do end end)").eval()?;
+    ///
+    /// let mut instructions = 0;
+    /// thread.set_hook(HookTriggers {
+    ///     every_nth_instruction: Some(100), ..Default::default()
+    /// }, move |_lua, _debug| {
+    ///     instructions += 100;
+    ///     if instructions >= 10000 {
+    ///         return Err(Error::RuntimeError("too many instructions".to_string()));
+    ///     }
+    ///     Ok(())
+    /// })?;
+    ///
+    /// assert!(thread.resume::<_, ()>(()).is_err());
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`Lua::set_hook`]: struct.Lua.html#method.set_hook
+    /// [`Lua::add_hook`]: struct.Lua.html#method.add_hook
+    pub fn set_hook<F>(&self, triggers: HookTriggers, callback: F) -> Result<()>
+    where
+        F: 'static + MaybeSend + FnMut(&Lua, Debug) -> Result<()>,
+    {
+        let lua = self.0.lua;
+        unsafe { lua.set_thread_hook(self, Some((triggers, Arc::new(RefCell::new(callback))))) }
+    }
+
+    /// Removes any hook previously set by `set_hook` for this thread.
+    ///
+    /// This function has no effect if a hook was not previously set.
+    pub fn remove_hook(&self) {
+        let lua = self.0.lua;
+        // Removing a hook does not allocate, so cannot fail
+        unsafe {
+            let _ = lua.set_thread_hook(self, None);
+        }
+    }
+
     /// Converts Thread to an AsyncThread which implements Future and Stream traits.
     ///
     /// `args` are passed as arguments to the thread function for first call.
@@ -1,14 +1,20 @@
-use std::cell::RefCell;
+use std::any::{Any, TypeId};
+use std::cell::{Cell, Ref, RefCell, RefMut, UnsafeCell};
+use std::collections::HashMap;
+use std::ops::{Deref, DerefMut};
 use std::os::raw::{c_int, c_void};
 use std::sync::{Arc, Mutex};
 use std::{fmt, mem, ptr};
 
+#[cfg(not(feature = "send"))]
+use std::marker::PhantomData;
+
 #[cfg(feature = "async")]
 use futures_core::future::LocalBoxFuture;
 
 use crate::error::Result;
 use crate::ffi;
-use crate::hook::Debug;
+use crate::hook::{Debug, InterruptAction};
 use crate::lua::Lua;
 use crate::util::{assert_stack, StackGuard};
 use crate::value::MultiValue;
@@ -31,6 +37,11 @@ pub(crate) type AsyncCallback<'lua, 'a> =
 
 pub(crate) type HookCallback = Arc<RefCell<dyn FnMut(&Lua, Debug) -> Result<()>>>;
 
+pub(crate) type InterruptCallback = Arc<RefCell<dyn FnMut(&Lua) -> InterruptAction>>;
+
+#[cfg(feature = "lua54")]
+pub(crate) type WarnCallback = Arc<dyn Fn(&Lua, &[u8], bool)>;
+
 #[cfg(feature = "send")]
 pub trait MaybeSend: Send {}
 #[cfg(feature = "send")]
@@ -93,6 +104,19 @@ pub(crate) struct LuaRef<'lua> {
     pub(crate) index: c_int,
 }
 
+impl<'lua> LuaRef<'lua> {
+    // Returns a pointer identifying the referenced object (see `lua_topointer`).
+    pub(crate) fn to_pointer(&self) -> *const c_void {
+        let lua = self.lua;
+        unsafe {
+            let _sg = StackGuard::new(lua.state);
+            assert_stack(lua.state, 1);
+            lua.push_ref(self);
+            ffi::lua_topointer(lua.state, -1)
+        }
+    }
+}
+
 impl<'lua> fmt::Debug for LuaRef<'lua> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "Ref({})", self.index)
@@ -123,3 +147,181 @@ impl<'lua> PartialEq for LuaRef<'lua> {
         }
     }
 }
+
+// A reference to a Lua value stored in the registry, which keeps the Lua state alive.
+// Used by the owned handles (`OwnedTable`, ...) that are not bound to the lifetime of `Lua`.
+// Not available with `feature = "send"` for the same reason as cloning `Lua`.
+#[cfg(not(feature = "send"))]
+pub(crate) struct OwnedRef {
+    pub(crate) lua: Lua,
+    pub(crate) registry_id: c_int,
+    // Unlike `Lua`, owned references must not be sent to other threads, as the state is shared
+    pub(crate) _no_send: PhantomData<*const ()>,
+}
+
+#[cfg(not(feature = "send"))]
+impl OwnedRef {
+    // Returns a reference bound to the `Lua` instance kept by this owned reference.
+    pub(crate) fn to_ref(&self) -> LuaRef<'_> {
+        self.to_lua_ref(&self.lua)
+    }
+
+    // Returns a reference bound to the given `Lua` instance, which must share the main state.
+    pub(crate) fn to_lua_ref<'lua>(&self, lua: &'lua Lua) -> LuaRef<'lua> {
+        unsafe {
+            let _sg = StackGuard::new(lua.state);
+            assert_stack(lua.state, 1);
+            lua.push_owned_ref(self);
+            lua.pop_ref()
+        }
+    }
+
+    // Stores the referenced value in a new registry slot, which can fail on a memory error.
+    pub(crate) fn try_clone(&self) -> Result<Self> {
+        self.lua.make_owned_ref(&self.to_ref())
+    }
+}
+
+#[cfg(not(feature = "send"))]
+impl fmt::Debug for OwnedRef {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "OwnedRef({})", self.registry_id)
+    }
+}
+
+#[cfg(not(feature = "send"))]
+impl Drop for OwnedRef {
+    fn drop(&mut self) {
+        self.lua.drop_owned_ref(self.registry_id)
+    }
+}
+
+// Storage for application data objects attached to a Lua state, see `Lua::set_app_data`.
+//
+// Every value is kept in its own `RefCell` to allow borrowing different types independently.
+// The container itself can be modified only when there are no active borrows, which is tracked by
+// the `borrow` counter.
+#[derive(Default)]
+pub(crate) struct AppData {
+    container: UnsafeCell<HashMap<TypeId, RefCell<Box<dyn Any>>>>,
+    borrow: Cell<usize>,
+}
+
+impl AppData {
+    pub(crate) fn insert<T: 'static + MaybeSend>(&self, data: T) -> Option<T> {
+        assert!(
+            self.borrow.get() == 0,
+            "cannot mutably borrow app data container"
+        );
+        let container = unsafe { &mut *self.container.get() };
+        container
+            .insert(TypeId::of::<T>(), RefCell::new(Box::new(data)))
+            .map(|data| *mlua_expect!(data.into_inner().downcast(), "app data type mismatch"))
+    }
+
+    pub(crate) fn borrow<T: 'static>(&self) -> Option<AppDataRef<'_, T>> {
+        let container = unsafe { &*self.container.get() };
+        let data = container
+            .get(&TypeId::of::<T>())?
+            .try_borrow()
+            .expect("cannot borrow app data: already mutably borrowed");
+        self.borrow.set(self.borrow.get() + 1);
+        Some(AppDataRef {
+            data: Ref::map(data, |data| {
+                mlua_expect!(data.downcast_ref(), "app data type mismatch")
+            }),
+            borrow: &self.borrow,
+        })
+    }
+
+    pub(crate) fn borrow_mut<T: 'static>(&self) -> Option<AppDataRefMut<'_, T>> {
+        let container = unsafe { &*self.container.get() };
+        let data = container
+            .get(&TypeId::of::<T>())?
+            .try_borrow_mut()
+            .expect("cannot mutably borrow app data: already borrowed");
+        self.borrow.set(self.borrow.get() + 1);
+        Some(AppDataRefMut {
+            data: RefMut::map(data, |data| {
+                mlua_expect!(data.downcast_mut(), "app data type mismatch")
+            }),
+            borrow: &self.borrow,
+        })
+    }
+
+    pub(crate) fn remove<T: 'static>(&self) -> Option<T> {
+        assert!(
+            self.borrow.get() == 0,
+            "cannot mutably borrow app data container"
+        );
+        let container = unsafe { &mut *self.container.get() };
+        let data = container.remove(&TypeId::of::<T>())?.into_inner();
+        Some(*mlua_expect!(data.downcast(), "app data type mismatch"))
+    }
+}
+
+/// A wrapper type for an immutably borrowed value from an app data container.
+///
+/// This type is similar to [`Ref`].
+///
+/// [`Ref`]: https://doc.rust-lang.org/std/cell/struct.Ref.html
+pub struct AppDataRef<'a, T: ?Sized + 'a> {
+    data: Ref<'a, T>,
+    borrow: &'a Cell<usize>,
+}
+
+impl<T: ?Sized> Drop for AppDataRef<'_, T> {
+    fn drop(&mut self) {
+        self.borrow.set(self.borrow.get() - 1);
+    }
+}
+
+impl<T: ?Sized> Deref for AppDataRef<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.data
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for AppDataRef<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        (**self).fmt(f)
+    }
+}
+
+/// A wrapper type for a mutably borrowed value from an app data container.
+///
+/// This type is similar to [`RefMut`].
+///
+/// [`RefMut`]: https://doc.rust-lang.org/std/cell/struct.RefMut.html
+pub struct AppDataRefMut<'a, T: ?Sized + 'a> {
+    data: RefMut<'a, T>,
+    borrow: &'a Cell<usize>,
+}
+
+impl<T: ?Sized> Drop for AppDataRefMut<'_, T> {
+    fn drop(&mut self) {
+        self.borrow.set(self.borrow.get() - 1);
+    }
+}
+
+impl<T: ?Sized> Deref for AppDataRefMut<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.data
+    }
+}
+
+impl<T: ?Sized> DerefMut for AppDataRefMut<'_, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.data
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for AppDataRefMut<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        (**self).fmt(f)
+    }
+}
@@ -12,6 +12,9 @@ use crate::types::{LuaRef, MaybeSend};
 use crate::util::{assert_stack, get_userdata, StackGuard};
 use crate::value::{FromLua, FromLuaMulti, ToLua, ToLuaMulti, Value};
 
+#[cfg(not(feature = "send"))]
+use crate::types::OwnedRef;
+
 /// Kinds of metamethods that can be overridden.
 ///
 /// Currently, this mechanism does not allow overriding the `__gc` metamethod, since there is
@@ -291,6 +294,63 @@ pub trait UserDataMethods<'lua, T: UserData> {
         F: 'static + MaybeSend + FnMut(&'lua Lua, A) -> Result<R>;
 }
 
+/// Field registry for [`UserData`] implementors.
+///
+/// [`UserData`]: trait.UserData.html
+pub trait UserDataFields<'lua, T: UserData> {
+    /// Add a regular field getter as a method which accepts a `&T` as the parameter.
+    ///
+    /// Regular field getters are implemented by overriding the `__index` metamethod and returning the
+    /// accessed field. This allows them to be used with the expected `userdata.field` syntax.
+    ///
+    /// If `add_meta_method` is used to set the `__index` metamethod, the `__index` metamethod will
+    /// be used as a fall-back if no regular field or method are found.
+    fn add_field_method_get<S, R, M>(&mut self, name: &S, method: M)
+    where
+        S: ?Sized + AsRef<[u8]>,
+        R: ToLua<'lua>,
+        M: 'static + MaybeSend + Fn(&'lua Lua, &T) -> Result<R>;
+
+    /// Add a regular field setter as a method which accepts a `&mut T` as the first parameter.
+    ///
+    /// Regular field setters are implemented by overriding the `__newindex` metamethod and setting the
+    /// accessed field. This allows them to be used with the expected `userdata.field = value` syntax.
+    ///
+    /// If `add_meta_method` is used to set the `__newindex` metamethod, the `__newindex` metamethod will
+    /// be used as a fall-back if no regular field is found.
+    fn add_field_method_set<S, A, M>(&mut self, name: &S, method: M)
+    where
+        S: ?Sized + AsRef<[u8]>,
+        A: FromLua<'lua>,
+        M: 'static + MaybeSend + FnMut(&'lua Lua, &mut T, A) -> Result<()>;
+
+    /// Add a regular field getter as a function which accepts a generic [`AnyUserData`] of type `T`
+    /// argument.
+    ///
+    /// Prefer to use [`add_field_method_get`] as it is easier to use.
+    ///
+    /// [`AnyUserData`]: struct.AnyUserData.html
+    /// [`add_field_method_get`]: #method.add_field_method_get
+    fn add_field_function_get<S, R, F>(&mut self, name: &S, function: F)
+    where
+        S: ?Sized + AsRef<[u8]>,
+        R: ToLua<'lua>,
+        F: 'static + MaybeSend + Fn(&'lua Lua, AnyUserData<'lua>) -> Result<R>;
+
+    /// Add a regular field setter as a function which accepts a generic [`AnyUserData`] of type `T`
+    /// first argument.
+    ///
+    /// Prefer to use [`add_field_method_set`] as it is easier to use.
+    ///
+    /// [`AnyUserData`]: struct.AnyUserData.html
+    /// [`add_field_method_set`]: #method.add_field_method_set
+    fn add_field_function_set<S, A, F>(&mut self, name: &S, function: F)
+    where
+        S: ?Sized + AsRef<[u8]>,
+        A: FromLua<'lua>,
+        F: 'static + MaybeSend + FnMut(&'lua Lua, AnyUserData<'lua>, A) -> Result<()>;
+}
+
 /// Trait for custom userdata types.
 ///
 /// By implementing this trait, a struct becomes eligible for use inside Lua code. Implementations
@@ -352,10 +412,45 @@ pub trait UserDataMethods<'lua, T: UserData> {
 /// # }
 /// ```
 ///
+/// Fields are exposed to Lua through getters and setters by implementing `add_fields` (refer to
+/// [`UserDataFields`] for more information):
+///
+/// ```
+/// # use mlua::{Lua, Result, UserData, UserDataFields};
+/// # fn main() -> Result<()> {
+/// # let lua = Lua::new();
+/// struct MyUserData(i32);
+///
+/// impl UserData for MyUserData {
+///     fn add_fields<'lua, F: UserDataFields<'lua, Self>>(fields: &mut F) {
+///         fields.add_field_method_get("val", |_, this| Ok(this.0));
+///
+///         fields.add_field_method_set("val", |_, this, val: i32| {
+///             this.0 = val;
+///             Ok(())
+///         });
+///     }
+/// }
+///
+/// lua.globals().set("myobject", MyUserData(123))?;
+///
+/// lua.load(r#"
+///     assert(myobject.val == 123)
+///     myobject.val = 130
+///     assert(myobject.val == 130)
+/// "#).exec()?;
+/// # Ok(())
+/// # }
+/// ```
+///
 /// [`ToLua`]: trait.ToLua.html
 /// [`FromLua`]: trait.FromLua.html
+/// [`UserDataFields`]: trait.UserDataFields.html
 /// [`UserDataMethods`]: trait.UserDataMethods.html
 pub trait UserData: Sized {
+    /// Adds custom fields specific to this userdata.
+    fn add_fields<'lua, F: UserDataFields<'lua, Self>>(_fields: &mut F) {}
+
     /// Adds custom methods and operators specific to this userdata.
     fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(_methods: &mut M) {}
 }
@@ -541,6 +636,19 @@ impl<'lua> AnyUserData<'lua> {
             }
         }
     }
+
+    /// Converts this userdata into an owned handle, which is not bound to the lifetime of `Lua`.
+    ///
+    /// See [`OwnedAnyUserData`] for details.
+    ///
+    /// Not available with `feature = "send"`.
+    ///
+    /// [`OwnedAnyUserData`]: struct.OwnedAnyUserData.html
+    #[cfg(not(feature = "send"))]
+    #[cfg_attr(docsrs, doc(cfg(not(feature = "send"))))]
+    pub fn into_owned(self) -> Result<OwnedAnyUserData> {
+        Ok(OwnedAnyUserData(self.0.lua.make_owned_ref(&self.0)?))
+    }
 }
 
 impl<'lua> PartialEq for AnyUserData<'lua> {
@@ -555,3 +663,33 @@ impl<'lua> AsRef<AnyUserData<'lua>> for AnyUserData<'lua> {
         self
     }
 }
+
+/// Owned handle to an internal Lua userdata.
+///
+/// Unlike [`AnyUserData`], it is not bound to the lifetime of [`Lua`]. Use [`to_ref`] to get a
+/// borrowed [`AnyUserData`] back. See [`OwnedFunction`] for details about owned handles.
+///
+/// [`AnyUserData`]: struct.AnyUserData.html
+/// [`Lua`]: struct.Lua.html
+/// [`to_ref`]: #method.to_ref
+/// [`OwnedFunction`]: struct.OwnedFunction.html
+#[cfg(not(feature = "send"))]
+#[cfg_attr(docsrs, doc(cfg(not(feature = "send"))))]
+#[derive(Debug)]
+pub struct OwnedAnyUserData(pub(crate) OwnedRef);
+
+#[cfg(not(feature = "send"))]
+impl OwnedAnyUserData {
+    /// Returns a borrowed handle to the userdata.
+    pub fn to_ref(&self) -> AnyUserData<'_> {
+        AnyUserData(self.0.to_ref())
+    }
+
+    /// Creates a new owned handle to the same userdata.
+    ///
+    /// Unlike cloning a borrowed handle, this stores the userdata in a new registry slot, which can
+    /// fail with a memory error.
+    pub fn try_clone(&self) -> Result<Self> {
+        Ok(OwnedAnyUserData(self.0.try_clone()?))
+    }
+}
@@ -9,6 +9,8 @@ use std::{mem, ptr, slice};
 
 use crate::error::{Error, Result};
 use crate::ffi;
+use crate::hook::Traceback;
+use crate::lua::relax_memory_limit;
 
 lazy_static::lazy_static! {
     // The capacity must(!) be greater than number of stored keys
@@ -22,14 +24,14 @@ pub unsafe fn assert_stack(state: *mut ffi::lua_State, amount: c_int) {
     // when there is a way to be confident about stack safety and test it, this could be enabled
     // only when `cfg!(debug_assertions)` is true.
     mlua_assert!(
-        ffi::lua_checkstack(state, amount) != 0,
+        relax_memory_limit(state, || ffi::lua_checkstack(state, amount)) != 0,
         "out of stack space"
     );
 }
 
 // Checks that Lua has enough free stack space and returns `Error::StackError` on failure.
 pub unsafe fn check_stack(state: *mut ffi::lua_State, amount: c_int) -> Result<()> {
-    if ffi::lua_checkstack(state, amount) == 0 {
+    if relax_memory_limit(state, || ffi::lua_checkstack(state, amount)) == 0 {
         Err(Error::StackError)
     } else {
         Ok(())
@@ -79,8 +81,10 @@ pub unsafe fn protect_lua(
 ) -> Result<()> {
     let stack_start = ffi::lua_gettop(state) - nargs;
 
-    ffi::lua_pushcfunction(state, error_traceback);
-    ffi::lua_pushcfunction(state, f);
+    relax_memory_limit(state, || {
+        ffi::lua_pushcfunction(state, error_traceback);
+        ffi::lua_pushcfunction(state, f);
+    });
     if nargs > 0 {
         ffi::lua_rotate(state, stack_start + 1, 2);
     }
@@ -142,8 +146,10 @@ where
 
     let stack_start = ffi::lua_gettop(state) - nargs;
 
-    ffi::lua_pushcfunction(state, error_traceback);
-    ffi::lua_pushcfunction(state, do_call::<F, R>);
+    relax_memory_limit(state, || {
+        ffi::lua_pushcfunction(state, error_traceback);
+        ffi::lua_pushcfunction(state, do_call::<F, R>);
+    });
     if nargs > 0 {
         ffi::lua_rotate(state, stack_start + 1, 2);
     }
@@ -299,54 +305,132 @@ pub unsafe fn get_meta_gc_userdata<MT: Any, T>(state: *mut ffi::lua_State, index
 // Populates the given table with the appropriate members to be a userdata metatable for the given
 // type.  This function takes the given table at the `metatable` index, and adds an appropriate __gc
 // member to it for the given type and a __metatable entry to protect the table from script access.
-// The function also, if given a `members` table index, will set up an __index metamethod to return
-// the appropriate member on __index.  Additionally, if there is already an __index entry on the
-// given metatable, instead of simply overwriting the __index, instead the created __index method
-// will capture the previous one, and use it as a fallback only if the given key is not found in the
-// provided members table.  Internally uses 6 stack spaces and does not call checkstack.
+// The function also, if given a `field_getters` or `methods` tables index, will set up an __index
+// metamethod to return the appropriate member on __index, trying field getters first and then
+// methods.  Additionally, if there is already an __index entry on the given metatable, instead of
+// simply overwriting the __index, instead the created __index method will capture the previous one,
+// and use it as a fallback only if the given key is not found in the provided tables.  The same is
+// done for __newindex if a `field_setters` table index is given.  Internally uses 6 stack spaces
+// and does not call checkstack.
 pub unsafe fn init_userdata_metatable<T>(
     state: *mut ffi::lua_State,
     metatable: c_int,
-    members: Option<c_int>,
+    field_getters: Option<c_int>,
+    field_setters: Option<c_int>,
+    methods: Option<c_int>,
 ) -> Result<()> {
-    // Used if both an __index metamethod is set and regular methods, checks methods table
-    // first, then __index metamethod.
+    // Used if field getters or both an __index metamethod and regular methods are set.
+    // Upvalues are: the original __index metamethod, field getters and methods tables (or nils).
     unsafe extern "C" fn meta_index_impl(state: *mut ffi::lua_State) -> c_int {
         ffi::luaL_checkstack(state, 2, ptr::null());
+        ffi::lua_settop(state, 2);
+
+        if ffi::lua_isnil(state, ffi::lua_upvalueindex(2)) == 0 {
+            ffi::lua_pushvalue(state, 2);
+            ffi::lua_rawget(state, ffi::lua_upvalueindex(2));
+            if ffi::lua_isnil(state, -1) == 0 {
+                ffi::lua_pushvalue(state, 1);
+                ffi::lua_call(state, 1, 1);
+                return 1;
+            }
+            ffi::lua_pop(state, 1);
+        }
 
-        ffi::lua_pushvalue(state, -1);
-        ffi::lua_gettable(state, ffi::lua_upvalueindex(2));
-        if ffi::lua_isnil(state, -1) == 0 {
-            ffi::lua_insert(state, -3);
-            ffi::lua_pop(state, 2);
-            1
-        } else {
+        if ffi::lua_isnil(state, ffi::lua_upvalueindex(3)) == 0 {
+            ffi::lua_pushvalue(state, 2);
+            ffi::lua_rawget(state, ffi::lua_upvalueindex(3));
+            if ffi::lua_isnil(state, -1) == 0 {
+                return 1;
+            }
             ffi::lua_pop(state, 1);
+        }
+
+        if ffi::lua_isnil(state, ffi::lua_upvalueindex(1)) == 0 {
             ffi::lua_pushvalue(state, ffi::lua_upvalueindex(1));
-            ffi::lua_insert(state, -3);
+            ffi::lua_insert(state, 1);
             ffi::lua_call(state, 2, 1);
-            1
+        } else {
+            ffi::lua_pushnil(state);
+        }
+        1
+    }
+
+    // Used if field setters are set.
+    // Upvalues are: the original __newindex metamethod (or nil) and field setters table.
+    unsafe extern "C" fn meta_newindex_impl(state: *mut ffi::lua_State) -> c_int {
+        ffi::luaL_checkstack(state, 2, ptr::null());
+        ffi::lua_settop(state, 3);
+
+        ffi::lua_pushvalue(state, 2);
+        ffi::lua_rawget(state, ffi::lua_upvalueindex(2));
+        if ffi::lua_isnil(state, -1) == 0 {
+            ffi::lua_insert(state, 1);
+            ffi::lua_remove(state, 3);
+            ffi::lua_call(state, 2, 0);
+            return 0;
+        }
+        ffi::lua_pop(state, 1);
+
+        if ffi::lua_isnil(state, ffi::lua_upvalueindex(1)) == 0 {
+            ffi::lua_pushvalue(state, ffi::lua_upvalueindex(1));
+            ffi::lua_insert(state, 1);
+            ffi::lua_call(state, 3, 0);
+            0
+        } else {
+            ffi::luaL_error(
+                state,
+                cstr!("attempt to set an unknown field '%s'"),
+                ffi::luaL_tolstring(state, 2, ptr::null_mut()),
+            )
         }
     }
 
-    let members = members.map(|i| ffi::lua_absindex(state, i));
+    let field_getters = field_getters.map(|i| ffi::lua_absindex(state, i));
+    let field_setters = field_setters.map(|i| ffi::lua_absindex(state, i));
+    let methods = methods.map(|i| ffi::lua_absindex(state, i));
     ffi::lua_pushvalue(state, metatable);
 
-    if let Some(members) = members {
+    if field_getters.is_some() || methods.is_some() {
         push_string(state, "__index")?;
         ffi::lua_pushvalue(state, -1);
 
         let index_type = ffi::lua_rawget(state, -3);
-        if index_type == ffi::LUA_TNIL {
-            ffi::lua_pop(state, 1);
-            ffi::lua_pushvalue(state, members);
-        } else if index_type == ffi::LUA_TFUNCTION {
-            ffi::lua_pushvalue(state, members);
+        match (index_type, field_getters, methods) {
+            (ffi::LUA_TNIL, None, Some(methods)) => {
+                ffi::lua_pop(state, 1);
+                ffi::lua_pushvalue(state, methods);
+            }
+            (ffi::LUA_TNIL, _, _) | (ffi::LUA_TFUNCTION, _, _) => {
+                for &idx in &[field_getters, methods] {
+                    match idx {
+                        Some(idx) => ffi::lua_pushvalue(state, idx),
+                        None => ffi::lua_pushnil(state),
+                    }
+                }
+                protect_lua_closure(state, 3, 1, |state| {
+                    ffi::lua_pushcclosure(state, meta_index_impl, 3);
+                })?;
+            }
+            _ => mlua_panic!("improper __index type {}", index_type),
+        }
+
+        protect_lua_closure(state, 3, 1, |state| {
+            ffi::lua_rawset(state, -3);
+        })?;
+    }
+
+    if let Some(field_setters) = field_setters {
+        push_string(state, "__newindex")?;
+        ffi::lua_pushvalue(state, -1);
+
+        let newindex_type = ffi::lua_rawget(state, -3);
+        if newindex_type == ffi::LUA_TNIL || newindex_type == ffi::LUA_TFUNCTION {
+            ffi::lua_pushvalue(state, field_setters);
             protect_lua_closure(state, 2, 1, |state| {
-                ffi::lua_pushcclosure(state, meta_index_impl, 2);
+                ffi::lua_pushcclosure(state, meta_newindex_impl, 2);
             })?;
         } else {
-            mlua_panic!("improper __index type {}", index_type);
+            mlua_panic!("improper __newindex type {}", newindex_type);
         }
 
         protect_lua_closure(state, 3, 1, |state| {
@@ -420,19 +504,69 @@ where
         }
         Ok(Err(err)) => {
             ffi::lua_settop(state, 1);
+            raise_wrapped(state, ud, Ok(err))
+        }
+        Err(p) => {
+            ffi::lua_settop(state, 1);
+            raise_wrapped(state, ud, Err(p))
+        }
+    }
+}
+
+// Same as `callback_error`, but for hook functions.
+//
+// In a hook, the stack belongs to the running function, so the values on the stack are left in
+// place and the preallocated memory for the error is kept at the top of the stack instead.
+pub unsafe fn hook_callback_error<R, F>(state: *mut ffi::lua_State, f: F) -> R
+where
+    F: FnOnce() -> Result<R>,
+{
+    let top = ffi::lua_gettop(state);
+    ffi::luaL_checkstack(
+        state,
+        3,
+        cstr!("not enough stack space for callback error handling"),
+    );
+
+    let ud = ffi::lua_newuserdata(
+        state,
+        mem::size_of::<WrappedError>().max(mem::size_of::<WrappedPanic>()),
+    );
+
+    match catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(r)) => {
+            ffi::lua_settop(state, top);
+            r
+        }
+        Ok(Err(err)) => {
+            ffi::lua_settop(state, top + 1);
+            raise_wrapped(state, ud, Ok(err))
+        }
+        Err(p) => {
+            ffi::lua_settop(state, top + 1);
+            raise_wrapped(state, ud, Err(p))
+        }
+    }
+}
+
+// Writes the error or panic to the preallocated userdata at the top of the stack and raises it.
+unsafe fn raise_wrapped(
+    state: *mut ffi::lua_State,
+    ud: *mut c_void,
+    err: std::result::Result<Error, Box<dyn Any + Send>>,
+) -> ! {
+    match err {
+        Ok(err) => {
             ptr::write(ud as *mut WrappedError, WrappedError(err));
             get_gc_metatable_for::<WrappedError>(state);
-            ffi::lua_setmetatable(state, -2);
-            ffi::lua_error(state)
         }
         Err(p) => {
-            ffi::lua_settop(state, 1);
             ptr::write(ud as *mut WrappedPanic, WrappedPanic(Some(p)));
             get_gc_metatable_for::<WrappedPanic>(state);
-            ffi::lua_setmetatable(state, -2);
-            ffi::lua_error(state)
         }
     }
+    ffi::lua_setmetatable(state, -2);
+    ffi::lua_error(state)
 }
 
 // Takes an error at the top of the stack, and if it is a WrappedError, converts it to an
@@ -462,16 +596,10 @@ pub unsafe extern "C" fn error_traceback(state: *mut ffi::lua_State) -> c_int {
             "<not enough stack space for traceback>".to_owned()
         };
 
-        let error = error.clone();
+        let error = wrap_callback_error(error.clone(), traceback);
         ffi::lua_remove(state, -2);
 
-        ptr::write(
-            ud,
-            WrappedError(Error::CallbackError {
-                traceback,
-                cause: Arc::new(error),
-            }),
-        );
+        ptr::write(ud, WrappedError(error));
         get_gc_metatable_for::<WrappedError>(state);
         ffi::lua_setmetatable(state, -2);
     } else if get_gc_userdata::<WrappedPanic>(state, -1).is_null()
@@ -484,6 +612,98 @@ pub unsafe extern "C" fn error_traceback(state: *mut ffi::lua_State) -> c_int {
     1
 }
 
+// Wraps an error returned from a Rust callback into `Error::CallbackError`, keeping the context
+// added to the error on the outside, so that it's visible in the error message.
+fn wrap_callback_error(error: Error, traceback: String) -> Error {
+    match error {
+        Error::WithContext { context, cause } => Error::WithContext {
+            context,
+            cause: Arc::new(wrap_callback_error((*cause).clone(), traceback)),
+        },
+        error => Error::CallbackError {
+            traceback,
+            cause: Arc::new(error),
+        },
+    }
+}
+
+// Details of an error raised by Lua code, collected by `script_error_traceback`.
+pub struct ScriptErrorDetails {
+    pub message: String,
+    pub chunk: Option<String>,
+    pub line: Option<i32>,
+    pub traceback: Traceback,
+}
+
+// Same as `error_traceback`, but an error raised by Lua code is replaced with a table holding the
+// original error value and its `ScriptErrorDetails`, to be taken by `take_script_error`.
+pub unsafe extern "C" fn script_error_traceback(state: *mut ffi::lua_State) -> c_int {
+    // luaL_tolstring may call the `__tostring` metamethod
+    const LUA_TOSTRING_STACK: c_int = 11;
+
+    if ffi::lua_checkstack(state, LUA_TOSTRING_STACK) == 0
+        || !get_wrapped_error(state, -1).is_null()
+        || !get_gc_userdata::<WrappedPanic>(state, -1).is_null()
+    {
+        return error_traceback(state);
+    }
+
+    // lua_createtable, lua_newuserdata and luaL_tolstring may error, so they must be called before
+    // creating any rust values that implement Drop.
+    ffi::lua_createtable(state, 2, 0);
+    ffi::lua_pushvalue(state, -2);
+    ffi::lua_rawseti(state, -2, 1);
+    let ud = ffi::lua_newuserdata(state, mem::size_of::<ScriptErrorDetails>())
+        as *mut ScriptErrorDetails;
+    ffi::luaL_tolstring(state, -3, ptr::null_mut());
+    let message = to_string(state, -1).into_owned();
+    ffi::lua_pop(state, 1);
+
+    // Skip the error handler itself
+    let traceback = Traceback::new(state, None, 1);
+    let (chunk, line) = match traceback.frames.iter().find(|frame| frame.what != "C") {
+        Some(frame) => (Some(frame.source.clone()), frame.line),
+        None => (None, None),
+    };
+    ptr::write(
+        ud,
+        ScriptErrorDetails {
+            message,
+            chunk,
+            line,
+            traceback,
+        },
+    );
+    get_gc_metatable_for::<ScriptErrorDetails>(state);
+    ffi::lua_setmetatable(state, -2);
+    ffi::lua_rawseti(state, -2, 2);
+    1
+}
+
+// Takes the details of an error produced by `script_error_traceback` at the top of the stack,
+// replacing it with the original error value. Returns `None` and leaves the stack untouched for
+// other errors.
+// Uses 1 stack space, does not call checkstack.
+pub unsafe fn take_script_error(state: *mut ffi::lua_State) -> Option<ScriptErrorDetails> {
+    if ffi::lua_type(state, -1) != ffi::LUA_TTABLE {
+        return None;
+    }
+    ffi::lua_rawgeti(state, -1, 2);
+    let details = get_gc_userdata::<ScriptErrorDetails>(state, -1).as_ref();
+    let details = details.map(|details| ScriptErrorDetails {
+        message: details.message.clone(),
+        chunk: details.chunk.clone(),
+        line: details.line,
+        traceback: details.traceback.clone(),
+    });
+    ffi::lua_pop(state, 1);
+    if details.is_some() {
+        ffi::lua_rawgeti(state, -1, 1);
+        ffi::lua_remove(state, -2);
+    }
+    details
+}
+
 // Does not call lua_checkstack, uses 1 stack space.
 pub unsafe fn get_main_state(state: *mut ffi::lua_State) -> Option<*mut ffi::lua_State> {
     #[cfg(any(feature = "lua54", feature = "lua53", feature = "lua52"))]
@@ -646,6 +866,8 @@ pub unsafe fn init_error_registry(state: *mut ffi::lua_State) {
         }),
     );
 
+    init_gc_metatable_for::<ScriptErrorDetails>(state, None);
+
     // Create destructed userdata metatable
 
     unsafe extern "C" fn destructed_error(state: *mut ffi::lua_State) -> c_int {
@@ -221,4 +221,17 @@ pub trait FromLuaMulti<'lua>: Sized {
     /// assigning values. Similarly, if not enough values are given, conversions should assume that
     /// any missing values are nil.
     fn from_lua_multi(values: MultiValue<'lua>, lua: &'lua Lua) -> Result<Self>;
+
+    /// Performs the conversion of arguments passed to a Rust callback.
+    ///
+    /// `pos` is the position of the first value in the argument list, starting from 1. Conversion
+    /// failures should be reported as [`Error::BadArgument`] with the position of the offending
+    /// argument. The default implementation calls [`from_lua_multi`].
+    ///
+    /// [`Error::BadArgument`]: enum.Error.html#variant.BadArgument
+    /// [`from_lua_multi`]: #tymethod.from_lua_multi
+    fn from_lua_args(values: MultiValue<'lua>, pos: usize, lua: &'lua Lua) -> Result<Self> {
+        let _ = pos;
+        Self::from_lua_multi(values, lua)
+    }
 }
@@ -35,6 +35,15 @@ async fn test_async_function() -> Result<()> {
     let res: i64 = lua.load("f(1, 2, 3)").eval_async().await?;
     assert_eq!(res, 9);
 
+    lua.load(
+        r#"
+        local ok, err = pcall(function() f(1, 2, "x") end)
+        assert(tostring(err):find("^bad argument #3 to 'f' %(error converting Lua string to"))
+    "#,
+    )
+    .exec_async()
+    .await?;
+
     Ok(())
 }
 
@@ -301,6 +310,8 @@ async fn test_async_userdata() -> Result<()> {
         userdata:set_value(12)
         assert(userdata.sleep(5) == "elapsed:5ms")
         assert(userdata:get_value() == 12)
+        local ok, err = pcall(function() userdata:set_value("x") end)
+        assert(tostring(err):find("^bad argument #1 to 'set_value' %(error converting Lua string to"))
     "#,
     )
     .exec_async()
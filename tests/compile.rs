@@ -17,6 +17,16 @@ fn test_compilation() {
     #[cfg(feature = "async")]
     t.compile_fail("tests/compile/async_nonstatic_userdata.rs");
 
+    #[cfg(all(feature = "async", feature = "macros"))]
+    t.compile_fail("tests/compile/userdata_impl_async_mut.rs");
+
+    #[cfg(feature = "macros")]
+    t.pass("tests/compile/userdata_impl_lua_result.rs");
+    #[cfg(feature = "macros")]
+    t.compile_fail("tests/compile/derive_container_attr.rs");
+    #[cfg(feature = "macros")]
+    t.compile_fail("tests/compile/derive_newtype_attr.rs");
+
     #[cfg(feature = "send")]
     t.compile_fail("tests/compile/non_send.rs");
     #[cfg(not(feature = "send"))]
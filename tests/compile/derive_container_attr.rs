@@ -0,0 +1,8 @@
+#[derive(mlua::FromLua)]
+#[lua(rename = "point")]
+struct Point {
+    x: f64,
+    y: f64,
+}
+
+fn main() {}
@@ -0,0 +1,4 @@
+#[derive(mlua::FromLua)]
+struct Id(#[lua(default)] i64);
+
+fn main() {}
@@ -0,0 +1,12 @@
+#[derive(Clone)]
+struct Counter(i64);
+
+#[mlua::userdata_impl]
+impl Counter {
+    #[method]
+    async fn increment(&mut self) {
+        self.0 += 1;
+    }
+}
+
+fn main() {}
@@ -0,0 +1,30 @@
+use mlua::prelude::*;
+
+type CounterResult<T> = std::result::Result<T, LuaError>;
+
+struct Counter(i64);
+
+#[mlua::userdata_impl]
+impl Counter {
+    #[method(result)]
+    fn checked_sub(&self, n: i64) -> LuaResult<i64> {
+        if n > self.0 {
+            return Err(LuaError::RuntimeError("overflow".into()));
+        }
+        Ok(self.0 - n)
+    }
+
+    #[method(result)]
+    fn checked_add(&self, n: i64) -> CounterResult<i64> {
+        self.0
+            .checked_add(n)
+            .ok_or_else(|| LuaError::RuntimeError("overflow".into()))
+    }
+
+    #[method]
+    fn parse(&self, s: String) -> std::result::Result<i64, String> {
+        s.parse().map_err(|_| format!("invalid number '{}'", s))
+    }
+}
+
+fn main() {}
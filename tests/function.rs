@@ -10,7 +10,7 @@
 )]
 extern "system" {}
 
-use mlua::{Function, Lua, Result, String};
+use mlua::{Error, Function, Lua, Result, String, UserData, UserDataMethods, Variadic};
 
 #[test]
 fn test_function() -> Result<()> {
@@ -101,3 +101,102 @@ fn test_dump() -> Result<()> {
 
     Ok(())
 }
+
+#[test]
+fn test_bad_argument() -> Result<()> {
+    struct Spawner;
+
+    impl UserData for Spawner {
+        fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+            methods.add_method("spawn", |_, _, (_, n): (String, i32)| Ok(n));
+        }
+    }
+
+    let lua = Lua::new();
+    let globals = lua.globals();
+    globals.set(
+        "spawn",
+        lua.create_function(|_, (_, n): (String, i32)| Ok(n))?,
+    )?;
+    globals.set(
+        "sum",
+        lua.create_function(|_, (base, rest): (i32, Variadic<i32>)| {
+            Ok(base + rest.iter().sum::<i32>())
+        })?,
+    )?;
+    globals.set("spawner", Spawner)?;
+
+    let check = |code: &str, pos: usize, name: Option<&str>| match lua.load(code).exec() {
+        Err(Error::CallbackError { cause, .. }) => match cause.as_ref() {
+            Error::BadArgument {
+                pos: p,
+                name: n,
+                cause,
+            } => {
+                assert_eq!(*p, pos, "{}", code);
+                assert_eq!(n.as_deref(), name, "{}", code);
+                match cause.as_ref() {
+                    Error::FromLuaConversionError { .. } => {}
+                    e => panic!("expected FromLuaConversionError, got {:?}", e),
+                }
+            }
+            e => panic!("expected BadArgument, got {:?}", e),
+        },
+        r => panic!("expected CallbackError, got {:?}", r),
+    };
+
+    check(r#"spawn("a")"#, 2, Some("spawn"));
+    check(r#"spawn({}, 1)"#, 1, Some("spawn"));
+    check(r#"sum(1, 2, "x")"#, 3, Some("sum"));
+    check(r#"spawner:spawn("a", {})"#, 2, Some("spawn"));
+    check(r#"local f = spawn; pcall(error) f("a")"#, 2, Some("f"));
+
+    lua.load(
+        r#"
+        local ok, err = pcall(spawn, "a")
+        assert(not ok)
+        assert(tostring(err):find("^bad argument #2 %(error converting Lua nil to i32"))
+        local ok, err = pcall(function() spawn("a", "b") end)
+        assert(tostring(err):find("^bad argument #2 to 'spawn' %(error converting Lua string to i32"))
+        local ok, err = pcall(function() spawn("a", 1e100) end)
+        assert(tostring(err):find("^bad argument #2 to 'spawn' %(error converting Lua number to i32"))
+    "#,
+    )
+    .exec()?;
+
+    match lua.load(r#"spawn("a")"#).exec() {
+        Err(Error::CallbackError { cause, .. }) => assert_eq!(
+            cause.to_string(),
+            "bad argument #2 to 'spawn' (error converting Lua nil to i32 \
+             (expected number or string coercible to number))"
+        ),
+        r => panic!("expected CallbackError, got {:?}", r),
+    }
+
+    Ok(())
+}
+
+#[cfg(not(feature = "send"))]
+#[test]
+fn test_owned_function() -> Result<()> {
+    use mlua::OwnedFunction;
+
+    let lua = Lua::new();
+
+    lua.load("function add(a, b) return a + b end").exec()?;
+    let add: OwnedFunction = lua.globals().get("add")?;
+    let mul = lua
+        .create_function(|_, (a, b): (i64, i64)| Ok(a * b))?
+        .into_owned()?;
+
+    let apply: Function = lua.load("function(f, a, b) return f(a, b) end").eval()?;
+    assert_eq!(apply.call::<_, i64>((&mul, 3, 4))?, 12);
+    assert_eq!(apply.call::<_, i64>((mul.try_clone()?, 5, 6))?, 30);
+
+    drop(apply);
+    drop(lua);
+    assert_eq!(add.to_ref().call::<_, i64>((1, 2))?, 3);
+    assert_eq!(mul.to_ref().call::<_, i64>((2, 3))?, 6);
+
+    Ok(())
+}
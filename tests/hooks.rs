@@ -14,8 +14,9 @@ use std::cell::RefCell;
 use std::ops::Deref;
 use std::str;
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
-use mlua::{Error, HookTriggers, Lua, Result, Value};
+use mlua::{DebugEvent, Error, HookTriggers, InterruptAction, Lua, Result, Thread, Value};
 
 #[test]
 fn line_counts() -> Result<()> {
@@ -259,3 +260,486 @@ fn hook_swap_within_hook() -> Result<()> {
         Ok(())
     })
 }
+
+#[test]
+fn test_interrupt() -> Result<()> {
+    let lua = Lua::new();
+
+    #[cfg(feature = "luajit")]
+    // For LuaJIT disable JIT, as compiled code does not trigger hooks
+    lua.load("jit.off()").exec()?;
+
+    let interrupts = Arc::new(Mutex::new(0));
+    let interrupts2 = interrupts.clone();
+    lua.set_interrupt(move |_| {
+        let mut count = interrupts2.lock().unwrap();
+        *count += 1;
+        if *count >= 5 {
+            InterruptAction::Error(Error::RuntimeError("interrupted".to_string()))
+        } else {
+            InterruptAction::Continue
+        }
+    })?;
+
+    // The hook works together with the interrupt
+    let lines = Arc::new(Mutex::new(0));
+    let lines2 = lines.clone();
+    lua.set_hook(
+        HookTriggers {
+            every_line: true,
+            ..Default::default()
+        },
+        move |_, _| {
+            *lines2.lock().unwrap() += 1;
+            Ok(())
+        },
+    )?;
+
+    match lua.load("while true do end").exec() {
+        Err(Error::CallbackError { cause, .. }) => match cause.deref() {
+            Error::RuntimeError(s) => assert_eq!(s, "interrupted"),
+            e => panic!("wrong callback error kind caught: {:?}", e),
+        },
+        r => panic!("wrong result: {:?}", r),
+    }
+    assert_eq!(*interrupts.lock().unwrap(), 5);
+    assert!(*lines.lock().unwrap() > 0);
+
+    lua.remove_hook();
+    lua.remove_interrupt();
+    lua.load("for i = 1, 10000 do end").exec()?;
+    assert_eq!(*interrupts.lock().unwrap(), 5);
+
+    Ok(())
+}
+
+#[test]
+#[cfg(any(feature = "lua54", feature = "lua53"))]
+fn test_interrupt_yield() -> Result<()> {
+    let lua = Lua::new();
+
+    #[cfg(feature = "luajit")]
+    // For LuaJIT disable JIT, as compiled code does not trigger hooks
+    lua.load("jit.off()").exec()?;
+
+    lua.set_interrupt(|_| InterruptAction::Yield)?;
+
+    let thread = lua.create_thread(
+        lua.load(
+            r#"
+            local n = 0
+            for i = 1, 10000 do
+                n = n + i
+            end
+            return n
+        "#,
+        )
+        .into_function()?,
+    )?;
+
+    let mut yields = 0;
+    let n = loop {
+        match thread.resume::<_, Option<i64>>(())? {
+            Some(n) => break n,
+            None => yields += 1,
+        }
+    };
+    assert_eq!(n, 50005000);
+    assert!(yields > 0);
+
+    // Main thread cannot yield and continues execution
+    lua.load("for i = 1, 10000 do end").exec()?;
+
+    Ok(())
+}
+
+#[test]
+fn test_execution_deadline() -> Result<()> {
+    let lua = Lua::new();
+
+    #[cfg(feature = "luajit")]
+    // For LuaJIT disable JIT, as compiled code does not trigger hooks
+    lua.load("jit.off()").exec()?;
+
+    lua.set_execution_deadline(Instant::now() + Duration::from_millis(50))?;
+    lua.load("for i = 1, 1000 do end").exec()?;
+
+    match lua.load("while true do end").exec() {
+        Err(Error::CallbackError { cause, .. }) => match cause.deref() {
+            Error::DeadlineExceeded => {}
+            e => panic!("wrong callback error kind caught: {:?}", e),
+        },
+        r => panic!("wrong result: {:?}", r),
+    }
+
+    lua.remove_execution_deadline();
+    lua.load("for i = 1, 1000 do end").exec()?;
+
+    Ok(())
+}
+
+#[test]
+fn test_execution_deadline_coroutine() -> Result<()> {
+    let lua = Lua::new();
+
+    #[cfg(feature = "luajit")]
+    // For LuaJIT disable JIT, as compiled code does not trigger hooks
+    lua.load("jit.off()").exec()?;
+
+    // The coroutine is created before the deadline is set
+    let thread: Thread = lua
+        .load("coroutine.create(function() while true do end end)")
+        .eval()?;
+    lua.set_execution_deadline(Instant::now() + Duration::from_millis(200))?;
+
+    match thread.resume::<_, ()>(()) {
+        Err(Error::CallbackError { cause, .. }) => match cause.deref() {
+            Error::DeadlineExceeded => {}
+            e => panic!("wrong callback error kind caught: {:?}", e),
+        },
+        r => panic!("wrong result: {:?}", r),
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_hook_instruction_counts() -> Result<()> {
+    let lua = Lua::new();
+
+    #[cfg(feature = "luajit")]
+    // For LuaJIT disable JIT, as compiled code does not trigger hooks
+    lua.load("jit.off()").exec()?;
+
+    let counts = Arc::new(Mutex::new((0, 0, 0)));
+    let counts2 = counts.clone();
+    lua.set_interrupt(move |_lua| {
+        counts2.lock().unwrap().0 += 1;
+        InterruptAction::Continue
+    })?;
+    let counts2 = counts.clone();
+    lua.add_hook(
+        HookTriggers {
+            every_nth_instruction: Some(7),
+            ..Default::default()
+        },
+        move |_lua, _debug| {
+            counts2.lock().unwrap().1 += 1;
+            Ok(())
+        },
+    )?;
+    let counts2 = counts.clone();
+    let every_hook = lua.add_hook(
+        HookTriggers {
+            every_nth_instruction: Some(1),
+            ..Default::default()
+        },
+        move |_lua, _debug| {
+            counts2.lock().unwrap().2 += 1;
+            Ok(())
+        },
+    )?;
+
+    let code = "for i = 1, 10000 do end";
+    lua.load(code).exec()?;
+    let (interrupts, sevens, instructions) = *counts.lock().unwrap();
+    assert_eq!(interrupts, instructions / 1000);
+    assert_eq!(sevens, instructions / 7);
+
+    // Each hook is still called at its own rate without the hook called on every instruction
+    lua.remove_hook_handle(every_hook);
+    *counts.lock().unwrap() = (0, 0, 0);
+    lua.load(code).exec()?;
+    let (interrupts, sevens, _) = *counts.lock().unwrap();
+    assert!((instructions / 1000 - 1..=instructions / 1000 + 1).contains(&interrupts));
+    assert!((instructions / 7 - 1..=instructions / 7 + 1).contains(&sevens));
+
+    Ok(())
+}
+
+#[test]
+fn test_multiple_hooks() -> Result<()> {
+    let lua = Lua::new();
+    let lines = Arc::new(Mutex::new(0));
+    let calls = Arc::new(Mutex::new(0));
+    let counts = Arc::new(Mutex::new(0));
+
+    let lines2 = lines.clone();
+    let lines_hook = lua.add_hook(
+        HookTriggers {
+            every_line: true,
+            ..Default::default()
+        },
+        move |_lua, _debug| {
+            *lines2.lock().unwrap() += 1;
+            Ok(())
+        },
+    )?;
+    let calls2 = calls.clone();
+    let calls_hook = lua.add_hook(
+        HookTriggers {
+            on_calls: true,
+            ..Default::default()
+        },
+        move |_lua, _debug| {
+            *calls2.lock().unwrap() += 1;
+            Ok(())
+        },
+    )?;
+    let counts2 = counts.clone();
+    lua.set_hook(
+        HookTriggers {
+            every_nth_instruction: Some(1),
+            ..Default::default()
+        },
+        move |_lua, _debug| {
+            *counts2.lock().unwrap() += 1;
+            Ok(())
+        },
+    )?;
+
+    let code = r#"
+        local x = 1
+        local y = tostring(x)
+    "#;
+    lua.load(code).exec()?;
+    assert_eq!(*lines.lock().unwrap(), 2);
+    assert!(*calls.lock().unwrap() >= 2);
+    assert!(*counts.lock().unwrap() > 0);
+
+    // Removing one hook keeps the others
+    lua.remove_hook_handle(calls_hook);
+    lua.remove_hook();
+    let (calls_before, counts_before) = (*calls.lock().unwrap(), *counts.lock().unwrap());
+    lua.load(code).exec()?;
+    assert_eq!(*lines.lock().unwrap(), 4);
+    assert_eq!(*calls.lock().unwrap(), calls_before);
+    assert_eq!(*counts.lock().unwrap(), counts_before);
+
+    lua.remove_hook_handle(lines_hook);
+    lua.load(code).exec()?;
+    assert_eq!(*lines.lock().unwrap(), 4);
+
+    // An error in a hook stops execution
+    let error_hook = lua.add_hook(
+        HookTriggers {
+            on_calls: true,
+            ..Default::default()
+        },
+        |_lua, _debug| Err(Error::RuntimeError("stop".to_string())),
+    )?;
+    assert!(lua.load("tostring(1)").exec().is_err());
+    lua.remove_hook_handle(error_hook);
+    lua.load("tostring(1)").exec()?;
+
+    Ok(())
+}
+
+#[test]
+fn test_thread_hook() -> Result<()> {
+    let lua = Lua::new();
+    let lines = Arc::new(Mutex::new(Vec::new()));
+
+    let thread: Thread = lua
+        .load(
+            r#"
+            coroutine.create(function()
+                local x = 1
+                coroutine.yield()
+                local y = 2
+            end)
+        "#,
+        )
+        .eval()?;
+
+    let lines2 = lines.clone();
+    thread.set_hook(
+        HookTriggers {
+            every_line: true,
+            ..Default::default()
+        },
+        move |_lua, debug| {
+            lines2.lock().unwrap().push(debug.curr_line());
+            Ok(())
+        },
+    )?;
+
+    // Other threads are not affected
+    lua.load("local a = 1\nlocal b = 2").exec()?;
+    assert!(lines.lock().unwrap().is_empty());
+
+    thread.resume::<_, ()>(())?;
+    assert_eq!(*lines.lock().unwrap(), vec![3, 4]);
+
+    thread.remove_hook();
+    thread.resume::<_, ()>(())?;
+    assert_eq!(*lines.lock().unwrap(), vec![3, 4]);
+
+    // Instruction limit for a single coroutine
+    let thread: Thread = lua
+        .load("coroutine.create(function() while true do end end)")
+        .eval()?;
+    thread.set_hook(
+        HookTriggers {
+            every_nth_instruction: Some(1000),
+            ..Default::default()
+        },
+        |_lua, _debug| Err(Error::RuntimeError("instruction limit".to_string())),
+    )?;
+    match thread.resume::<_, ()>(()) {
+        Err(Error::CallbackError { ref cause, .. }) => match cause.deref() {
+            Error::RuntimeError(msg) => assert_eq!(msg, "instruction limit"),
+            e => panic!("wrong error: {:?}", e),
+        },
+        r => panic!("expected error, got {:?}", r),
+    }
+    lua.load("for i = 1, 10000 do end").exec()?;
+
+    // The hook is installed on the thread, so it is called when resumed from Lua as well
+    let thread: Thread = lua
+        .load("coroutine.create(function() while true do end end)")
+        .eval()?;
+    thread.set_hook(
+        HookTriggers {
+            every_nth_instruction: Some(1000),
+            ..Default::default()
+        },
+        |_lua, _debug| Err(Error::RuntimeError("instruction limit".to_string())),
+    )?;
+    lua.globals().set("co", thread)?;
+    let (ok, err): (bool, String) = lua
+        .load("local ok, err = coroutine.resume(co); return ok, tostring(err)")
+        .eval()?;
+    assert!(!ok);
+    assert!(err.contains("instruction limit"), "unexpected error: {}", err);
+
+    // Hooks set for all threads later apply to the thread as well, even if it's resumed from Lua
+    let thread: Thread = lua
+        .load("coroutine.create(function() while true do end end)")
+        .eval()?;
+    thread.set_hook(
+        HookTriggers {
+            on_calls: true,
+            ..Default::default()
+        },
+        |_lua, _debug| Ok(()),
+    )?;
+    lua.globals().set("co", thread)?;
+    lua.set_execution_deadline(Instant::now() + Duration::from_millis(200))?;
+    let (ok, err): (bool, String) = lua
+        .load("local ok, err = coroutine.resume(co); return ok, tostring(err)")
+        .eval()?;
+    assert!(!ok);
+    assert!(err.contains("deadline"), "unexpected error: {}", err);
+    lua.remove_execution_deadline();
+
+    Ok(())
+}
+
+#[test]
+fn test_resumed_thread_hook_changes() -> Result<()> {
+    let lua = Lua::new();
+    let lines = Arc::new(Mutex::new(Vec::new()));
+
+    let thread: Thread = lua
+        .load(
+            r#"
+            coroutine.create(function()
+                while true do
+                    coroutine.yield()
+                end
+            end)
+        "#,
+        )
+        .eval()?;
+
+    // The first resume happens without any hooks set
+    thread.resume::<_, ()>(())?;
+
+    // The thread has no hook installed, so hooks set after it last ran are installed on resume
+    let lines2 = lines.clone();
+    lua.set_hook(
+        HookTriggers {
+            every_line: true,
+            ..Default::default()
+        },
+        move |_lua, debug| {
+            lines2.lock().unwrap().push(debug.curr_line());
+            Ok(())
+        },
+    )?;
+    thread.resume::<_, ()>(())?;
+    let count = lines.lock().unwrap().len();
+    assert!(count > 0);
+
+    // Their removal takes effect at the next hook event of the thread
+    lua.remove_hook();
+    thread.resume::<_, ()>(())?;
+    thread.resume::<_, ()>(())?;
+    assert_eq!(lines.lock().unwrap().len(), count);
+
+    Ok(())
+}
+
+#[test]
+fn test_debug_locals_upvalues() -> Result<()> {
+    let lua = Lua::new();
+    let inspected = Arc::new(Mutex::new(Vec::new()));
+
+    let inspected2 = inspected.clone();
+    lua.set_hook(
+        HookTriggers {
+            on_calls: true,
+            every_line: true,
+            ..Default::default()
+        },
+        move |_lua, debug| {
+            if debug.event() != DebugEvent::Line {
+                assert!(matches!(
+                    debug.event(),
+                    DebugEvent::Call | DebugEvent::TailCall
+                ));
+                return Ok(());
+            }
+            if debug.curr_line() != 5 {
+                return Ok(());
+            }
+
+            let locals = debug.locals();
+            let names = (locals.iter())
+                .map(|(name, _)| name.as_str())
+                .filter(|name| !name.starts_with('('));
+            assert_eq!(names.collect::<Vec<_>>(), vec!["a", "b"]);
+            assert_eq!(debug.local(1).map(|(_, v)| v), Some(Value::Integer(1)));
+            assert!(debug.local(100).is_none());
+            assert!(debug.set_local(2, "changed")?);
+            assert!(!debug.set_local(100, 0)?);
+
+            let (name, value) = debug.upvalue(1).expect("missing upvalue");
+            assert_eq!(name, "up");
+            match value {
+                Value::Table(t) => assert_eq!(t.get::<_, i64>(1)?, 42),
+                v => panic!("unexpected upvalue: {:?}", v),
+            }
+            inspected2.lock().unwrap().push(debug.upvalues().len());
+            Ok(())
+        },
+    )?;
+
+    let b: String = lua
+        .load(
+            r#"
+            local up = {42}
+            local function f(a)
+                local b = "b" .. #up
+                return b
+            end
+            return f(1)
+        "#,
+        )
+        .eval()?;
+    assert_eq!(b, "changed");
+    assert_eq!(*inspected.lock().unwrap(), vec![1]);
+
+    Ok(())
+}
@@ -0,0 +1,328 @@
+#![cfg(feature = "macros")]
+#![cfg_attr(
+    all(feature = "luajit", target_os = "macos", target_arch = "x86_64"),
+    feature(link_args)
+)]
+
+#[cfg_attr(
+    all(feature = "luajit", target_os = "macos", target_arch = "x86_64"),
+    link_args = "-pagezero_size 10000 -image_base 100000000",
+    allow(unused_attributes)
+)]
+extern "system" {}
+
+use mlua::{AnyUserData, Error, Lua, Result};
+
+#[derive(Clone)]
+struct Counter {
+    value: i64,
+}
+
+#[mlua::userdata_impl]
+impl Counter {
+    #[function]
+    fn new(value: i64) -> Self {
+        Counter { value }
+    }
+
+    #[method]
+    fn get(&self) -> i64 {
+        self.value
+    }
+
+    #[method(name = "add")]
+    fn increment(&mut self, n: i64) {
+        self.value += n;
+    }
+
+    #[method(result)]
+    fn checked_div(&self, d: i64) -> Result<i64> {
+        if d == 0 {
+            return Err(Error::RuntimeError("division by zero".into()));
+        }
+        Ok(self.value / d)
+    }
+
+    #[method(result)]
+    fn checked_sub(&self, n: i64) -> mlua::prelude::LuaResult<i64> {
+        if n > self.value {
+            return Err(Error::RuntimeError("overflow".into()));
+        }
+        Ok(self.value - n)
+    }
+
+    #[method(result)]
+    fn describe<'lua>(&self, lua: &'lua Lua, prefix: String) -> Result<mlua::String<'lua>> {
+        lua.create_string(&format!("{}{}", prefix, self.value))
+    }
+
+    #[method]
+    fn parse(&self, s: String) -> std::result::Result<i64, String> {
+        s.parse().map_err(|_| format!("invalid number '{}'", s))
+    }
+
+    #[meta(Add)]
+    fn meta_add(&self, other: i64) -> i64 {
+        self.value + other
+    }
+
+    #[meta(ToString)]
+    fn meta_tostring(&self) -> String {
+        format!("Counter({})", self.value)
+    }
+
+    #[meta(Call)]
+    fn meta_call(&mut self) -> i64 {
+        self.value += 1;
+        self.value
+    }
+
+    #[cfg(feature = "async")]
+    #[method]
+    async fn get_async(&self, extra: i64) -> i64 {
+        self.value + extra
+    }
+
+    // Not visible from Lua
+    #[allow(dead_code)]
+    fn private(&self) -> i64 {
+        0
+    }
+}
+
+#[test]
+fn test_userdata_impl() -> Result<()> {
+    let lua = Lua::new();
+    let globals = lua.globals();
+
+    let counter: AnyUserData = lua.create_userdata(Counter::new(1))?;
+    globals.set("counter", counter.clone())?;
+    globals.set(
+        "new_counter",
+        lua.create_function(|_, v| Ok(Counter::new(v)))?,
+    )?;
+
+    lua.load(
+        r#"
+        assert(counter:get() == 1)
+        counter:add(4)
+        assert(counter:get() == 5)
+        assert(counter:checked_div(2) == 2)
+        assert(counter:describe("value: ") == "value: 5")
+        assert(counter + 10 == 15)
+        assert(tostring(counter) == "Counter(5)")
+        assert(counter() == 6)
+        assert(counter.private == nil)
+        assert(counter.increment == nil)
+
+        local ok, err = pcall(counter.checked_div, counter, 0)
+        assert(not ok and tostring(err):find("division by zero"))
+        assert(counter:checked_sub(2) == 4)
+        ok, err = pcall(counter.checked_sub, counter, 7)
+        assert(not ok and tostring(err):find("overflow"))
+
+        assert(new_counter(3).new(7):get() == 7)
+
+        assert(counter:parse("12") == 12)
+        local value, err = counter:parse("x")
+        assert(value == nil and err == "invalid number 'x'")
+    "#,
+    )
+    .exec()?;
+
+    assert_eq!(counter.borrow::<Counter>()?.value, 6);
+
+    Ok(())
+}
+
+#[cfg(feature = "async")]
+#[tokio::test]
+async fn test_userdata_impl_async() -> Result<()> {
+    let lua = Lua::new();
+
+    let counter: AnyUserData = lua.create_userdata(Counter::new(2))?;
+    lua.globals().set("counter", counter)?;
+
+    let res: i64 = lua.load("counter:get_async(3)").eval_async().await?;
+    assert_eq!(res, 5);
+
+    Ok(())
+}
+
+#[derive(mlua::ToLua, mlua::FromLua, Debug, PartialEq)]
+struct Server {
+    host: String,
+    #[lua(default)]
+    port: u16,
+    #[lua(rename = "tls")]
+    use_tls: bool,
+    #[lua(skip)]
+    connections: usize,
+    tags: Option<Vec<String>>,
+}
+
+#[derive(mlua::ToLua, mlua::FromLua, Debug, PartialEq)]
+struct Point(i32, i32);
+
+#[derive(mlua::ToLua, mlua::FromLua, Debug, PartialEq)]
+struct Id(i64);
+
+#[derive(mlua::ToLua, mlua::FromLua, Debug, PartialEq)]
+struct Marker;
+
+#[derive(mlua::ToLua, mlua::FromLua, Debug, PartialEq)]
+struct Cached(#[lua(skip)] Option<String>);
+
+#[derive(mlua::ToLua, mlua::FromLua, Debug, PartialEq)]
+enum Shape {
+    Empty,
+    #[lua(rename = "circle")]
+    Circle(f64),
+    Line(Point, Point),
+    Rect {
+        w: f64,
+        h: f64,
+    },
+}
+
+#[test]
+fn test_derive_struct() -> Result<()> {
+    let lua = Lua::new();
+    let globals = lua.globals();
+
+    let server = Server {
+        host: "localhost".into(),
+        port: 8080,
+        use_tls: true,
+        connections: 10,
+        tags: None,
+    };
+    globals.set("server", server)?;
+    globals.set("point", Point(1, 2))?;
+    globals.set("id", Id(7))?;
+    lua.load(
+        r#"
+        assert(server.host == "localhost")
+        assert(server.port == 8080)
+        assert(server.tls == true and server.use_tls == nil)
+        assert(server.connections == nil)
+        assert(point[1] == 1 and point[2] == 2)
+        assert(id == 7)
+    "#,
+    )
+    .exec()?;
+
+    let server: Server = lua
+        .load(r#"{host = "example.com", tls = false, tags = {"a", "b"}}"#)
+        .eval()?;
+    assert_eq!(
+        server,
+        Server {
+            host: "example.com".into(),
+            port: 0,
+            use_tls: false,
+            connections: 0,
+            tags: Some(vec!["a".into(), "b".into()]),
+        }
+    );
+    assert_eq!(lua.load("{3, 4}").eval::<Point>()?, Point(3, 4));
+    assert_eq!(lua.load("5").eval::<Id>()?, Id(5));
+
+    globals.set("marker", Marker)?;
+    globals.set("cached", Cached(Some("data".into())))?;
+    lua.load(
+        r#"
+        assert(type(marker) == "table" and next(marker) == nil)
+        assert(type(cached) == "table" and next(cached) == nil)
+    "#,
+    )
+    .exec()?;
+    assert_eq!(lua.load("{}").eval::<Marker>()?, Marker);
+    assert_eq!(lua.load("{}").eval::<Cached>()?, Cached(None));
+
+    // Fields are read with raw access
+    let server: Server = lua
+        .load(
+            r#"
+            setmetatable({host = "raw", tls = true}, {
+                __index = function() error("metamethod called") end,
+            })
+        "#,
+        )
+        .eval()?;
+    assert_eq!(server.host, "raw");
+    assert_eq!(server.tags, None);
+
+    match lua.load(r#"{host = "a", port = "x"}"#).eval::<Server>() {
+        Err(Error::FromLuaConversionError {
+            from,
+            to,
+            message: Some(msg),
+        }) => {
+            assert_eq!(from, "string");
+            assert_eq!(to, "Server");
+            assert!(
+                msg.starts_with("field `port`: "),
+                "unexpected message: {}",
+                msg
+            );
+        }
+        r => panic!("expected FromLuaConversionError, got {:?}", r),
+    }
+
+    match lua.load("42").eval::<Server>() {
+        Err(Error::FromLuaConversionError { from, to, .. }) => {
+            assert_eq!(from, "integer");
+            assert_eq!(to, "Server");
+        }
+        r => panic!("expected FromLuaConversionError, got {:?}", r),
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_derive_enum() -> Result<()> {
+    let lua = Lua::new();
+    let globals = lua.globals();
+
+    globals.set("empty", Shape::Empty)?;
+    globals.set("circle", Shape::Circle(1.5))?;
+    globals.set("line", Shape::Line(Point(0, 0), Point(1, 1)))?;
+    globals.set("rect", Shape::Rect { w: 2.0, h: 3.0 })?;
+    lua.load(
+        r#"
+        assert(empty == "Empty")
+        assert(circle.circle == 1.5)
+        assert(line.Line[2][1] == 1)
+        assert(rect.Rect.w == 2 and rect.Rect.h == 3)
+    "#,
+    )
+    .exec()?;
+
+    let roundtrip = |shape: Shape| -> Result<Shape> { lua.unpack(lua.pack(shape)?) };
+    assert_eq!(roundtrip(Shape::Empty)?, Shape::Empty);
+    assert_eq!(roundtrip(Shape::Circle(1.5))?, Shape::Circle(1.5));
+    assert_eq!(
+        roundtrip(Shape::Line(Point(0, 0), Point(1, 1)))?,
+        Shape::Line(Point(0, 0), Point(1, 1))
+    );
+    assert_eq!(
+        roundtrip(Shape::Rect { w: 2.0, h: 3.0 })?,
+        Shape::Rect { w: 2.0, h: 3.0 }
+    );
+    assert_eq!(
+        lua.load("{Rect = {w = 1, h = 2}}").eval::<Shape>()?,
+        Shape::Rect { w: 1.0, h: 2.0 }
+    );
+
+    match lua.load(r#""Triangle""#).eval::<Shape>() {
+        Err(Error::FromLuaConversionError {
+            message: Some(msg), ..
+        }) => assert_eq!(msg, "unknown variant `Triangle`"),
+        r => panic!("expected FromLuaConversionError, got {:?}", r),
+    }
+    assert!(lua.load("{Empty = 1, Rect = {}}").eval::<Shape>().is_err());
+
+    Ok(())
+}
@@ -14,10 +14,10 @@ use std::sync::Arc;
 
 use mlua::{Lua, Result, UserData};
 
-#[cfg(any(feature = "lua54", feature = "lua53", feature = "lua52"))]
+#[cfg(not(feature = "luajit"))]
 use mlua::Error;
 
-#[cfg(any(feature = "lua54", feature = "lua53", feature = "lua52"))]
+#[cfg(not(feature = "luajit"))]
 #[test]
 fn test_memory_limit() -> Result<()> {
     let lua = Lua::new();
@@ -45,6 +45,185 @@ fn test_memory_limit() -> Result<()> {
     Ok(())
 }
 
+#[cfg(not(feature = "luajit"))]
+#[test]
+fn test_custom_allocator() -> Result<()> {
+    use std::alloc::{self, Layout};
+    use std::sync::atomic::{AtomicIsize, Ordering};
+
+    use mlua::{Allocator, StdLib};
+
+    struct TrackingAllocator(Arc<AtomicIsize>);
+
+    unsafe impl Allocator for TrackingAllocator {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            self.0.fetch_add(layout.size() as isize, Ordering::Relaxed);
+            alloc::alloc(layout)
+        }
+
+        unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+            let diff = new_size as isize - layout.size() as isize;
+            self.0.fetch_add(diff, Ordering::Relaxed);
+            alloc::realloc(ptr, layout, new_size)
+        }
+
+        unsafe fn free(&self, ptr: *mut u8, layout: Layout) {
+            self.0.fetch_sub(layout.size() as isize, Ordering::Relaxed);
+            alloc::dealloc(ptr, layout)
+        }
+    }
+
+    let allocated = Arc::new(AtomicIsize::new(0));
+    let lua = Lua::new_with_allocator(StdLib::ALL_SAFE, TrackingAllocator(allocated.clone()))?;
+    assert!(allocated.load(Ordering::Relaxed) > 0);
+    assert_eq!(
+        lua.used_memory(),
+        allocated.load(Ordering::Relaxed) as usize
+    );
+
+    let f = lua
+        .load("local t = {}; for i = 1,10000 do t[i] = i end")
+        .into_function()?;
+    f.call::<_, ()>(())?;
+    assert_eq!(
+        lua.used_memory(),
+        allocated.load(Ordering::Relaxed) as usize
+    );
+
+    lua.gc_collect()?;
+    lua.set_memory_limit(lua.used_memory() + 10000)?;
+    match f.call::<_, ()>(()) {
+        Err(Error::MemoryError(_)) => {}
+        something_else => panic!("did not trigger memory error: {:?}", something_else),
+    };
+    lua.set_memory_limit(0)?;
+
+    match Lua::new_with_allocator(StdLib::DEBUG, TrackingAllocator(allocated.clone())) {
+        Err(Error::SafetyError(_)) => {}
+        Err(e) => panic!("expected SafetyError, got {:?}", e),
+        Ok(_) => panic!("expected SafetyError, got new Lua state"),
+    }
+
+    drop(f);
+    drop(lua);
+    assert_eq!(allocated.load(Ordering::Relaxed), 0);
+
+    Ok(())
+}
+
+#[cfg(feature = "luajit")]
+#[test]
+fn test_soft_memory_limit() -> Result<()> {
+    let lua = Lua::new();
+
+    let initial_memory = lua.used_memory();
+    assert!(initial_memory > 0);
+
+    // The limit is checked by the GC and does not affect code running below it
+    let memory_limit = initial_memory + 10_000_000;
+    assert_eq!(lua.set_memory_limit(memory_limit)?, 0);
+    lua.load(
+        r#"
+        local t = {}
+        for i = 1,10000 do t[i] = {} end
+        collectgarbage("collect")
+        collectgarbage("collect")
+    "#,
+    )
+    .exec()?;
+    lua.gc_collect()?;
+
+    assert_eq!(lua.set_memory_limit(0)?, memory_limit);
+    lua.gc_collect()?;
+
+    Ok(())
+}
+
+#[cfg(feature = "luajit")]
+#[test]
+fn test_soft_memory_limit_exceeded() -> Result<()> {
+    let lua = Lua::new();
+
+    // Disable JIT, as compiled code does not trigger hooks used to stop the running code
+    lua.load("jit.off()").exec()?;
+
+    lua.set_memory_limit(lua.used_memory() + 10_000_000)?;
+    let f = lua
+        .load("local t = {}; for i = 1,1000000 do t[i] = {} end")
+        .into_function()?;
+    match f.call::<_, ()>(()) {
+        Err(mlua::Error::MemoryError(_)) => {}
+        something_else => panic!("did not trigger memory error: {:?}", something_else),
+    };
+
+    lua.set_memory_limit(0)?;
+    lua.gc_collect()?;
+    f.call::<_, ()>(()).expect("should trigger no memory limit");
+
+    Ok(())
+}
+
+#[cfg(not(feature = "luajit"))]
+#[test]
+fn test_memory_stats() -> Result<()> {
+    let lua = Lua::new();
+    assert!(lua.memory_stats().is_none());
+
+    lua.enable_memory_stats(Some(10))?;
+    lua.load(
+        r#"
+        function fill(n)
+            local t = {}
+            for i = 1, n do t[i] = {} end
+            return t
+        end
+        local t = fill(1000)
+        t = nil
+        collectgarbage()
+    "#,
+    )
+    .exec()?;
+
+    let stats = lua.memory_stats().expect("memory stats are enabled");
+    assert_eq!(stats.used_memory, lua.used_memory());
+    assert!(stats.peak_memory > stats.used_memory);
+    assert!(stats.allocations >= 1000);
+    assert!(stats.deallocations >= 1000);
+    assert!(stats.allocated_bytes > 0);
+    assert_eq!(stats.failed_allocations, 0);
+
+    let classes = stats.size_classes.iter().map(|&(_, n)| n).sum::<u64>();
+    assert_eq!(classes, stats.allocations + stats.reallocations);
+    assert!(stats.size_classes.windows(2).all(|w| w[0].0 < w[1].0));
+
+    let fill = &stats.functions[0];
+    assert_eq!(fill.name.as_deref(), Some("fill"));
+    assert_eq!(fill.line_defined, Some(2));
+    assert!(fill.allocations >= 1000);
+
+    // Rejected allocations are counted as failed
+    lua.set_memory_limit(lua.used_memory() + 10000)?;
+    assert!(lua.load("fill(1000)").exec().is_err());
+    lua.set_memory_limit(0)?;
+    assert!(lua.memory_stats().unwrap().failed_allocations > 0);
+
+    lua.disable_memory_stats();
+    assert!(lua.memory_stats().is_none());
+
+    Ok(())
+}
+
+#[cfg(feature = "luajit")]
+#[test]
+fn test_memory_stats_not_available() {
+    let lua = Lua::new();
+    match lua.enable_memory_stats(None) {
+        Err(mlua::Error::MemoryStatsNotAvailable) => {}
+        r => panic!("expected MemoryStatsNotAvailable, got {:?}", r),
+    }
+    assert!(lua.memory_stats().is_none());
+}
+
 #[test]
 fn test_gc_control() -> Result<()> {
     let lua = Lua::new();
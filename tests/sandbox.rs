@@ -0,0 +1,217 @@
+#![cfg_attr(
+    all(feature = "luajit", target_os = "macos", target_arch = "x86_64"),
+    feature(link_args)
+)]
+
+#[cfg_attr(
+    all(feature = "luajit", target_os = "macos", target_arch = "x86_64"),
+    link_args = "-pagezero_size 10000 -image_base 100000000",
+    allow(unused_attributes)
+)]
+extern "system" {}
+
+use mlua::{Error, Function, Lua, Result, Table, Value};
+
+#[test]
+fn test_sandbox() -> Result<()> {
+    let lua = Lua::new();
+    lua.globals().set("shared", lua.create_table()?)?;
+
+    assert!(!lua.is_sandboxed());
+    lua.sandbox(true)?;
+    assert!(lua.is_sandboxed());
+
+    // Every chunk has its own environment
+    lua.load("a = 1; assert(a == 1)").exec()?;
+    assert_eq!(lua.load("a").eval::<Option<i32>>()?, None);
+    assert_eq!(lua.globals().get::<_, Option<i32>>("a")?, None);
+
+    // Globals and standard library tables are read-only
+    lua.load(
+        r#"
+        assert(string.len("abc") == 3)
+        assert(("abc"):upper() == "ABC")
+        assert(not pcall(function() string.len = nil end))
+        assert(not pcall(function() shared.x = 1 end))
+        assert(not pcall(function() _G.print = nil end))
+        assert(not pcall(rawset, table, "insert", nil))
+        assert(not pcall(setmetatable, math, {}))
+        assert(not pcall(table.insert, string, "x"))
+        assert(not pcall(table.remove, _G))
+        assert(not pcall(table.sort, math))
+        local t = {3, 1, 2}
+        table.insert(t, 4)
+        table.sort(t)
+        assert(table.remove(t, 1) == 1 and #t == 3)
+        assert(getmetatable("") == false)
+        assert(debug == nil and dofile == nil and loadfile == nil)
+        assert(io == nil and package == nil and require == nil)
+        assert(os.execute == nil and os.remove == nil and os.rename == nil)
+        assert(os.exit == nil and os.getenv == nil and os.tmpname == nil)
+        assert(type(os.time()) == "number" and type(os.clock()) == "number")
+        for k in pairs(_G) do
+            assert(k ~= "io" and k ~= "package" and k ~= "require")
+        end
+
+        print = 1
+        assert(print == 1 and type(_G.print) == "function")
+    "#,
+    )
+    .exec()?;
+    let string: Table = lua.globals().get("string")?;
+    assert!(string.get::<_, Option<mlua::Function>>("len")?.is_some());
+
+    // Values set by the host are visible in sandbox
+    lua.globals().set("host_value", 42)?;
+    assert_eq!(lua.load("host_value").eval::<i32>()?, 42);
+
+    // Chunks loaded from Lua are isolated as well and must be text
+    lua.load(
+        r#"
+        local f = load("b = 2; return b")
+        assert(f() == 2 and b == nil)
+        local env = {}
+        assert(load("c = 3", "chunk", "t", env))()
+        assert(env.c == 3 and c == nil)
+        assert(not pcall(function() load("d = 4", "chunk", "t", _G)() end))
+        local f, err = load(string.dump(function() end))
+        assert(f == nil and err ~= nil)
+        local f, err = load("return +")
+        assert(f == nil and err ~= nil)
+        local pieces = { "local x ", "= 1", "0", " return x" }
+        local i = 0
+        f = load(function()
+            i = i + 1
+            return pieces[i]
+        end)
+        assert(f() == 10)
+    "#,
+    )
+    .exec()?;
+
+    // Errors other than syntax errors are propagated
+    match lua
+        .load(r#"load(function() error("reader failed") end)"#)
+        .exec()
+    {
+        Err(Error::CallbackError { cause, .. }) => match *cause {
+            Error::RuntimeError(ref msg) => assert!(msg.contains("reader failed")),
+            ref err => panic!("expected RuntimeError, got {:?}", err),
+        },
+        r => panic!("expected CallbackError, got {:?}", r),
+    }
+
+    // An explicit `nil` environment is not replaced by the sandbox one
+    let f: Function = lua
+        .load(r#"load("return print", "chunk", "t", nil)"#)
+        .eval()?;
+    #[cfg(any(feature = "lua54", feature = "lua53", feature = "lua52"))]
+    assert!(f.call::<_, Value>(()).is_err());
+    #[cfg(any(feature = "lua51", feature = "luajit"))]
+    assert_eq!(f.call::<_, Value>(())?.type_name(), "function");
+
+    // Explicit environment bypasses the sandbox
+    let env = lua.create_table()?;
+    lua.load("e = 5").set_environment(env.clone())?.exec()?;
+    assert_eq!(env.get::<_, i32>("e")?, 5);
+
+    lua.sandbox(false)?;
+    assert!(!lua.is_sandboxed());
+
+    lua.load("f = 6").exec()?;
+    assert_eq!(lua.globals().get::<_, i32>("f")?, 6);
+    lua.load(r#"assert(getmetatable("") ~= false)"#).exec()?;
+
+    Ok(())
+}
+
+#[test]
+fn test_sandbox_isolation() -> Result<()> {
+    let lua = Lua::new();
+    lua.sandbox(true)?;
+
+    // Read-only tables cannot be used to pass values between chunks
+    lua.load(
+        r#"
+        pcall(table.insert, string, "leaked")
+        pcall(rawset, string, "leaked", true)
+    "#,
+    )
+    .exec()?;
+    lua.load(r#"assert(string[1] == nil and string.leaked == nil)"#)
+        .exec()?;
+
+    // Memory errors are not turned into `nil, message`
+    lua.set_memory_limit(lua.used_memory() + 100_000)?;
+    let result = lua
+        .load(
+            r#"
+            local piece = ("x"):rep(1000)
+            load(function() return piece end)
+        "#,
+        )
+        .exec();
+    lua.set_memory_limit(0)?;
+    match result {
+        Err(Error::CallbackError { cause, .. }) => match *cause {
+            Error::MemoryError(_) => {}
+            ref err => panic!("expected MemoryError, got {:?}", err),
+        },
+        r => panic!("expected CallbackError, got {:?}", r),
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_sandbox_string_metatable() -> Result<()> {
+    let lua = unsafe { Lua::unsafe_new() };
+    lua.load(r#"rawset(debug.getmetatable(""), "__metatable", "locked")"#)
+        .exec()?;
+
+    lua.sandbox(true)?;
+    lua.load(r#"assert(getmetatable("") == false)"#).exec()?;
+    lua.sandbox(false)?;
+
+    // The previous protection is restored
+    lua.load(r#"assert(getmetatable("") == "locked")"#).exec()?;
+
+    Ok(())
+}
+
+#[test]
+fn test_sandbox_copy_value() -> Result<()> {
+    let lua1 = Lua::new();
+    let lua2 = Lua::new();
+    lua2.sandbox(true)?;
+
+    // Copied functions get a sandboxed environment
+    let f = lua1
+        .load("function() g = 1; return print ~= nil end")
+        .eval()?;
+    let f = lua2.copy_value(f)?;
+    assert!(lua2.unpack::<mlua::Function>(f)?.call::<_, bool>(())?);
+    assert_eq!(lua2.globals().get::<_, Option<i32>>("g")?, None);
+
+    Ok(())
+}
+
+#[test]
+#[cfg(any(feature = "lua51", feature = "luajit"))]
+fn test_sandbox_fenv() -> Result<()> {
+    let lua = Lua::new();
+    lua.sandbox(true)?;
+
+    lua.load(
+        r#"
+        assert(getfenv(0) == _G)
+        assert(getfenv(1) ~= _G)
+        assert(not pcall(setfenv, 0, {}))
+        assert(not pcall(setfenv, print, {}))
+        local function f() return x end
+        setfenv(f, { x = 1 })
+        assert(f() == 1)
+    "#,
+    )
+    .exec()
+}
@@ -13,7 +13,9 @@ extern "system" {}
 use std::cell::Cell;
 use std::rc::Rc;
 
-use mlua::{Error, Function, Lua, MetaMethod, Result, String, UserData, UserDataMethods};
+use mlua::{
+    Error, Function, Lua, MetaMethod, Result, String, UserData, UserDataFields, UserDataMethods,
+};
 
 #[test]
 fn scope_func() -> Result<()> {
@@ -145,6 +147,50 @@ fn scope_userdata_methods() -> Result<()> {
     Ok(())
 }
 
+#[test]
+fn scope_userdata_fields() -> Result<()> {
+    struct MyUserData<'a>(&'a Cell<i64>);
+
+    impl<'a> UserData for MyUserData<'a> {
+        fn add_fields<'lua, F: UserDataFields<'lua, Self>>(fields: &mut F) {
+            fields.add_field_method_get("val", |_, data| Ok(data.0.get()));
+            fields.add_field_method_set("val", |_, data, val| {
+                data.0.set(val);
+                Ok(())
+            });
+        }
+
+        fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+            methods.add_method("inc", |_, data, ()| {
+                data.0.set(data.0.get() + 1);
+                Ok(())
+            });
+        }
+    }
+
+    let lua = Lua::new();
+
+    let i = Cell::new(42);
+    let f: Function = lua
+        .load(
+            r#"
+            function(u)
+                assert(u.val == 42)
+                u.val = 10
+                u:inc()
+                assert(u.val == 11)
+            end
+        "#,
+        )
+        .eval()?;
+
+    lua.scope(|scope| f.call::<_, ()>(scope.create_nonstatic_userdata(MyUserData(&i))?))?;
+
+    assert_eq!(i.get(), 11);
+
+    Ok(())
+}
+
 #[test]
 fn scope_userdata_functions() -> Result<()> {
     struct MyUserData<'a>(&'a i64);
@@ -0,0 +1,456 @@
+#![cfg(feature = "serialize")]
+#![cfg_attr(
+    all(feature = "luajit", target_os = "macos", target_arch = "x86_64"),
+    feature(link_args)
+)]
+
+#[cfg_attr(
+    all(feature = "luajit", target_os = "macos", target_arch = "x86_64"),
+    link_args = "-pagezero_size 10000 -image_base 100000000",
+    allow(unused_attributes)
+)]
+extern "system" {}
+
+use std::collections::HashMap;
+
+use mlua::serde::ser::EnumTagging;
+use mlua::{
+    DeserializeOptions, Error, Lua, LuaSerdeExt, Result as LuaResult, SerializeOptions, Value,
+};
+use serde::{Deserialize, Serialize, Serializer};
+
+#[test]
+fn test_serialize() -> Result<(), Box<dyn std::error::Error>> {
+    #[derive(Serialize)]
+    struct MyUserData(i64, String);
+
+    #[derive(Serialize)]
+    struct Test {
+        int: i64,
+        float: f64,
+        string: String,
+        seq: Vec<u8>,
+        map: HashMap<String, i32>,
+        tuple: (u8, u8),
+        empty: Vec<()>,
+        opt: Option<bool>,
+        userdata: MyUserData,
+    }
+
+    let lua = Lua::new();
+    let globals = lua.globals();
+    globals.set("null", lua.null())?;
+
+    let mut map = HashMap::new();
+    map.insert("x".to_string(), 1);
+
+    let test = Test {
+        int: 123,
+        float: 321.99,
+        string: "hello".into(),
+        seq: vec![1, 2, 3],
+        map,
+        tuple: (5, 6),
+        empty: Vec::new(),
+        opt: None,
+        userdata: MyUserData(1, "a".into()),
+    };
+    globals.set("data", lua.to_value(&test)?)?;
+
+    lua.load(
+        r#"
+        assert(data["int"] == 123)
+        assert(data["float"] == 321.99)
+        assert(data["string"] == "hello")
+        assert(#data["seq"] == 3 and data["seq"][3] == 3)
+        assert(data["map"]["x"] == 1)
+        assert(data["tuple"][1] == 5 and data["tuple"][2] == 6)
+        assert(#data["empty"] == 0)
+        assert(data["opt"] == null)
+        assert(data["userdata"][1] == 1 and data["userdata"][2] == "a")
+    "#,
+    )
+    .exec()?;
+
+    Ok(())
+}
+
+#[test]
+fn test_serialize_array_metatable() -> LuaResult<()> {
+    let lua = Lua::new();
+
+    let seq = match lua.to_value(&vec![1, 2, 3])? {
+        Value::Table(t) => t,
+        v => panic!("expected table, got {:?}", v),
+    };
+    assert_eq!(seq.get_metatable(), Some(lua.array_metatable()));
+    assert_eq!(seq.len()?, 3);
+
+    let options = SerializeOptions::new().set_array_metatable(false);
+    let seq = match lua.to_value_with(&vec![1, 2, 3], options)? {
+        Value::Table(t) => t,
+        v => panic!("expected table, got {:?}", v),
+    };
+    assert_eq!(seq.get_metatable(), None);
+
+    // The array metatable is protected
+    lua.globals().set("seq", lua.to_value(&[1, 2])?)?;
+    assert!(!lua.load("getmetatable(seq)").eval::<bool>()?);
+
+    Ok(())
+}
+
+#[test]
+fn test_serialize_null() -> LuaResult<()> {
+    let lua = Lua::new();
+
+    assert_eq!(lua.to_value(&())?, lua.null());
+    assert_eq!(lua.to_value(&Option::<u8>::None)?, lua.null());
+
+    let options = SerializeOptions::new()
+        .serialize_none_to_null(false)
+        .serialize_unit_to_null(false);
+    assert_eq!(lua.to_value_with(&(), options)?, Value::Nil);
+    assert_eq!(lua.to_value_with(&Option::<u8>::None, options)?, Value::Nil);
+
+    Ok(())
+}
+
+#[test]
+fn test_serialize_enum() -> LuaResult<()> {
+    #[derive(Serialize)]
+    enum E {
+        Unit,
+        Newtype(u8),
+        Tuple(u8, u8),
+        Struct { a: u8 },
+    }
+
+    let lua = Lua::new();
+    let globals = lua.globals();
+
+    globals.set("unit", lua.to_value(&E::Unit)?)?;
+    globals.set("newtype", lua.to_value(&E::Newtype(1))?)?;
+    globals.set("tuple", lua.to_value(&E::Tuple(1, 2))?)?;
+    globals.set("struct", lua.to_value(&E::Struct { a: 1 })?)?;
+    lua.load(
+        r#"
+        assert(unit == "Unit")
+        assert(newtype["Newtype"] == 1)
+        assert(tuple["Tuple"][2] == 2)
+        assert(struct["Struct"]["a"] == 1)
+    "#,
+    )
+    .exec()?;
+
+    let options = SerializeOptions::new().enum_tagging(EnumTagging::Adjacent {
+        tag: "t",
+        content: "c",
+    });
+    globals.set("unit", lua.to_value_with(&E::Unit, options)?)?;
+    globals.set("newtype", lua.to_value_with(&E::Newtype(1), options)?)?;
+    globals.set("tuple", lua.to_value_with(&E::Tuple(1, 2), options)?)?;
+    globals.set("struct", lua.to_value_with(&E::Struct { a: 1 }, options)?)?;
+    lua.load(
+        r#"
+        assert(unit["t"] == "Unit" and unit["c"] == nil)
+        assert(newtype["t"] == "Newtype" and newtype["c"] == 1)
+        assert(tuple["t"] == "Tuple" and tuple["c"][2] == 2)
+        assert(struct["t"] == "Struct" and struct["c"]["a"] == 1)
+    "#,
+    )
+    .exec()
+}
+
+#[test]
+fn test_serialize_failure() -> LuaResult<()> {
+    struct Failing;
+
+    impl Serialize for Failing {
+        fn serialize<S: Serializer>(&self, _serializer: S) -> Result<S::Ok, S::Error> {
+            Err(serde::ser::Error::custom("cannot serialize"))
+        }
+    }
+
+    let lua = Lua::new();
+    match lua.to_value(&vec![Failing]) {
+        Err(Error::ToLuaConversionError {
+            message: Some(msg), ..
+        }) => assert_eq!(msg, "cannot serialize"),
+        r => panic!("expected ToLuaConversionError, got {:?}", r),
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_deserialize() -> Result<(), Box<dyn std::error::Error>> {
+    #[derive(Deserialize, Debug, PartialEq)]
+    struct Test {
+        int: u32,
+        float: f64,
+        string: String,
+        seq: Vec<u8>,
+        map: HashMap<String, i32>,
+        tuple: (u8, u8),
+        empty: Vec<()>,
+        opt: Option<bool>,
+        unit: (),
+    }
+
+    let lua = Lua::new();
+    lua.globals().set("null", lua.null())?;
+
+    let value = lua
+        .load(
+            r#"
+        {
+            int = 123,
+            float = 321.99,
+            string = "hello",
+            seq = {1, 2, 3},
+            map = {x = 1},
+            tuple = {5, 6},
+            empty = {},
+            opt = null,
+            unit = null,
+            ignored = function() end,
+        }
+    "#,
+        )
+        .eval()?;
+    let test: Test = lua.from_value(value)?;
+
+    let mut map = HashMap::new();
+    map.insert("x".to_string(), 1);
+    let expected = Test {
+        int: 123,
+        float: 321.99,
+        string: "hello".into(),
+        seq: vec![1, 2, 3],
+        map,
+        tuple: (5, 6),
+        empty: Vec::new(),
+        opt: None,
+        unit: (),
+    };
+    assert_eq!(test, expected);
+
+    // Integral floats are accepted as integers
+    let value = lua.load("4.0").eval()?;
+    assert_eq!(lua.from_value::<u8>(value)?, 4);
+
+    Ok(())
+}
+
+#[test]
+fn test_deserialize_any() -> LuaResult<()> {
+    #[derive(Deserialize, Debug, PartialEq)]
+    #[serde(untagged)]
+    enum Any {
+        Int(i64),
+        Float(f64),
+        Str(String),
+        Seq(Vec<Any>),
+        Map(HashMap<String, Any>),
+    }
+
+    let lua = Lua::new();
+    lua.globals().set("array_mt", lua.array_metatable())?;
+
+    let value = lua
+        .load("{1, 2.5, 'x', {}, setmetatable({}, array_mt), {a = {1}}}")
+        .eval()?;
+    let any: Any = lua.from_value(value)?;
+
+    let mut map = HashMap::new();
+    map.insert("a".to_string(), Any::Seq(vec![Any::Int(1)]));
+    assert_eq!(
+        any,
+        Any::Seq(vec![
+            Any::Int(1),
+            Any::Float(2.5),
+            Any::Str("x".into()),
+            Any::Map(HashMap::new()),
+            Any::Seq(Vec::new()),
+            Any::Map(map),
+        ])
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_deserialize_enum() -> LuaResult<()> {
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    enum E {
+        Unit,
+        Newtype(u8),
+        Tuple(u8, u8),
+        Struct { a: u8 },
+    }
+
+    let lua = Lua::new();
+
+    for &tagging in &[
+        EnumTagging::External,
+        EnumTagging::Adjacent {
+            tag: "t",
+            content: "c",
+        },
+    ] {
+        let ser_options = SerializeOptions::new().enum_tagging(tagging);
+        let de_options = DeserializeOptions::new().enum_tagging(tagging);
+        for e in &[E::Unit, E::Newtype(1), E::Tuple(1, 2), E::Struct { a: 1 }] {
+            let value = lua.to_value_with(e, ser_options)?;
+            assert_eq!(&lua.from_value_with::<E>(value, de_options)?, e);
+        }
+    }
+
+    let value = lua.load("{Struct = {a = 1}, Unit = true}").eval()?;
+    assert!(lua.from_value::<E>(value).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_deserialize_unsupported() -> LuaResult<()> {
+    let lua = Lua::new();
+
+    let value = lua.load("{f = print}").eval::<Value>()?;
+    match lua.from_value::<HashMap<String, ()>>(value.clone()) {
+        Err(Error::FromLuaConversionError {
+            message: Some(msg), ..
+        }) => assert_eq!(msg, "f: unsupported value type `function`"),
+        r => panic!("expected FromLuaConversionError, got {:?}", r),
+    }
+
+    let options = DeserializeOptions::new().deny_unsupported_types(false);
+    let map = lua.from_value_with::<HashMap<String, ()>>(value, options)?;
+    assert_eq!(map.len(), 1);
+
+    Ok(())
+}
+
+#[test]
+fn test_deserialize_error_path() -> LuaResult<()> {
+    #[derive(Deserialize, Debug)]
+    #[allow(dead_code)]
+    struct Server {
+        host: String,
+        port: u16,
+    }
+
+    #[derive(Deserialize, Debug)]
+    #[allow(dead_code)]
+    struct Config {
+        servers: Vec<Server>,
+    }
+
+    #[derive(Deserialize, Debug)]
+    #[allow(dead_code)]
+    struct Root {
+        config: Config,
+    }
+
+    let lua = Lua::new();
+    let value = lua
+        .load(
+            r#"
+        {
+            config = {
+                servers = {
+                    {host = "a", port = 80},
+                    {host = "b", port = 81},
+                    {host = "c", port = "eighty"},
+                },
+            },
+        }
+    "#,
+        )
+        .eval()?;
+
+    match lua.from_value::<Root>(value) {
+        Err(Error::FromLuaConversionError {
+            from,
+            message: Some(msg),
+            ..
+        }) => {
+            assert_eq!(from, "string");
+            assert!(
+                msg.starts_with("config.servers[3].port: invalid type: string"),
+                "unexpected message: {}",
+                msg
+            );
+        }
+        r => panic!("expected FromLuaConversionError, got {:?}", r),
+    }
+
+    let value = lua.load("{config = {servers = {{host = 'a'}}}}").eval()?;
+    match lua.from_value::<Root>(value) {
+        Err(Error::FromLuaConversionError {
+            from,
+            message: Some(msg),
+            ..
+        }) => {
+            assert_eq!(from, "table");
+            assert_eq!(msg, "config.servers[1]: missing field `port`");
+        }
+        r => panic!("expected FromLuaConversionError, got {:?}", r),
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_deserialize_recursive() -> LuaResult<()> {
+    #[derive(Deserialize, Debug)]
+    #[allow(dead_code)]
+    struct Node {
+        next: Option<Box<Node>>,
+    }
+
+    let lua = Lua::new();
+
+    let value = lua.load("local t = {}; t.next = t; return t").eval()?;
+    match lua.from_value::<Node>(value) {
+        Err(Error::FromLuaConversionError {
+            message: Some(msg), ..
+        }) => assert_eq!(msg, "next: recursive table detected"),
+        r => panic!("expected FromLuaConversionError, got {:?}", r),
+    }
+
+    #[derive(Deserialize, Debug)]
+    #[allow(dead_code)]
+    enum E {
+        A(Box<E>),
+        B,
+    }
+
+    for &(code, tagging) in &[
+        ("local t = {}; t.A = t; return t", EnumTagging::External),
+        (
+            "local t = {t = 'A'}; t.c = t; return t",
+            EnumTagging::Adjacent {
+                tag: "t",
+                content: "c",
+            },
+        ),
+    ] {
+        let value = lua.load(code).eval()?;
+        let options = DeserializeOptions::new().enum_tagging(tagging);
+        match lua.from_value_with::<E>(value, options) {
+            Err(Error::FromLuaConversionError {
+                message: Some(msg), ..
+            }) => assert!(msg.ends_with("recursive table detected"), "{}", msg),
+            r => panic!("expected FromLuaConversionError, got {:?}", r),
+        }
+    }
+
+    // Shared (but not recursive) tables are fine
+    let value = lua.load("local t = {1, 2}; return {a = t, b = t}").eval()?;
+    let map: HashMap<String, Vec<u8>> = lua.from_value(value)?;
+    assert_eq!(map["a"], map["b"]);
+
+    Ok(())
+}
@@ -162,6 +162,135 @@ fn test_table_sequence_from() -> Result<()> {
     Ok(())
 }
 
+#[test]
+fn test_table_for_each() -> Result<()> {
+    let lua = Lua::new();
+
+    let t = lua.create_table()?;
+    t.extend_sequence(1..=1000)?;
+    t.set("a", "x")?;
+    t.set("b", "y")?;
+
+    let mut sum = 0;
+    let mut keys = Vec::new();
+    t.for_each(|k: Value, v: Value| {
+        match (k, v) {
+            (Value::Integer(i), Value::Integer(v)) if i == v => sum += v,
+            (Value::String(k), Value::String(_)) => keys.push(k.to_str()?.to_owned()),
+            (k, v) => panic!("unexpected pair {:?} = {:?}", k, v),
+        }
+        Ok(())
+    })?;
+    assert_eq!(sum, 500500);
+    keys.sort();
+    assert_eq!(keys, vec!["a", "b"]);
+
+    // Errors stop the iteration
+    let mut count = 0;
+    let res = t.for_each(|_: Value, _: Value| {
+        count += 1;
+        Err::<(), _>(mlua::Error::RuntimeError("stop".to_string()))
+    });
+    assert!(res.is_err());
+    assert_eq!(count, 1);
+    assert!(t.for_each(|_: i64, _: i64| Ok(())).is_err());
+
+    // Fields may be cleared during the traversal
+    t.for_each(|k: Value, _: Value| t.raw_set(k, Nil))?;
+    assert_eq!(t.clone().pairs::<Value, Value>().count(), 0);
+
+    Ok(())
+}
+
+#[test]
+fn test_table_pairs_ref() -> Result<()> {
+    let lua = Lua::new();
+
+    let t: Table = lua.load("{ 1, 2, 3, four = 4 }").eval()?;
+    let mut pairs = t
+        .pairs_ref::<Value, i64>()
+        .collect::<Result<Vec<_>>>()?
+        .into_iter()
+        .map(|(_, v)| v)
+        .collect::<Vec<_>>();
+    pairs.sort_unstable();
+    assert_eq!(pairs, vec![1, 2, 3, 4]);
+
+    // Iterators are independent from each other
+    let mut outer = 0;
+    for pair in t.pairs_ref::<Value, i64>() {
+        pair?;
+        outer += 1;
+        assert_eq!(t.pairs_ref::<Value, Value>().count(), 4);
+    }
+    assert_eq!(outer, 4);
+
+    // The iteration stops at the first conversion error
+    let t: Table = lua.load("{ a = 1, b = 2, c = 3 }").eval()?;
+    let mut iter = t.pairs_ref::<i64, i64>();
+    assert!(iter.next().unwrap().is_err());
+    assert!(iter.next().is_none());
+
+    Ok(())
+}
+
+#[test]
+fn test_table_push_pop() -> Result<()> {
+    let lua = Lua::new();
+
+    let t = lua.create_table_with_capacity(4, 0)?;
+    t.set_metatable(Some(lua.load("{ __newindex = error }").eval()?));
+    t.raw_push(1)?;
+    t.raw_push("two")?;
+    assert_eq!(t.raw_len(), 2);
+    assert_eq!(t.raw_pop::<String>()?, "two");
+    // The element is kept if the conversion fails
+    assert!(t.raw_pop::<Table>().is_err());
+    assert_eq!(t.raw_len(), 1);
+    assert_eq!(t.raw_pop::<i64>()?, 1);
+    assert_eq!(t.raw_pop::<Value>()?, Nil);
+    assert_eq!(t.raw_len(), 0);
+
+    t.extend_sequence(1..=300)?;
+    assert_eq!(t.raw_len(), 300);
+    t.extend_sequence(Vec::<i64>::new())?;
+    t.extend_sequence(vec!["last"])?;
+    assert_eq!(t.raw_get::<_, i64>(129)?, 129);
+    assert_eq!(t.raw_get::<_, String>(301)?, "last");
+
+    Ok(())
+}
+
+#[test]
+fn test_table_set_many_clear() -> Result<()> {
+    let lua = Lua::new();
+
+    let t = lua.create_table_with_capacity(0, 300)?;
+    t.set_many((0..300).map(|i| (format!("key{}", i), i)))?;
+    t.set_many(vec![("key0", 10), ("key0", 20)])?;
+    assert_eq!(t.get::<_, i64>("key0")?, 20);
+    assert_eq!(t.get::<_, i64>("key299")?, 299);
+    assert_eq!(t.clone().pairs::<String, i64>().count(), 300);
+
+    // Metamethods are invoked
+    let log = lua.create_table()?;
+    let mt = lua.create_table()?;
+    mt.set("__newindex", log.clone())?;
+    t.set_metatable(Some(mt));
+    t.set_many(vec![("key0", 30), ("new", 1)])?;
+    assert_eq!(t.raw_get::<_, i64>("key0")?, 30);
+    assert_eq!(t.raw_get::<_, Value>("new")?, Nil);
+    assert_eq!(log.get::<_, i64>("new")?, 1);
+
+    t.extend_sequence(1..=10)?;
+    t.clear()?;
+    assert_eq!(t.clone().pairs::<Value, Value>().count(), 0);
+    assert_eq!(t.raw_len(), 0);
+    assert!(t.get_metatable().is_some());
+
+    Ok(())
+}
+
 #[test]
 fn test_table_scope() -> Result<()> {
     let lua = Lua::new();
@@ -309,3 +438,33 @@ fn test_table_call() -> Result<()> {
 
     Ok(())
 }
+
+#[cfg(not(feature = "send"))]
+#[test]
+fn test_owned_table() -> Result<()> {
+    use mlua::OwnedTable;
+
+    let lua = Lua::new();
+
+    let table = lua.create_table()?;
+    table.set("a", 1)?;
+    let owned = table.into_owned()?;
+    let owned2 = owned.try_clone()?;
+    assert_eq!(owned.to_ref(), owned2.to_ref());
+
+    lua.globals().set("t", &owned)?;
+    lua.load("t.b = 2").exec()?;
+    let owned3: OwnedTable = lua.globals().get("t")?;
+    assert_eq!(owned3.to_ref().get::<_, i64>("b")?, 2);
+    drop((owned2, owned3));
+
+    // The table keeps the Lua state alive
+    drop(lua);
+    let table = owned.to_ref();
+    assert_eq!(table.get::<_, i64>("a")?, 1);
+    assert_eq!(table.get::<_, i64>("b")?, 2);
+    table.set("c", 3)?;
+    assert_eq!(table.len()?, 0);
+
+    Ok(())
+}
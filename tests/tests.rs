@@ -11,13 +11,14 @@
 extern "system" {}
 
 use std::iter::FromIterator;
-use std::panic::catch_unwind;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::string::String as StdString;
 use std::sync::Arc;
 use std::{error, f32, f64, fmt};
 
 use mlua::{
-    ChunkMode, Error, ExternalError, Function, Lua, Nil, Result, StdLib, String, Table, UserData,
-    Value, Variadic,
+    ChunkMode, Error, ErrorContext, ExternalError, Function, Lua, Nil, Result, ScriptError, StdLib,
+    String, Table, UserData, Value, Variadic,
 };
 
 #[test]
@@ -430,6 +431,126 @@ fn test_error() -> Result<()> {
     Ok(())
 }
 
+#[test]
+fn test_script_error() -> Result<()> {
+    let lua = Lua::new();
+
+    let f = lua
+        .load(
+            r#"
+            local function fail()
+                error("boom")
+            end
+            fail()
+        "#,
+        )
+        .set_name("script")?
+        .into_function()?;
+    let err = f.try_call::<_, ()>(())?.unwrap_err();
+    assert!(
+        err.message.ends_with("boom"),
+        "unexpected message: {}",
+        err.message
+    );
+    assert_eq!(err.chunk.as_deref(), Some(r#"[string "script"]"#));
+    assert_eq!(err.line, Some(3));
+    assert!(err
+        .traceback
+        .frames
+        .iter()
+        .any(|frame| frame.what == "main"));
+    assert_eq!(err.value, Value::String(lua.create_string(&err.message)?));
+
+    // `call` still returns a `RuntimeError` with the same message
+    match f.call::<_, ()>(()) {
+        Err(Error::RuntimeError(msg)) => assert!(msg.starts_with(&err.message)),
+        r => panic!("expected RuntimeError, got {:?}", r),
+    }
+
+    let f = lua.load("error({code = 42})").into_function()?;
+    match f.try_call::<_, ()>(())? {
+        Err(ScriptError {
+            value: Value::Table(value),
+            ..
+        }) => assert_eq!(value.get::<_, i64>("code")?, 42),
+        r => panic!("expected ScriptError with a table, got {:?}", r),
+    }
+
+    // Errors from Rust callbacks are not script errors
+    let f =
+        lua.create_function(|_, ()| -> Result<()> { Err(Error::RuntimeError("rust".into())) })?;
+    match f.try_call::<_, ()>(()) {
+        Err(Error::CallbackError { .. }) => {}
+        r => panic!("expected CallbackError, got {:?}", r),
+    }
+
+    // Deep stacks are truncated the same way as in `debug.traceback`
+    let f = lua.load("local function f() f() end f()").into_function()?;
+    let err = f.try_call::<_, ()>(())?.unwrap_err();
+    assert!(err.traceback.frames.len() <= 22);
+
+    let f = lua.load("return 1").into_function()?;
+    assert_eq!(f.try_call::<_, i64>(())?.ok(), Some(1));
+
+    Ok(())
+}
+
+#[test]
+fn test_error_context() -> Result<()> {
+    let lua = Lua::new();
+
+    let err = lua
+        .load("error('boom')")
+        .exec()
+        .context("outer")
+        .with_context(|_| "wrapper")
+        .unwrap_err();
+    assert!(err
+        .to_string()
+        .starts_with("wrapper: outer: runtime error: "));
+    let source = error::Error::source(&err).unwrap();
+    assert!(source.to_string().starts_with("outer: "));
+    match source.source().unwrap().downcast_ref::<Error>() {
+        Some(Error::RuntimeError(_)) => {}
+        e => panic!("expected RuntimeError, got {:?}", e),
+    }
+
+    let called = std::cell::Cell::new(false);
+    Ok::<_, Error>(()).with_context(|_| {
+        called.set(true);
+        "unused"
+    })?;
+    assert!(!called.get());
+
+    let func = lua.create_function(|_, ()| -> Result<()> {
+        Err(Error::RuntimeError("inner".into())).context("in callback")
+    })?;
+    lua.globals().set("func", func.clone())?;
+    lua.load(
+        r#"
+        local ok, err = pcall(func)
+        assert(not ok and tostring(err) == "in callback: runtime error: inner")
+    "#,
+    )
+    .exec()?;
+
+    match lua.load("func()").exec() {
+        Err(Error::WithContext { context, cause }) => {
+            assert_eq!(context, "in callback");
+            match cause.as_ref() {
+                Error::CallbackError { cause, .. } => match cause.as_ref() {
+                    Error::RuntimeError(msg) => assert_eq!(msg, "inner"),
+                    e => panic!("expected RuntimeError, got {:?}", e),
+                },
+                e => panic!("expected CallbackError, got {:?}", e),
+            }
+        }
+        r => panic!("expected WithContext, got {:?}", r),
+    }
+
+    Ok(())
+}
+
 #[test]
 fn test_result_conversions() -> Result<()> {
     let lua = Lua::new();
@@ -743,6 +864,276 @@ fn test_mismatched_registry_key() -> Result<()> {
     Ok(())
 }
 
+#[test]
+#[cfg(feature = "lua54")]
+fn test_warnings() -> Result<()> {
+    let lua = Lua::new();
+    let warnings = Arc::new(std::sync::Mutex::new(Vec::new()));
+
+    let warnings2 = warnings.clone();
+    lua.set_warning_function(move |_, msg, to_continue| {
+        let msg = StdString::from_utf8_lossy(msg).into_owned();
+        warnings2.lock().unwrap().push((msg, to_continue));
+    })?;
+
+    lua.load(r#"warn("a", "b")"#).exec()?;
+    lua.warning("c", false)?;
+    assert_eq!(
+        *warnings.lock().unwrap(),
+        vec![
+            ("a".to_string(), true),
+            ("b".to_string(), false),
+            ("c".to_string(), false)
+        ]
+    );
+    assert!(lua.warning("d\0", false).is_err());
+
+    lua.remove_warning_function();
+    lua.load(r#"warn("e")"#).exec()?;
+    assert_eq!(warnings.lock().unwrap().len(), 3);
+
+    // The warning function can use Lua while a coroutine is running
+    lua.set_warning_function(|lua, msg, _| {
+        let msg = lua.create_string(msg).unwrap();
+        lua.globals().set("last_warning", msg).unwrap();
+    })?;
+    lua.load(r#"coroutine.wrap(function() warn("f") end)()"#)
+        .exec()?;
+    assert_eq!(lua.globals().get::<_, String>("last_warning")?, "f");
+
+    // Panics in the warning function are resumed once Lua returns to Rust
+    lua.set_warning_function(|_, _, _| panic!("warning panic"))?;
+    match catch_unwind(AssertUnwindSafe(|| lua.load(r#"warn("g")"#).exec())) {
+        Ok(r) => panic!("no panic was detected, {:?}", r),
+        Err(p) => assert_eq!(*p.downcast::<&str>().unwrap(), "warning panic"),
+    }
+    assert!(catch_unwind(AssertUnwindSafe(|| lua.warning("h", false))).is_err());
+    lua.remove_warning_function();
+    lua.load(r#"warn("i")"#).exec()?;
+
+    Ok(())
+}
+
+#[test]
+#[cfg(feature = "lua54")]
+fn test_warnings_on_close() -> Result<()> {
+    let lua = Lua::new();
+    let warnings = Arc::new(std::sync::Mutex::new(StdString::new()));
+
+    let warnings2 = warnings.clone();
+    lua.set_warning_function(move |_, msg, _| {
+        warnings2
+            .lock()
+            .unwrap()
+            .push_str(&StdString::from_utf8_lossy(msg));
+    })?;
+
+    // Errors in `__gc` metamethods called while closing the state are reported as warnings,
+    // which must not be skipped (or deadlock) because of the internal state being locked
+    lua.load(r#"obj = setmetatable({}, { __gc = function() error("gc error") end })"#)
+        .exec()?;
+    drop(lua);
+    assert!(warnings.lock().unwrap().contains("gc error"));
+
+    Ok(())
+}
+
+#[cfg(not(feature = "send"))]
+#[test]
+fn test_lua_clone() -> Result<()> {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    struct MyUserData(Arc<()>);
+    impl UserData for MyUserData {}
+
+    let rc = Arc::new(());
+    let lua = Lua::new();
+    lua.globals().set("ud", MyUserData(rc.clone()))?;
+    lua.globals().set("x", 1)?;
+
+    let lua2 = lua.clone();
+    assert_eq!(lua2.globals().get::<_, i64>("x")?, 1);
+    lua2.globals().set("x", 2)?;
+    assert_eq!(lua.globals().get::<_, i64>("x")?, 2);
+
+    // Weak references can be created from the `Lua` passed to callbacks
+    let weak = Rc::new(RefCell::new(None));
+    let weak2 = weak.clone();
+    lua.create_function(move |lua, ()| {
+        *weak2.borrow_mut() = Some(lua.downgrade());
+        Ok(())
+    })?
+    .call::<_, ()>(())?;
+    let weak = weak.borrow_mut().take().unwrap();
+
+    // The state is closed when the last handle is dropped
+    drop(lua);
+    assert_eq!(Arc::strong_count(&rc), 2);
+    let lua3 = weak.upgrade().expect("state is alive");
+    assert_eq!(lua3.globals().get::<_, i64>("x")?, 2);
+    drop(lua2);
+    drop(lua3);
+    assert_eq!(Arc::strong_count(&rc), 1);
+    assert!(weak.upgrade().is_none());
+
+    Ok(())
+}
+
+#[test]
+fn test_copy_value() -> Result<()> {
+    #[derive(Clone)]
+    struct Cloneable(i64);
+    impl UserData for Cloneable {}
+
+    struct NotCloneable;
+    impl UserData for NotCloneable {}
+
+    let lua1 = Lua::new();
+    let lua2 = Lua::new();
+    lua1.register_cloneable_userdata::<Cloneable>();
+
+    lua1.globals().set("ud", Cloneable(42))?;
+    lua1.globals().set("label", "lua1")?;
+    lua2.globals().set("label", "lua2")?;
+    let value = lua1
+        .load(
+            r#"
+            local shared = { "shared" }
+            local t = {
+                1, 2.5, true, "str\0bytes", shared, shared,
+                [shared] = "key",
+                nested = { deep = { deeper = {} } },
+                double = function(x) return x * 2 end,
+                label = function() return label end,
+                ud = ud,
+            }
+            t.nested.deep.deeper.root = t
+            return setmetatable(t, { __index = function(_, k) return k .. "!" end })
+        "#,
+        )
+        .eval()?;
+
+    let t: Table = lua2.unpack(lua2.copy_value(value)?)?;
+    assert_eq!(t.get::<_, i64>(1)?, 1);
+    assert_eq!(t.get::<_, f64>(2)?, 2.5);
+    assert!(t.get::<_, bool>(3)?);
+    assert_eq!(t.get::<_, String>(4)?.as_bytes(), b"str\0bytes");
+    let shared: Table = t.get(5)?;
+    assert!(shared.equals(t.get::<_, Table>(6)?)?);
+    assert_eq!(t.get::<_, StdString>(shared)?, "key");
+    let root: Table = lua2
+        .load("return ...")
+        .call::<_, Table>(t.clone())?
+        .get::<_, Table>("nested")?
+        .get::<_, Table>("deep")?
+        .get::<_, Table>("deeper")?
+        .get("root")?;
+    assert!(root.equals(&t)?);
+    assert_eq!(t.get::<_, StdString>("missing")?, "missing!");
+    assert_eq!(t.get::<_, Function>("double")?.call::<_, i64>(21)?, 42);
+    assert_eq!(
+        t.get::<_, Function>("label")?.call::<_, StdString>(())?,
+        "lua2"
+    );
+    assert_eq!(
+        t.get::<_, mlua::AnyUserData>("ud")?
+            .borrow::<Cloneable>()?
+            .0,
+        42
+    );
+
+    // Values that cannot be copied
+    let not_copyable = [
+        Value::Function(lua1.create_function(|_, ()| Ok(()))?),
+        lua1.load("local x = 1; return function() return x end")
+            .eval()?,
+        Value::Function(
+            lua1.load("return x")
+                .set_environment(lua1.create_table()?)?
+                .into_function()?,
+        ),
+        Value::Thread(lua1.create_thread(lua1.load("return").into_function()?)?),
+        Value::UserData(lua1.create_userdata(NotCloneable)?),
+        lua1.load("return { { setmetatable({}, { [print] = 1 }) } }")
+            .eval()?,
+    ];
+    for value in not_copyable.iter() {
+        match lua2.copy_value(value.clone()) {
+            Err(Error::CopyError { .. }) => {}
+            r => panic!("expected CopyError, got {:?}", r),
+        }
+    }
+
+    // Functions from an unsafe state may come from unchecked bytecode
+    let unsafe_lua = unsafe { Lua::unsafe_new() };
+    let f = unsafe_lua.load("return 1").into_function()?;
+    match lua2.copy_value(Value::Function(f.clone())) {
+        Err(Error::SafetyError(_)) => {}
+        r => panic!("expected SafetyError, got {:?}", r),
+    }
+    let lua3 = unsafe { Lua::unsafe_new() };
+    let f: Function = lua3.unpack(lua3.copy_value(Value::Function(f))?)?;
+    assert_eq!(f.call::<_, i64>(())?, 1);
+
+    Ok(())
+}
+
+#[test]
+fn test_app_data() -> Result<()> {
+    let lua = Lua::new();
+
+    assert!(lua.set_app_data("test1").is_none());
+    lua.set_app_data(vec!["test2"]);
+
+    // Check that we can hold multiple borrows at a time
+    {
+        let s = lua.app_data_ref::<&str>().unwrap();
+        let s2 = lua.app_data_ref::<&str>().unwrap();
+        let v = lua.app_data_mut::<Vec<&str>>().unwrap();
+        assert_eq!(*s, "test1");
+        assert_eq!(*s2, "test1");
+        assert_eq!(*v, vec!["test2"]);
+    }
+
+    let f = lua.create_function(|lua, ()| {
+        {
+            let mut s = lua.app_data_mut::<&str>().unwrap();
+            assert_eq!(*s, "test1");
+            *s = "test4";
+        }
+        {
+            let mut v = lua.app_data_mut::<Vec<&str>>().unwrap();
+            v.push("test3");
+        }
+        Ok(())
+    })?;
+    f.call::<_, ()>(())?;
+
+    assert_eq!(*lua.app_data_ref::<&str>().unwrap(), "test4");
+    assert_eq!(
+        *lua.app_data_ref::<Vec<&str>>().unwrap(),
+        vec!["test2", "test3"]
+    );
+    assert!(lua.app_data_ref::<u32>().is_none());
+
+    // Mutable and immutable borrows of the same value conflict
+    {
+        let _v = lua.app_data_ref::<Vec<&str>>().unwrap();
+        assert!(catch_unwind(AssertUnwindSafe(|| lua.app_data_mut::<Vec<&str>>())).is_err());
+        assert!(catch_unwind(AssertUnwindSafe(|| lua.remove_app_data::<u32>())).is_err());
+    }
+
+    assert_eq!(
+        lua.remove_app_data::<Vec<&str>>(),
+        Some(vec!["test2", "test3"])
+    );
+    assert!(lua.app_data_ref::<Vec<&str>>().is_none());
+    assert_eq!(lua.set_app_data("test5"), Some("test4"));
+
+    Ok(())
+}
+
 #[test]
 fn too_many_returns() -> Result<()> {
     let lua = Lua::new();
@@ -949,3 +1340,160 @@ fn test_jit_version() -> Result<()> {
         .contains("LuaJIT"));
     Ok(())
 }
+
+#[test]
+fn test_inspect_stack() -> Result<()> {
+    let lua = Lua::new();
+
+    // Not inside any function
+    assert!(lua.inspect_stack(0, |_| ()).is_none());
+
+    let logline = lua.create_function(|lua, msg: StdString| {
+        let (source, line) = lua
+            .inspect_stack(1, |debug| {
+                let source = debug.source().short_src.map(|s| s.to_vec());
+                (source, debug.curr_line())
+            })
+            .unwrap(); // caller
+        let source = StdString::from_utf8(source.unwrap_or_default()).unwrap();
+        Ok(format!("{}:{} {}", source, line, msg))
+    })?;
+    lua.globals().set("logline", logline)?;
+    let caller_local = lua.create_function(|lua, n: i32| {
+        let local = lua.inspect_stack(1, |debug| {
+            let (name, value) = debug.local(n)?;
+            Some((name, lua.unpack::<i64>(value)))
+        });
+        let (name, value) = local.flatten().unwrap();
+        Ok((name, value?))
+    })?;
+    lua.globals().set("caller_local", caller_local)?;
+
+    lua.load(
+        r#"
+        local x = 1
+        local r = logline("hello")
+        assert(r == '[string "chunk"]:3 hello', r)
+
+        local function outer()
+            return (logline("world"))
+        end
+        assert(outer() == '[string "chunk"]:7 world')
+
+        local name, value = caller_local(1)
+        assert(name == "x" and value == 1)
+    "#,
+    )
+    .set_name("chunk")?
+    .exec()?;
+
+    let depth = lua.create_function(|lua, ()| {
+        let mut level = 0;
+        while lua.inspect_stack(level, |_| ()).is_some() {
+            level += 1;
+        }
+        Ok(level)
+    })?;
+    lua.globals().set("depth", depth)?;
+    assert_eq!(lua.load("return (depth())").eval::<usize>()?, 2);
+    assert_eq!(
+        lua.load("local function f() return (depth()) end; return (f())")
+            .eval::<usize>()?,
+        3
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_traceback() -> Result<()> {
+    let lua = Lua::new();
+
+    let snapshot = lua.create_function(|lua, ()| {
+        let traceback = lua.traceback(Some("snapshot"), 1);
+        Ok(traceback.to_string())
+    })?;
+    lua.globals().set("snapshot", snapshot)?;
+    let frames = lua.create_function(|lua, ()| {
+        let traceback = lua.traceback(None, 0);
+        let frames = traceback.frames.into_iter().map(|f| {
+            let frame = lua.create_table()?;
+            frame.set(1, f.what)?;
+            frame.set(2, f.name)?;
+            frame.set(3, f.line)?;
+            Ok(frame)
+        });
+        frames.collect::<Result<Vec<_>>>()
+    })?;
+    lua.globals().set("frames", frames)?;
+
+    let traceback: StdString = lua
+        .load(
+            r#"
+            local function inner()
+                return (snapshot())
+            end
+            return (inner())
+        "#,
+        )
+        .set_name("chunk")?
+        .eval()?;
+    assert_eq!(
+        traceback,
+        "snapshot\nstack traceback:\n\
+         \t[string \"chunk\"]:3: in function 'inner'\n\
+         \t[string \"chunk\"]:5: in main chunk"
+    );
+
+    lua.load(
+        r#"
+        local function inner()
+            local f = frames()
+            assert(f[1][1] == "C" and f[1][3] == nil)
+            assert(f[2][1] == "Lua" and f[2][2] == "inner" and f[2][3] == 3)
+            assert(f[3][1] == "main" and f[3][3] == 8)
+        end
+        inner()
+    "#,
+    )
+    .exec()?;
+
+    Ok(())
+}
+
+#[test]
+fn test_traceback_deep() -> Result<()> {
+    let lua = unsafe { Lua::unsafe_new() };
+
+    let snapshot =
+        lua.create_function(|lua, ()| Ok(lua.traceback(Some("snapshot"), 1).to_string()))?;
+    lua.globals().set("snapshot", snapshot)?;
+
+    // Middle frames of deep stacks are omitted at the same place as in `debug.traceback`.
+    // Function names are formatted differently across Lua versions, so only the locations are compared.
+    let (traceback, expected): (StdString, StdString) = lua
+        .load(
+            r#"
+            local function deep(n)
+                if n == 0 then
+                    return snapshot(), debug.traceback("snapshot", 1)
+                end
+                local traceback, expected = deep(n - 1)
+                return traceback, expected
+            end
+            local traceback, expected = deep(40)
+            return traceback, expected
+        "#,
+        )
+        .set_name("deep")?
+        .eval()?;
+    let locations = |s: &str| {
+        s.lines()
+            .map(|line| line.split(" in ").next().unwrap().to_owned())
+            .collect::<Vec<_>>()
+    };
+    assert!(traceback.contains("\n\t..."));
+    assert_eq!(locations(&traceback), locations(&expected));
+
+    Ok(())
+}
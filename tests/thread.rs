@@ -145,3 +145,37 @@ fn coroutine_panic() {
         Err(p) => assert!(*p.downcast::<&str>().unwrap() == "test_panic"),
     }
 }
+
+#[test]
+fn test_thread_traceback() -> Result<()> {
+    let lua = Lua::new();
+
+    let thread: Thread = lua
+        .load(
+            r#"
+            coroutine.create(function()
+                local function wait()
+                    coroutine.yield()
+                end
+                wait()
+            end)
+        "#,
+        )
+        .set_name("chunk")?
+        .eval()?;
+    assert!(thread.traceback().frames.is_empty());
+
+    thread.resume::<_, ()>(())?;
+    let traceback = thread.traceback();
+    let lines = traceback.frames.iter().filter_map(|f| f.line);
+    assert_eq!(lines.collect::<Vec<_>>(), vec![4, 6]);
+    assert_eq!(traceback.frames.last().unwrap().name, None);
+    assert!(traceback
+        .to_string()
+        .contains("[string \"chunk\"]:4: in function 'wait'"));
+
+    thread.resume::<_, ()>(())?;
+    assert!(thread.traceback().frames.is_empty());
+
+    Ok(())
+}
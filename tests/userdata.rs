@@ -17,7 +17,7 @@ use std::sync::atomic::{AtomicI64, Ordering};
 
 use mlua::{
     AnyUserData, ExternalError, Function, Lua, MetaMethod, Result, String, UserData,
-    UserDataMethods, Value,
+    UserDataFields, UserDataMethods, Value,
 };
 
 #[test]
@@ -347,3 +347,133 @@ fn test_functions() -> Result<()> {
 
     Ok(())
 }
+
+#[test]
+fn test_fields() -> Result<()> {
+    #[derive(Copy, Clone)]
+    struct MyUserData(i64);
+
+    impl UserData for MyUserData {
+        fn add_fields<'lua, F: UserDataFields<'lua, Self>>(fields: &mut F) {
+            fields.add_field_method_get("val", |_, data| Ok(data.0));
+            fields.add_field_method_set("val", |_, data, val| {
+                data.0 = val;
+                Ok(())
+            });
+            fields.add_field_function_get("doubled", |_, ud| Ok(ud.borrow::<MyUserData>()?.0 * 2));
+            fields.add_field_function_set("doubled", |_, ud, val: i64| {
+                ud.borrow_mut::<MyUserData>()?.0 = val / 2;
+                Ok(())
+            });
+        }
+
+        fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+            methods.add_method("dummy", |_, _, ()| Ok("dummy"));
+
+            methods.add_meta_method(MetaMethod::Index, |_, data, name: std::string::String| {
+                match name.as_str() {
+                    "uval" => Ok(Some(data.0 as u64)),
+                    _ => Ok(None),
+                }
+            });
+
+            methods.add_meta_method_mut(
+                MetaMethod::NewIndex,
+                |_, data, (name, val): (std::string::String, i64)| match name.as_str() {
+                    "uval" => {
+                        data.0 = val;
+                        Ok(())
+                    }
+                    _ => Err(format!("field '{}' does not exist", name).to_lua_err()),
+                },
+            );
+        }
+    }
+
+    let lua = Lua::new();
+    let globals = lua.globals();
+    let ud = lua.create_userdata(MyUserData(7))?;
+    globals.set("ud", ud.clone())?;
+    lua.load(
+        r#"
+        assert(ud.val == 7)
+        ud.val = 10
+        assert(ud.val == 10)
+        assert(ud.doubled == 20)
+        ud.doubled = 30
+        assert(ud.val == 15)
+        assert(ud:dummy() == "dummy")
+        assert(ud.uval == 15)
+        ud.uval = 5
+        assert(ud.val == 5)
+        assert(ud.unknown == nil)
+
+        local ok, err = pcall(function() ud.unknown = 1 end)
+        assert(not ok and tostring(err):find("field 'unknown' does not exist"))
+        local ok, err = pcall(function() ud.val = "abc" end)
+        assert(not ok)
+    "#,
+    )
+    .exec()?;
+    assert_eq!(ud.borrow::<MyUserData>()?.0, 5);
+
+    // Fields without `__newindex` fallback
+    struct MyUserData2(i64);
+
+    impl UserData for MyUserData2 {
+        fn add_fields<'lua, F: UserDataFields<'lua, Self>>(fields: &mut F) {
+            fields.add_field_method_get("val", |_, data| Ok(data.0));
+            fields.add_field_method_set("val", |_, data, val| {
+                data.0 = val;
+                Ok(())
+            });
+        }
+    }
+
+    globals.set("ud2", MyUserData2(1))?;
+    lua.load(
+        r#"
+        ud2.val = 2
+        assert(ud2.val == 2)
+        assert(ud2.other == nil)
+        local ok, err = pcall(function() ud2.other = 1 end)
+        assert(not ok and tostring(err):find("attempt to set an unknown field 'other'"))
+    "#,
+    )
+    .exec()?;
+
+    Ok(())
+}
+
+#[cfg(not(feature = "send"))]
+#[test]
+fn test_owned_userdata() -> Result<()> {
+    use mlua::OwnedAnyUserData;
+
+    struct MyUserData(Arc<()>);
+
+    impl UserData for MyUserData {
+        fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+            methods.add_method("refs", |_, this, ()| Ok(Arc::strong_count(&this.0)));
+        }
+    }
+
+    let lua = Lua::new();
+    let rc = Arc::new(());
+
+    let ud = lua.create_userdata(MyUserData(rc.clone()))?.into_owned()?;
+    lua.globals().set("ud", &ud)?;
+    assert_eq!(lua.load("ud:refs()").eval::<usize>()?, 2);
+    let ud2: OwnedAnyUserData = lua.globals().get("ud")?;
+    assert_eq!(ud.to_ref(), ud2.to_ref());
+    drop(ud2);
+
+    // The userdata is destroyed together with the Lua state, when the last owner is dropped
+    drop(lua);
+    assert_eq!(Arc::strong_count(&rc), 2);
+    assert!(ud.to_ref().borrow::<MyUserData>().is_ok());
+    drop(ud);
+    assert_eq!(Arc::strong_count(&rc), 1);
+
+    Ok(())
+}